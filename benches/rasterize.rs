@@ -0,0 +1,49 @@
+use brother_ql_rs::image::{DynamicImage, GrayImage, Luma};
+use brother_ql_rs::printer::constants::label_data;
+use brother_ql_rs::utils::{self, raster, Dither, ImageOptions};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+/// A gray gradient with some texture, so that every pixel isn't the same
+fn gradient(width: u32, height: u32) -> GrayImage {
+    GrayImage::from_fn(width, height, |x, y| {
+        Luma([((x * 255 / width.max(1)) ^ (y % 16)) as u8])
+    })
+}
+
+fn pack(c: &mut Criterion) {
+    // About 85cm of continuous 62mm tape
+    let image = gradient(696, 10_000);
+    let mut group = c.benchmark_group("pack");
+    group.throughput(Throughput::Elements(u64::from(image.height())));
+    group.bench_function("long label", |b| {
+        let mut lines = Vec::new();
+        b.iter(|| {
+            raster::rasterize_into(black_box(&image), &mut lines);
+            black_box(&lines);
+        })
+    });
+    group.finish();
+}
+
+fn rasterize(c: &mut Criterion) {
+    let label = label_data(62, None).unwrap();
+    let photo = DynamicImage::ImageLuma8(gradient(1200, 1800));
+    let mut group = c.benchmark_group("rasterize image");
+    for dither in [Dither::Threshold, Dither::FloydSteinberg, Dither::Bayer8] {
+        let options = ImageOptions {
+            dither,
+            ..ImageOptions::default()
+        };
+        group.bench_function(format!("{dither:?}"), |b| {
+            b.iter_batched(
+                || photo.clone(),
+                |photo| utils::rasterize_image_with_options(photo, &label, &options).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, pack, rasterize);
+criterion_main!(benches);
@@ -1,21 +1,153 @@
-use barcoders::{
-    generators::{self},
-    sym::ean13::EAN13,
-};
-use image::{ImageBuffer, Rgba};
-use qrcodegen::QrCode;
+use barcoders::sym::{code128::Code128, code39::Code39, ean13::EAN13, ean8::EAN8};
+use image::{DynamicImage, ImageBuffer, Rgba};
+use qrcodegen::{QrCode, QrCodeEcc};
+#[cfg(feature = "template")]
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::template::{
+    BarcodeElement, Color, Element, ErrorCorrection, LabelMedia, QrElement, Template,
+    TemplateError, Units,
+};
+
 #[derive(Error, Debug)]
 pub enum BarcodeError {
     #[error("overflow error: {0}")]
     Overflow(String),
+    #[error("invalid barcode data: {0}")]
+    InvalidData(String),
+}
+
+/// Linear barcode symbologies supported by `encode`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "template", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "template", serde(rename_all = "snake_case"))]
+pub enum Symbology {
+    Ean13,
+    Ean8,
+    Code39,
+    /// Code 128, using character set B unless `data` starts with a character set selector (`À`, `Ɓ`, or `Ć`)
+    Code128,
+}
+
+/// Encodes data as a linear barcode, returning one entry per module that is 1 for a bar and 0 for a space
+pub fn encode(symbology: Symbology, data: &str) -> Result<Vec<u8>, BarcodeError> {
+    let invalid = |e: barcoders::error::Error| BarcodeError::InvalidData(e.to_string());
+    match symbology {
+        Symbology::Ean13 => Ok(EAN13::new(data).map_err(invalid)?.encode()),
+        Symbology::Ean8 => Ok(EAN8::new(data).map_err(invalid)?.encode()),
+        Symbology::Code39 => Ok(Code39::new(data).map_err(invalid)?.encode()),
+        Symbology::Code128 => {
+            let data = if data.starts_with(['À', 'Ɓ', 'Ć']) {
+                data.to_string()
+            } else {
+                format!("Ɓ{data}")
+            };
+            Ok(Code128::new(data).map_err(invalid)?.encode())
+        }
+    }
+}
+
+/// Encodes text as a QR code
+pub fn encode_qr(data: &str, ecc: QrCodeEcc) -> Result<QrCode, BarcodeError> {
+    QrCode::encode_text(data, ecc).map_err(|_| BarcodeError::Overflow("qr".into()))
 }
 
 pub enum EAN13Data {
     EncodedPrice { sku: usize, price: f32 },
     Simple(String),
 }
+impl EAN13Data {
+    /// The 12 or 13 digits to encode
+    fn digits(self) -> Result<String, BarcodeError> {
+        match self {
+            EAN13Data::EncodedPrice { sku, price } => encode_price(sku, price),
+            EAN13Data::Simple(s) => Ok(s),
+        }
+    }
+}
+
+/// Encodes a SKU and price as the 12 digits of an in-store EAN-13 barcode
+fn encode_price(sku: usize, price: f32) -> Result<String, BarcodeError> {
+    if sku > 99999 {
+        return Err(BarcodeError::Overflow("sku".into()));
+    }
+    let cents = (price * 100.0).floor() as usize;
+    if cents > 99999 {
+        return Err(BarcodeError::Overflow("price".into()));
+    }
+    Ok(format!("20{:05}{:05}", sku, cents))
+}
+
+/// Layout used by `generate_ean13_barcode`: a barcode at the end of a label on 62mm continuous tape
+pub fn ean13_template(data: EAN13Data) -> Result<Template, BarcodeError> {
+    let mut template = Template::new(
+        LabelMedia {
+            width: 62,
+            length: None,
+        },
+        Units::Dots,
+    );
+    template.length = Some(150.0);
+    template.elements.push(Element::Barcode(BarcodeElement {
+        x: 496.0,
+        y: 0.0,
+        width: 200.0,
+        height: 100.0,
+        symbology: Symbology::Ean13,
+        data: data.digits()?,
+        module_width: None,
+        color: Color::Black,
+    }));
+    Ok(template)
+}
+
+/// Layout used by `generate_barcode_large`: a large price barcode on 62mm continuous tape, with a QR code of `link`
+/// next to it
+pub fn barcode_large_template(
+    sku: usize,
+    price: f32,
+    link: Option<String>,
+) -> Result<Template, BarcodeError> {
+    let mut template = Template::new(
+        LabelMedia {
+            width: 62,
+            length: None,
+        },
+        Units::Dots,
+    );
+    template.length = Some(270.0);
+    template.elements.push(Element::Barcode(BarcodeElement {
+        x: 346.0,
+        y: 0.0,
+        width: 350.0,
+        height: 230.0,
+        symbology: Symbology::Ean13,
+        data: encode_price(sku, price)?,
+        module_width: None,
+        color: Color::Black,
+    }));
+    if let Some(link) = link {
+        template.elements.push(Element::Qr(QrElement {
+            x: 89.0,
+            y: 60.0,
+            size: 152.0,
+            data: link,
+            error_correction: ErrorCorrection::High,
+            color: Color::Black,
+        }));
+    }
+    Ok(template)
+}
+
+fn render(template: &Template) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, BarcodeError> {
+    match template.render() {
+        Ok(image) => Ok(DynamicImage::ImageLuma8(image).to_rgba8()),
+        Err(TemplateError::Barcode(e)) => Err(e),
+        // Barcode templates only contain barcodes and QR codes
+        Err(e) => Err(BarcodeError::InvalidData(e.to_string())),
+    }
+}
 
 pub fn generate_ean13_barcode(
     data: EAN13Data,
@@ -23,78 +155,7 @@ pub fn generate_ean13_barcode(
     _description: String,
     _link: Option<String>,
 ) -> Result<image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, BarcodeError> {
-    let mut label = ImageBuffer::new(696, 150);
-    label.iter_mut().for_each(|c| *c = u8::MAX);
-
-    // Barcode
-    {
-        let data = match data {
-            EAN13Data::EncodedPrice { sku, price } => {
-                if sku > 99999 {
-                    return Err(BarcodeError::Overflow("sku".into()));
-                }
-                let cents = (price * 100.0).floor() as usize;
-                if cents > 99999 {
-                    return Err(BarcodeError::Overflow("price".into()));
-                }
-                format!("20{:05}{:05}", sku, cents)
-            }
-            EAN13Data::Simple(s) => s,
-        };
-
-        let barcode = generators::image::Image::image_buffer(1)
-            .generate_buffer(EAN13::new(data).unwrap().encode())
-            .unwrap();
-
-        let barcode =
-            image::imageops::resize(&barcode, 200, 100, image::imageops::FilterType::Nearest);
-
-        image::imageops::overlay(&mut label, &barcode, 496, 0);
-    }
-
-    /* // QR Code
-    if let Some(link) = link {
-        let qr = QrCode::encode_text(&link, qrcodegen::QrCodeEcc::High)
-            .map_err(|_| BarcodeError::Overflow("link".into()))?;
-        let size = qr.size().abs() as u32;
-        if size < 21 || size > 177 {
-            return Err(BarcodeError::Overflow("qr".into()));
-        }
-
-        let mut qr_img = ImageBuffer::new(size, size);
-
-        for x in 0..size {
-            for y in 0..size {
-                qr_img.put_pixel(
-                    x,
-                    y,
-                    match qr.get_module(x as i32, y as i32) {
-                        true => {
-                            println!("{x}, {y} = true");
-                            Rgba([u8::MIN, u8::MIN, u8::MIN, u8::MAX])
-                        }
-                        false => {
-                            println!("{x}, {y} = false");
-                            Rgba([u8::MAX, u8::MAX, u8::MAX, u8::MAX])
-                        }
-                    },
-                );
-            }
-        }
-
-        let margin = 20;
-        let final_size = 210;
-        let qr_img = image::imageops::resize(
-            &qr_img,
-            final_size - (2 * margin),
-            final_size - (2 * margin),
-            image::imageops::FilterType::Nearest,
-        );
-
-        image::imageops::overlay(&mut label, &qr_img, margin as i64, margin as i64);
-    } */
-
-    Ok(label)
+    render(&ean13_template(data)?)
 }
 
 pub fn generate_barcode_large(
@@ -104,75 +165,68 @@ pub fn generate_barcode_large(
     _description: String,
     link: Option<String>,
 ) -> Result<image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, BarcodeError> {
-    let mut label = ImageBuffer::new(696, 270);
-    label.iter_mut().for_each(|c| *c = u8::MAX);
-
-    // Barcode
-    {
-        if sku > 99999 {
-            return Err(BarcodeError::Overflow("sku".into()));
-        }
-        let cents = (price * 100.0).floor() as usize;
-        if cents > 99999 {
-            return Err(BarcodeError::Overflow("price".into()));
-        }
-        let data = format!("20{:05}{:05}", sku, cents);
-
-        let barcode = generators::image::Image::image_buffer(1)
-            .generate_buffer(EAN13::new(data).unwrap().encode())
-            .unwrap();
-
-        let barcode =
-            image::imageops::resize(&barcode, 350, 230, image::imageops::FilterType::Nearest);
+    render(&barcode_large_template(sku, price, link)?)
+}
 
-        image::imageops::overlay(&mut label, &barcode, 346, 0);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Smallest rectangle, as `(left, top, right, bottom)`, containing every dark pixel
+    fn ink_bounds(label: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> (u32, u32, u32, u32) {
+        let dark = || label.enumerate_pixels().filter(|(_, _, p)| p[0] < 128);
+        (
+            dark().map(|(x, _, _)| x).min().unwrap(),
+            dark().map(|(_, y, _)| y).min().unwrap(),
+            dark().map(|(x, _, _)| x).max().unwrap() + 1,
+            dark().map(|(_, y, _)| y).max().unwrap() + 1,
+        )
     }
 
-    // QR Code
-    if let Some(link) = link {
-        let qr = QrCode::encode_text(&link, qrcodegen::QrCodeEcc::High)
-            .map_err(|_| BarcodeError::Overflow("link".into()))?;
-        let size = qr.size().unsigned_abs();
-        if !(21..=177).contains(&size) {
-            return Err(BarcodeError::Overflow("qr".into()));
-        }
-
-        let mut qr_img = ImageBuffer::new(size, size);
-
-        for x in 0..size {
-            for y in 0..size {
-                qr_img.put_pixel(
-                    x,
-                    y,
-                    match qr.get_module(x as i32, y as i32) {
-                        true => {
-                            println!("{x}, {y} = true");
-                            Rgba([u8::MIN, u8::MIN, u8::MIN, u8::MAX])
-                        }
-                        false => {
-                            println!("{x}, {y} = false");
-                            Rgba([u8::MAX, u8::MAX, u8::MAX, u8::MAX])
-                        }
-                    },
-                );
-            }
-        }
+    #[test]
+    fn keeps_original_layouts() {
+        let price = EAN13Data::EncodedPrice {
+            sku: 1234,
+            price: 5.99,
+        };
+        let label = generate_ean13_barcode(price, String::new(), String::new(), None).unwrap();
+        assert_eq!(label.dimensions(), (696, 150));
+        // 95 modules of 2 dots, centered in the 200x100 dot box at the end of the label
+        assert_eq!(ink_bounds(&label), (501, 0, 691, 100));
+
+        let link = Some("https://example.com".to_string());
+        let label = generate_barcode_large(42, 19.5, String::new(), String::new(), link).unwrap();
+        assert_eq!(label.dimensions(), (696, 270));
+        // The QR code in its 152 dot square at (89, 60), and 95 modules of 3 dots centered in the 350x230 dot box
+        // at (346, 0)
+        let (left, top, right, bottom) = ink_bounds(&label);
+        assert!((89..89 + 152 / 25).contains(&left), "{}", left);
+        assert_eq!((top, right, bottom), (0, 664, 230));
+    }
 
-        let margin = 20;
-        let qr_img = image::imageops::resize(
-            &qr_img,
-            192 - (2 * margin),
-            192 - (2 * margin),
-            image::imageops::FilterType::Nearest,
-        );
-
-        image::imageops::overlay(
-            &mut label,
-            &qr_img,
-            (69 + margin) as i64,
-            (40 + margin) as i64,
-        );
+    #[test]
+    fn rejects_overflowing_prices() {
+        assert!(matches!(
+            encode_price(100_000, 1.0),
+            Err(BarcodeError::Overflow(_))
+        ));
+        assert!(matches!(
+            encode_price(1, 1000.0),
+            Err(BarcodeError::Overflow(_))
+        ));
+        assert_eq!(encode_price(7, 0.5).unwrap(), "200000700050");
     }
 
-    Ok(label)
+    #[test]
+    fn encodes_symbologies() {
+        // Start guard, 12 digits of 7 modules, center guard and end guard
+        assert_eq!(encode(Symbology::Ean13, "590123412345").unwrap().len(), 95);
+        assert_eq!(encode(Symbology::Ean8, "9638507").unwrap().len(), 67);
+        assert!(encode(Symbology::Code128, "brother-ql").is_ok());
+        assert!(encode(Symbology::Code39, "QL-800").is_ok());
+        assert!(matches!(
+            encode(Symbology::Ean13, "not digits"),
+            Err(BarcodeError::InvalidData(_))
+        ));
+    }
 }
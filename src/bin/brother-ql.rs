@@ -0,0 +1,903 @@
+//! Command-line interface for printing to, querying, and discovering Brother QL printers
+
+use std::error::Error;
+use std::fs;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+#[cfg(feature = "template")]
+use brother_ql_rs::template::{
+    merge::{self, Merge},
+    LabelMedia, Renderer, Template,
+};
+#[cfg(feature = "text")]
+use brother_ql_rs::text::{Alignment, FontRegistry, LayoutOptions, TextRasterizer, Weight};
+use brother_ql_rs::{
+    barcode,
+    image::DynamicImage,
+    ppd,
+    printer::{
+        self, constants, job,
+        options::{CutMode, Priority},
+        status, Orientation, PrintOptions, ThermalPrinter,
+    },
+    units::Length,
+    utils::{
+        self, preprocess::Sharpen, Align, Dither, Fit, ImageOptions, Mirror, Overflow, Placement,
+        Preprocess,
+    },
+};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+#[derive(Parser)]
+#[command(version, about = "Print to Brother QL-series thermal label printers")]
+struct Cli {
+    /// Serial number of the printer to use (defaults to the first printer found)
+    #[arg(long, global = true)]
+    serial: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List attached printers
+    List,
+    /// Show the status of a printer and its loaded media
+    Status,
+    /// Print an image
+    Print {
+        /// Image files to print, one label each, as a single job. SVG files are rendered at the printer's
+        /// resolution with the svg feature, and PDF files print one label per page with the pdf feature.
+        #[arg(required = true)]
+        images: Vec<PathBuf>,
+        /// Send each line to the printer as soon as it is rendered, for banners too long to hold in memory. Only
+        /// works for a single raster image that fills the label, e.g. with --fit width on continuous tape. PNG
+        /// files are also decoded one row at a time, other formats are decoded whole first.
+        #[arg(long, conflicts_with = "copies")]
+        stream: bool,
+        #[command(flatten)]
+        options: PrintArgs,
+    },
+    /// Print an EAN-13 barcode
+    Barcode {
+        /// 12 or 13 digits to encode
+        data: String,
+        #[command(flatten)]
+        options: PrintArgs,
+    },
+    /// Print text, scaled to fit the label
+    #[cfg(feature = "text")]
+    Text {
+        /// Text to print
+        text: String,
+        /// Smaller text to print below the main text
+        #[arg(long, conflicts_with = "wrap")]
+        secondary: Option<String>,
+        /// Wrap the text into lines across the label
+        #[arg(long)]
+        wrap: bool,
+        /// Alignment of wrapped lines
+        #[arg(long, value_enum, default_value_t = AlignmentArg::Left, requires = "wrap")]
+        align: AlignmentArg,
+        /// Cut off wrapped text after this many lines
+        #[arg(long, requires = "wrap")]
+        max_lines: Option<usize>,
+        /// TrueType or OpenType font file to use (defaults to the embedded font, if enabled)
+        #[arg(long)]
+        font: Option<PathBuf>,
+        /// Bold font file to use with --bold
+        #[arg(long)]
+        bold_font: Option<PathBuf>,
+        /// Font files to try, in order, for characters missing from the font
+        #[arg(long)]
+        fallback_font: Vec<PathBuf>,
+        /// Print bold text
+        #[arg(long)]
+        bold: bool,
+        /// Scale factor for the maximum font size
+        #[arg(long, default_value_t = 1.0)]
+        scale: f32,
+        /// Print white text on a black background
+        #[arg(long)]
+        invert: bool,
+        /// Number of copies to print
+        #[arg(long, default_value_t = 1)]
+        copies: usize,
+        #[command(flatten)]
+        job: JobArgs,
+    },
+    /// Print a label template
+    #[cfg(feature = "template")]
+    Template {
+        /// Template file, as TOML if it has a .toml extension and as JSON otherwise
+        template: PathBuf,
+        /// Write the rendered label to a PNG file instead of printing it
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Number of copies to print
+        #[arg(long, default_value_t = 1)]
+        copies: usize,
+        #[command(flatten)]
+        job: JobArgs,
+    },
+    /// Print one label per row of a CSV file, filling a template's {{placeholders}} from the columns
+    #[cfg(feature = "template")]
+    Merge {
+        /// Template file, as TOML if it has a .toml extension and as JSON otherwise
+        template: PathBuf,
+        /// CSV file with a header row naming the placeholders
+        records: PathBuf,
+        /// Write the rendered labels to numbered PNG files in this directory instead of printing them
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Print the remaining labels even if some rows fail to render
+        #[arg(long)]
+        skip_invalid: bool,
+        #[command(flatten)]
+        job: JobArgs,
+    },
+    /// Write the command stream for an image to a file instead of printing it
+    Dump {
+        /// Image, SVG, or PDF file to convert
+        image: PathBuf,
+        /// Loaded label, as its width in mm for continuous tape (e.g. "62") or width x length in mm for
+        /// die-cut labels (e.g. "62x29")
+        #[arg(long)]
+        label: String,
+        /// File to write the command stream to
+        #[arg(short, long, default_value = "label.bin")]
+        output: PathBuf,
+        #[command(flatten)]
+        options: PrintArgs,
+    },
+    /// Write a CUPS PPD file for use with the rastertobrotherql filter
+    Ppd {
+        /// Printer model, e.g. "QL-800"
+        #[arg(long, default_value = "QL-800")]
+        model: String,
+        /// File to write the PPD to
+        #[arg(short, long, default_value = "brother-ql.ppd")]
+        output: PathBuf,
+    },
+    /// Serve an HTTP API for printing to all attached printers
+    #[cfg(feature = "server")]
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        addr: String,
+        /// Directory to spool print jobs to
+        #[arg(long, default_value = "spool")]
+        spool_dir: PathBuf,
+    },
+    /// Share a printer over IPP, so that it can be added as a network printer
+    #[cfg(feature = "ipp")]
+    Ipp {
+        /// Address to listen on
+        #[arg(long, default_value = "0.0.0.0:8631")]
+        addr: String,
+        /// Directory to spool print jobs to
+        #[arg(long, default_value = "spool")]
+        spool_dir: PathBuf,
+    },
+}
+
+#[derive(Args)]
+struct PrintArgs {
+    /// Orientation of the image on the label
+    #[arg(long, value_enum, default_value_t = OrientationArg::Normal)]
+    orientation: OrientationArg,
+    /// Flip the image, e.g. for iron-on transfers
+    #[arg(long, value_enum, default_value_t = MirrorArg::None)]
+    mirror: MirrorArg,
+    /// Dither the image instead of thresholding it (for photos), with Floyd-Steinberg unless another method is
+    /// given as e.g. --dither=atkinson
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = DitherArg::Threshold,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "floyd-steinberg"
+    )]
+    dither: DitherArg,
+    /// How to scale the image to the label
+    #[arg(long, value_enum, default_value_t = FitArg::Width)]
+    fit: FitArg,
+    /// Alignment of the image across the tape
+    #[arg(long, value_enum, default_value_t = AlignArg::Center)]
+    align: AlignArg,
+    /// Alignment of the image along the tape
+    #[arg(long, value_enum, default_value_t = AlignArg::Center)]
+    vertical_align: AlignArg,
+    /// Length of labels on continuous tape, e.g. "50mm", "2in", or "600" dots (defaults to the length of the image)
+    #[arg(long)]
+    length: Option<Length>,
+    /// What to do with images that don't fit on the label with --fit width or none
+    #[arg(long, value_enum, default_value_t = OverflowArg::Shrink)]
+    overflow: OverflowArg,
+    /// Number of copies to print
+    #[arg(long, default_value_t = 1)]
+    copies: usize,
+    /// Print PDF pages with --orientation instead of turning them to match the label
+    #[cfg(feature = "pdf")]
+    #[arg(long)]
+    no_auto_rotate: bool,
+    /// Keep the blank margins around the content of PDF pages
+    #[cfg(feature = "pdf")]
+    #[arg(long)]
+    no_crop: bool,
+    #[command(flatten)]
+    adjust: AdjustArgs,
+    #[command(flatten)]
+    job: JobArgs,
+}
+
+#[derive(Args)]
+struct AdjustArgs {
+    /// Gray level from 0 to 255 from which pixels print white; lower values give a lighter image
+    #[arg(long, default_value_t = 128)]
+    threshold: u8,
+    /// Stretch the gray levels so that the darkest and lightest parts of the image become black and white
+    #[arg(long)]
+    auto_levels: bool,
+    /// Brightness adjustment from -1.0 to 1.0
+    #[arg(long, default_value_t = 0.0, allow_hyphen_values = true)]
+    brightness: f32,
+    /// Contrast multiplier, e.g. 1.5 for more contrast
+    #[arg(long, default_value_t = 1.0)]
+    contrast: f32,
+    /// Gamma correction; values above 1.0 lighten the midtones
+    #[arg(long, default_value_t = 1.0)]
+    gamma: f32,
+    /// Sharpen the image with an unsharp mask of the given radius in pixels
+    #[arg(long, value_name = "SIGMA")]
+    sharpen: Option<f32>,
+    /// Print light parts of the image black and dark parts white
+    #[arg(long)]
+    invert: bool,
+}
+impl From<&AdjustArgs> for Preprocess {
+    fn from(args: &AdjustArgs) -> Self {
+        Self {
+            auto_levels: args.auto_levels,
+            brightness: args.brightness,
+            contrast: args.contrast,
+            gamma: args.gamma,
+            sharpen: args.sharpen.map(|sigma| Sharpen {
+                sigma,
+                threshold: 0,
+            }),
+            invert: args.invert,
+            threshold: args.threshold,
+            ..Self::default()
+        }
+    }
+}
+
+#[derive(Args)]
+struct JobArgs {
+    /// Cut after every N labels
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..))]
+    cut_each: u8,
+    /// Don't cut the labels
+    #[arg(long, conflicts_with = "cut_each")]
+    no_cut: bool,
+    /// Don't feed and cut after the last label, so the next job continues without a blank margin
+    #[arg(long)]
+    chain: bool,
+    /// Print faster at lower quality
+    #[arg(long)]
+    fast: bool,
+    /// Blank tape to feed before and after each label on continuous tape, from 35 to 1000 dots (3mm to 85mm)
+    #[arg(long)]
+    feed_margin: Option<Length>,
+    /// Remove blank space from the start and end of each label
+    #[arg(long)]
+    trim_blank: bool,
+    /// Blank space to add to the start of each label
+    #[arg(long, default_value_t = Length::dots(0))]
+    pad_leading: Length,
+    /// Blank space to add to the end of each label
+    #[arg(long, default_value_t = Length::dots(0))]
+    pad_trailing: Length,
+}
+impl From<&JobArgs> for PrintOptions {
+    fn from(args: &JobArgs) -> Self {
+        Self {
+            cut: if args.no_cut {
+                CutMode::Off
+            } else {
+                CutMode::Auto
+            },
+            cut_each: args.cut_each,
+            chain: args.chain,
+            priority: if args.fast {
+                Priority::Speed
+            } else {
+                Priority::Quality
+            },
+            feed_margin: args.feed_margin,
+            trim_blank: args.trim_blank,
+            padding: (args.pad_leading, args.pad_trailing),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OrientationArg {
+    Normal,
+    /// Turned 90 degrees clockwise
+    Rotated,
+    Rotated180,
+    /// Turned 90 degrees counterclockwise
+    Rotated270,
+    /// Normal or rotated, whichever prints the image larger
+    Auto,
+}
+impl From<OrientationArg> for Orientation {
+    fn from(orientation: OrientationArg) -> Self {
+        match orientation {
+            OrientationArg::Normal => Orientation::Normal,
+            OrientationArg::Rotated => Orientation::Rotated,
+            OrientationArg::Rotated180 => Orientation::Rotated180,
+            OrientationArg::Rotated270 => Orientation::Rotated270,
+            OrientationArg::Auto => Orientation::Auto,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum MirrorArg {
+    None,
+    Horizontal,
+    Vertical,
+}
+impl From<MirrorArg> for Mirror {
+    fn from(mirror: MirrorArg) -> Self {
+        match mirror {
+            MirrorArg::None => Mirror::None,
+            MirrorArg::Horizontal => Mirror::Horizontal,
+            MirrorArg::Vertical => Mirror::Vertical,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DitherArg {
+    Threshold,
+    FloydSteinberg,
+    Atkinson,
+    JarvisJudiceNinke,
+    Stucki,
+    Sierra,
+    Bayer4,
+    Bayer8,
+}
+impl From<DitherArg> for Dither {
+    fn from(dither: DitherArg) -> Self {
+        match dither {
+            DitherArg::Threshold => Dither::Threshold,
+            DitherArg::FloydSteinberg => Dither::FloydSteinberg,
+            DitherArg::Atkinson => Dither::Atkinson,
+            DitherArg::JarvisJudiceNinke => Dither::JarvisJudiceNinke,
+            DitherArg::Stucki => Dither::Stucki,
+            DitherArg::Sierra => Dither::Sierra,
+            DitherArg::Bayer4 => Dither::Bayer4,
+            DitherArg::Bayer8 => Dither::Bayer8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FitArg {
+    Width,
+    Contain,
+    Cover,
+    Stretch,
+    None,
+}
+impl From<FitArg> for Fit {
+    fn from(fit: FitArg) -> Self {
+        match fit {
+            FitArg::Width => Fit::Width,
+            FitArg::Contain => Fit::Contain,
+            FitArg::Cover => Fit::Cover,
+            FitArg::Stretch => Fit::Stretch,
+            FitArg::None => Fit::None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum AlignArg {
+    Start,
+    Center,
+    End,
+}
+impl From<AlignArg> for Align {
+    fn from(align: AlignArg) -> Self {
+        match align {
+            AlignArg::Start => Align::Start,
+            AlignArg::Center => Align::Center,
+            AlignArg::End => Align::End,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OverflowArg {
+    Shrink,
+    Crop,
+    Error,
+}
+impl From<OverflowArg> for Overflow {
+    fn from(overflow: OverflowArg) -> Self {
+        match overflow {
+            OverflowArg::Shrink => Overflow::Shrink,
+            OverflowArg::Crop => Overflow::Crop,
+            OverflowArg::Error => Overflow::Error,
+        }
+    }
+}
+
+#[cfg(feature = "text")]
+#[derive(Clone, Copy, ValueEnum)]
+enum AlignmentArg {
+    Left,
+    Center,
+    Right,
+}
+#[cfg(feature = "text")]
+impl From<AlignmentArg> for Alignment {
+    fn from(alignment: AlignmentArg) -> Self {
+        match alignment {
+            AlignmentArg::Left => Alignment::Left,
+            AlignmentArg::Center => Alignment::Center,
+            AlignmentArg::Right => Alignment::Right,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    match cli.command {
+        Command::List => list(),
+        Command::Status => {
+            let printer = open_printer(cli.serial.as_deref())?;
+            print_status(&printer.get_status()?);
+            Ok(())
+        }
+        Command::Print {
+            images,
+            stream: true,
+            options,
+        } => {
+            let [image] = images.as_slice() else {
+                return Err("--stream prints a single image".into());
+            };
+            let image_options = image_options(&options);
+            let print_options = (&options.job).into();
+            let status =
+                if image::ImageFormat::from_path(image).ok() == Some(image::ImageFormat::Png) {
+                    let png = BufReader::new(fs::File::open(image)?);
+                    let printer = open_printer(cli.serial.as_deref())?;
+                    printer.print_png_streaming(png, &image_options, &print_options)?
+                } else {
+                    let image = image::open(image)?;
+                    let printer = open_printer(cli.serial.as_deref())?;
+                    printer.print_image_streaming(image, &image_options, &print_options)?
+                };
+            check_status(&status)
+        }
+        Command::Print {
+            images,
+            stream: false,
+            options,
+        } => {
+            let printer = open_printer(cli.serial.as_deref())?;
+            let label = printer.current_label()?;
+            let pages = images
+                .into_iter()
+                .map(|image| rasterize_file(&image, &label, &options))
+                .collect::<Result<Vec<_>>>()?
+                .concat();
+            // Collate copies of multiple images
+            let pages: Vec<_> = pages
+                .iter()
+                .cycle()
+                .take(pages.len() * options.copies)
+                .cloned()
+                .collect();
+            check_status(&printer.print_pages(&pages, &(&options.job).into())?)
+        }
+        Command::Barcode { data, options } => {
+            // Dithering blurs the edges of the bars
+            if options.dither != DitherArg::Threshold {
+                return Err("barcodes cannot be dithered".into());
+            }
+            let printer = open_printer(cli.serial.as_deref())?;
+            let label = barcode::generate_ean13_barcode(
+                barcode::EAN13Data::Simple(data),
+                String::new(),
+                String::new(),
+                None,
+            )?;
+            print(&printer, DynamicImage::ImageRgba8(label), &options)
+        }
+        #[cfg(feature = "text")]
+        Command::Text {
+            text,
+            secondary,
+            wrap,
+            align,
+            max_lines,
+            font,
+            bold_font,
+            fallback_font,
+            bold,
+            scale,
+            invert,
+            copies,
+            job,
+        } => {
+            let printer = open_printer(cli.serial.as_deref())?;
+            let fonts = load_fonts(font, bold_font, &fallback_font)?;
+            let mut rasterizer = TextRasterizer::with_fonts(printer.current_label()?, fonts)?;
+            let weight = if bold { Weight::Bold } else { Weight::Regular };
+            rasterizer.set_font(None, weight);
+            let lines = if wrap {
+                let options = LayoutOptions {
+                    font_size: LayoutOptions::default().font_size * scale,
+                    alignment: align.into(),
+                    max_lines,
+                    ..LayoutOptions::default()
+                };
+                rasterizer.rasterize_paragraph(&text, &options, invert)
+            } else {
+                rasterizer.rasterize(&text, secondary.as_deref(), scale, invert)
+            };
+            check_status(&printer.print_lines_with_options(lines, copies, &(&job).into())?)
+        }
+        #[cfg(feature = "template")]
+        Command::Template {
+            template,
+            output,
+            copies,
+            job,
+        } => {
+            let template = load_template(&template)?;
+            let image = template.render()?;
+            if let Some(output) = output {
+                return Ok(image.save(output)?);
+            }
+
+            let printer = open_printer(cli.serial.as_deref())?;
+            let label = printer.current_label()?;
+            if LabelMedia::from(&label) != template.media {
+                return Err(format!(
+                    "template is for {} labels but {} labels are loaded",
+                    ppd::media_name(&template.label()?),
+                    ppd::media_name(&label)
+                )
+                .into());
+            }
+            let image = DynamicImage::ImageLuma8(image);
+            let lines = utils::rasterize_image(image, &label, Orientation::Normal, false);
+            check_status(&printer.print_lines_with_options(lines, copies, &(&job).into())?)
+        }
+        #[cfg(feature = "template")]
+        Command::Merge {
+            template,
+            records,
+            output,
+            skip_invalid,
+            job,
+        } => {
+            let template = load_template(&template)?;
+            let records = merge::read_csv(fs::File::open(records)?)?;
+            let merged = Merge::render(&Renderer::default(), &template, records)?;
+            for error in &merged.errors {
+                eprintln!("{error}");
+            }
+            if !merged.errors.is_empty() && !skip_invalid {
+                return Err(format!("{} records failed to render", merged.errors.len()).into());
+            }
+
+            if let Some(output) = output {
+                fs::create_dir_all(&output)?;
+                for (record, image) in &merged.pages {
+                    image.save(output.join(format!("{}.png", record + 1)))?;
+                }
+                return Ok(());
+            }
+            let printer = open_printer(cli.serial.as_deref())?;
+            check_status(&merged.print(&printer, &(&job).into())?)
+        }
+        Command::Dump {
+            image,
+            label,
+            output,
+            options,
+        } => {
+            let label = parse_label(&label)?;
+            let pages = rasterize_file(&image, &label, &options)?;
+            let job = job::serialize_pages(
+                status::Media::from_label(&label),
+                pages
+                    .iter()
+                    .cycle()
+                    .take(pages.len() * options.copies)
+                    .map(Vec::as_slice),
+                &(&options.job).into(),
+            )?;
+            fs::write(&output, job)?;
+            let lines: usize = pages.iter().map(Vec::len).sum();
+            eprintln!("wrote {} raster lines to {}", lines, output.display());
+            Ok(())
+        }
+        Command::Ppd { model, output } => Ok(fs::write(output, ppd::generate(&model))?),
+        #[cfg(feature = "server")]
+        Command::Serve { addr, spool_dir } => {
+            let server = brother_ql_rs::server::Server::new(addr.as_str(), spool_dir)?;
+            eprintln!("listening on http://{addr}");
+            Ok(server.run()?)
+        }
+        #[cfg(feature = "ipp")]
+        Command::Ipp { addr, spool_dir } => {
+            let printer = open_printer(cli.serial.as_deref())?;
+            let server = brother_ql_rs::ipp::IppServer::new(printer, addr.as_str(), spool_dir)?;
+            eprintln!("listening on ipp://{addr}/ipp/print");
+            Ok(server.run()?)
+        }
+    }
+}
+
+fn list() -> Result<()> {
+    let devices = printer::printers();
+    if devices.is_empty() {
+        eprintln!("no printers found");
+    }
+    for device in devices {
+        let descriptor = device.device_descriptor()?;
+        let model = constants::printer_name_from_id(descriptor.product_id()).unwrap_or("unknown");
+        let serial = device
+            .open()
+            .and_then(|handle| handle.read_serial_number_string_ascii(&descriptor))
+            .unwrap_or_else(|_| "unknown serial".into());
+        println!(
+            "{model}\t{serial}\tbus {:03} device {:03}",
+            device.bus_number(),
+            device.address()
+        );
+    }
+    Ok(())
+}
+
+fn open_printer(serial: Option<&str>) -> Result<ThermalPrinter<rusb::GlobalContext>> {
+    for device in printer::printers() {
+        if let Some(serial) = serial {
+            // Check the serial number before claiming the device, which resets the printer
+            let descriptor = device.device_descriptor()?;
+            let device_serial = device
+                .open()
+                .and_then(|handle| handle.read_serial_number_string_ascii(&descriptor));
+            if device_serial.ok().as_deref() != Some(serial) {
+                continue;
+            }
+        }
+        return Ok(ThermalPrinter::new(device)?);
+    }
+    Err(match serial {
+        Some(serial) => format!("no printer with serial number {serial} found").into(),
+        None => "no printers found".into(),
+    })
+}
+
+fn print(
+    printer: &ThermalPrinter<rusb::GlobalContext>,
+    image: DynamicImage,
+    options: &PrintArgs,
+) -> Result<()> {
+    let lines = rasterize(image, &printer.current_label()?, options)?;
+    check_status(&printer.print_lines_with_options(
+        lines,
+        options.copies,
+        &(&options.job).into(),
+    )?)
+}
+
+/// Rasterizes an image file into one page, or a PDF file into one page per PDF page. SVG files are rendered
+/// directly at the label's size.
+fn rasterize_file(
+    path: &Path,
+    label: &constants::Label,
+    options: &PrintArgs,
+) -> Result<Vec<Vec<[u8; 90]>>> {
+    #[cfg(any(feature = "svg", feature = "pdf"))]
+    let is = |expected: &str| {
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        extension.is_some_and(|extension| extension.eq_ignore_ascii_case(expected))
+    };
+    #[cfg(feature = "svg")]
+    if is("svg") {
+        let svg = brother_ql_rs::svg::Svg::open(path)?;
+        return Ok(vec![svg.rasterize(label, &image_options(options))?]);
+    }
+    #[cfg(feature = "pdf")]
+    if is("pdf") {
+        let pdf_options = brother_ql_rs::pdf::PdfOptions {
+            image: image_options(options),
+            auto_rotate: !options.no_auto_rotate,
+            crop: !options.no_crop,
+        };
+        let renderer = brother_ql_rs::pdf::PdfRenderer::new()?;
+        return Ok(renderer.rasterize(path, label, &pdf_options)?);
+    }
+    Ok(vec![rasterize(image::open(path)?, label, options)?])
+}
+
+fn rasterize(
+    image: DynamicImage,
+    label: &constants::Label,
+    options: &PrintArgs,
+) -> Result<Vec<[u8; 90]>> {
+    Ok(utils::rasterize_image_with_options(
+        image,
+        label,
+        &image_options(options),
+    )?)
+}
+
+fn image_options(options: &PrintArgs) -> ImageOptions {
+    ImageOptions {
+        orientation: options.orientation.into(),
+        mirror: options.mirror.into(),
+        dither: options.dither.into(),
+        placement: Placement {
+            fit: options.fit.into(),
+            align: options.align.into(),
+            vertical_align: options.vertical_align.into(),
+            length: options.length,
+            overflow: options.overflow.into(),
+        },
+        preprocess: (&options.adjust).into(),
+    }
+}
+
+fn check_status(status: &status::Response) -> Result<()> {
+    if !status.errors.is_empty() {
+        return Err(status.errors.join(", ").into());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "text")]
+fn load_fonts(
+    font: Option<PathBuf>,
+    bold_font: Option<PathBuf>,
+    fallback_fonts: &[PathBuf],
+) -> Result<FontRegistry> {
+    let mut fonts = FontRegistry::new();
+    if let Some(font) = font {
+        fonts.add("font", Weight::Regular, fs::read(font)?)?;
+    }
+    if let Some(bold_font) = bold_font {
+        fonts.add("font", Weight::Bold, fs::read(bold_font)?)?;
+    }
+    for (i, fallback) in fallback_fonts.iter().enumerate() {
+        fonts.add(
+            &format!("fallback {i}"),
+            Weight::Regular,
+            fs::read(fallback)?,
+        )?;
+    }
+    #[cfg(feature = "embedded-font")]
+    fonts.add_default_family();
+    if fonts.is_empty() {
+        return Err("--font is required without the embedded-font feature".into());
+    }
+    Ok(fonts)
+}
+
+#[cfg(feature = "template")]
+fn load_template(path: &std::path::Path) -> Result<Template> {
+    let contents = fs::read_to_string(path)?;
+    Ok(match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => Template::from_toml(&contents)?,
+        _ => Template::from_json(&contents)?,
+    })
+}
+
+fn print_status(status: &status::Response) {
+    println!("model:        {}", status.model);
+    println!("status:       {:?}", status.status_type);
+    println!("phase:        {:?}", status.phase_type);
+    println!("notification: {:?}", status.notification);
+    let media = status.media;
+    match media.media_type {
+        status::MediaType::None => println!("media:        none"),
+        status::MediaType::ContinuousTape => {
+            println!("media:        {}mm continuous tape", media.width)
+        }
+        status::MediaType::DieCutLabels => {
+            println!(
+                "media:        {}mm x {}mm die-cut labels",
+                media.width, media.length
+            )
+        }
+    }
+    if status.errors.is_empty() {
+        println!("errors:       none");
+    } else {
+        println!("errors:       {}", status.errors.join(", "));
+    }
+}
+
+fn parse_label(label: &str) -> Result<constants::Label> {
+    let invalid = || format!("invalid label {label:?}, expected e.g. \"62\" or \"62x29\"");
+    let (width, length) = match label.split_once('x') {
+        Some((width, length)) => (width, Some(length.parse().map_err(|_| invalid())?)),
+        None => (label, None),
+    };
+    let width = width.parse().map_err(|_| invalid())?;
+    constants::label_data(width, length).ok_or_else(|| format!("unknown label {label:?}").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dump_dither(args: &[&str]) -> DitherArg {
+        let args = ["brother-ql", "dump"].iter().chain(args).copied();
+        match Cli::try_parse_from(args).unwrap().command {
+            Command::Dump { image, options, .. } => {
+                assert_eq!(image, Path::new("/tmp/x.png"));
+                options.dither
+            }
+            _ => panic!("expected the dump command"),
+        }
+    }
+
+    #[test]
+    fn parses_dither() {
+        let dither = dump_dither(&["/tmp/x.png", "--label", "62"]);
+        assert!(dither == DitherArg::Threshold);
+        let dither = dump_dither(&["--dither", "/tmp/x.png", "--label", "62"]);
+        assert!(dither == DitherArg::FloydSteinberg);
+        let dither = dump_dither(&["/tmp/x.png", "--dither", "--label", "62"]);
+        assert!(dither == DitherArg::FloydSteinberg);
+        let dither = dump_dither(&["--dither=atkinson", "/tmp/x.png", "--label", "62"]);
+        assert!(dither == DitherArg::Atkinson);
+
+        let args = [
+            "brother-ql",
+            "dump",
+            "--dither=sepia",
+            "/tmp/x.png",
+            "--label",
+            "62",
+        ];
+        assert!(Cli::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn cli_is_consistent() {
+        use clap::CommandFactory;
+        Cli::command().debug_assert();
+    }
+}
@@ -0,0 +1,132 @@
+//! CUPS filter converting CUPS raster to the Brother QL command stream
+//!
+//! Invoked by CUPS as `rastertobrotherql job-id user title copies options [file]`. Reads CUPS raster from `file` or
+//! stdin and writes a single print job with every page, one label each, to stdout. Use a PPD generated by `ppd::generate` (e.g. with
+//! `brother-ql ppd`) so that CUPS produces raster this filter understands.
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process::ExitCode;
+
+use brother_ql_rs::{
+    cups_raster::{self, PageHeader},
+    image::DynamicImage,
+    ppd,
+    printer::{
+        constants::{self, Label},
+        job, status, Orientation, PrintOptions,
+    },
+    utils,
+};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    if !(6..=7).contains(&args.len()) {
+        eprintln!("Usage: rastertobrotherql job-id user title copies options [file]");
+        return ExitCode::FAILURE;
+    }
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            // Prefixed for the CUPS error log
+            eprintln!("ERROR: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<()> {
+    let copies: usize = args[4]
+        .parse()
+        .map_err(|_| format!("invalid copies {:?}", args[4]))?;
+    let dither = args[5]
+        .split_whitespace()
+        .any(|option| option.eq_ignore_ascii_case("Dither=True"));
+
+    let input: Box<dyn Read> = match args.get(6) {
+        Some(path) => Box::new(File::open(path)?),
+        None => Box::new(io::stdin().lock()),
+    };
+
+    // A job prints on a single media, which is that of the first page
+    let mut media: Option<Label> = None;
+    let mut pages = Vec::new();
+    for (number, page) in cups_raster::Reader::new(input)?.enumerate() {
+        let page = page?;
+        let label = label_for_page(&page.header)
+            .ok_or_else(|| format!("no label matches page size {:?}", page.header.page_size))?;
+        let media = media.get_or_insert(label);
+        if ppd::media_name(&label) != ppd::media_name(media) {
+            return Err(format!(
+                "page {} is on {} but the job is on {}",
+                number + 1,
+                ppd::media_name(&label),
+                ppd::media_name(media)
+            )
+            .into());
+        }
+        eprintln!(
+            "INFO: rendering page {} on {}",
+            number + 1,
+            ppd::media_name(&label)
+        );
+
+        let image = crop_to_imageable_area(
+            DynamicImage::ImageLuma8(page.to_luma8()?),
+            &page.header,
+            &label,
+        );
+        pages.push(utils::rasterize_image(
+            image,
+            &label,
+            Orientation::Normal,
+            dither,
+        ));
+    }
+    let Some(label) = media else {
+        return Ok(());
+    };
+
+    // Collate copies
+    let copies = pages
+        .iter()
+        .cycle()
+        .take(pages.len() * copies.max(1))
+        .map(Vec::as_slice);
+    let job = job::serialize_pages(
+        status::Media::from_label(&label),
+        copies,
+        &PrintOptions::cut_each(1),
+    )?;
+    let mut output = io::stdout().lock();
+    output.write_all(&job)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Crop a page to the imageable area given for its label in the PPD, which is the part of the label that is
+/// printed
+fn crop_to_imageable_area(image: DynamicImage, header: &PageHeader, label: &Label) -> DynamicImage {
+    let (side_margin, end_margin) = ppd::imageable_margins(label);
+    let to_pixels =
+        |points: f64, resolution: u32| (points * f64::from(resolution) / 72.0).round() as u32;
+    let x = to_pixels(side_margin, header.hw_resolution.0).min(image.width() / 2);
+    let y = to_pixels(end_margin, header.hw_resolution.1).min(image.height() / 2);
+    image.crop_imm(x, y, image.width() - 2 * x, image.height() - 2 * y)
+}
+
+/// Find the label a page was rendered for, by its PPD page size name or else by its dimensions
+fn label_for_page(header: &PageHeader) -> Option<Label> {
+    if let Some(label) = ppd::label_from_media_name(&header.page_size_name) {
+        return Some(label);
+    }
+
+    let to_mm = |points: u32| (f64::from(points) * 25.4 / 72.0).round() as u32;
+    let (width, length) = (to_mm(header.page_size.0), to_mm(header.page_size.1));
+    constants::labels()
+        .find(|l| l.tape_size.0 == width && l.tape_size.1 == length)
+        .or_else(|| constants::labels().find(|l| l.tape_size.0 == width && l.tape_size.1 == 0))
+}
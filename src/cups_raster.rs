@@ -0,0 +1,567 @@
+//! Decoding of CUPS raster streams, including PWG raster
+//!
+//! Based on the [CUPS Raster Format](https://www.cups.org/doc/spec-raster.html) and
+//! [PWG Raster Format (PWG 5102.4)](https://ftp.pwg.org/pub/pwg/candidates/cs-ippraster10-20120420-5102.4.pdf)
+//! specifications. A stream consists of a 4 byte sync word followed by pages, each of which is a 1796 byte
+//! page header and the page's raster data.
+
+use std::convert::TryInto;
+use std::io::{self, Read};
+
+use image::{GrayImage, Luma, RgbImage};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum RasterError {
+    #[error("io: {0}")]
+    Io(#[from] io::Error),
+    #[error("not a CUPS raster stream: invalid sync word {0:?}")]
+    InvalidSyncWord([u8; 4]),
+    #[error("invalid page header: {0}")]
+    InvalidHeader(String),
+    #[error("invalid raster data: {0}")]
+    InvalidData(String),
+    #[error("unsupported raster format: {0}")]
+    Unsupported(String),
+}
+
+type Result<T> = std::result::Result<T, RasterError>;
+
+/// Size of a version 2 page header, which is also written for version 1 and 3 streams
+pub const HEADER_SIZE: usize = 1796;
+
+/// Largest page accepted, in pixels: a 1m label, the longest the printers can print, on 102mm tape at 600 dots
+/// per inch along the tape is about 28 million
+pub const MAX_PAGE_PIXELS: u64 = 32 * 1024 * 1024;
+
+/// Version of a raster stream, given by its sync word
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    /// Uncompressed (`RaSt`)
+    V1,
+    /// Compressed, also used by PWG raster (`RaS2`)
+    V2,
+    /// Uncompressed (`RaS3`)
+    V3,
+}
+
+/// Color spaces (`cupsColorSpace`) that can be converted to grayscale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Luminance (`CUPS_CSPACE_W`), where 0 is black
+    W,
+    /// Red, green, blue (`CUPS_CSPACE_RGB`)
+    Rgb,
+    /// Black (`CUPS_CSPACE_K`), where 0 is white
+    K,
+    /// sRGB gray (`CUPS_CSPACE_SW`), where 0 is black
+    SGray,
+    /// sRGB color (`CUPS_CSPACE_SRGB`)
+    SRgb,
+}
+impl ColorSpace {
+    fn from_value(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(ColorSpace::W),
+            1 => Some(ColorSpace::Rgb),
+            3 => Some(ColorSpace::K),
+            18 => Some(ColorSpace::SGray),
+            19 => Some(ColorSpace::SRgb),
+            _ => None,
+        }
+    }
+
+    /// Number of colors in each pixel
+    fn channels(&self) -> u32 {
+        match self {
+            ColorSpace::Rgb | ColorSpace::SRgb => 3,
+            _ => 1,
+        }
+    }
+
+    /// Byte value that represents white in a raster of this color space
+    fn white(&self) -> u8 {
+        match self {
+            ColorSpace::K => 0x00,
+            _ => 0xFF,
+        }
+    }
+}
+
+/// The fields of a page header needed to interpret and print its raster data
+#[derive(Debug, Clone)]
+pub struct PageHeader {
+    /// Media type name, e.g. from the PPD or IPP `media-type`
+    pub media_type: String,
+    /// Resolution in dots per inch, horizontally and vertically
+    pub hw_resolution: (u32, u32),
+    /// Number of copies to produce
+    pub num_copies: u32,
+    /// Page size in points (1/72 inch)
+    pub page_size: (u32, u32),
+    /// Width of the raster in pixels
+    pub width: u32,
+    /// Height of the raster in pixels
+    pub height: u32,
+    pub bits_per_color: u32,
+    pub bits_per_pixel: u32,
+    pub bytes_per_line: u32,
+    /// `cupsColorOrder`: 0 for chunky pixels, which is the only order supported for color rasters
+    pub color_order: u32,
+    /// Raw `cupsColorSpace` value, see `ColorSpace`
+    pub color_space: u32,
+    /// Media size name, e.g. `om_ql-62x29_62x29mm` (PWG) or `62x29mm` (CUPS)
+    pub page_size_name: String,
+    /// Byte order of 16 bit colors, which follows the byte order of the stream
+    pub big_endian: bool,
+}
+impl PageHeader {
+    fn parse(header: &[u8; HEADER_SIZE], big_endian: bool) -> Result<Self> {
+        let u32_at = |offset: usize| {
+            let bytes = header[offset..offset + 4].try_into().unwrap();
+            if big_endian {
+                u32::from_be_bytes(bytes)
+            } else {
+                u32::from_le_bytes(bytes)
+            }
+        };
+        let string_at = |offset: usize| {
+            let bytes = &header[offset..offset + 64];
+            let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+            String::from_utf8_lossy(&bytes[..end]).into_owned()
+        };
+
+        let page_header = Self {
+            media_type: string_at(128),
+            hw_resolution: (u32_at(276), u32_at(280)),
+            num_copies: u32_at(340),
+            page_size: (u32_at(352), u32_at(356)),
+            width: u32_at(372),
+            height: u32_at(376),
+            bits_per_color: u32_at(384),
+            bits_per_pixel: u32_at(388),
+            bytes_per_line: u32_at(392),
+            color_order: u32_at(396),
+            color_space: u32_at(400),
+            page_size_name: string_at(1732),
+            big_endian,
+        };
+        page_header.validate()?;
+        Ok(page_header)
+    }
+
+    /// Checks that the pixel format is supported and that the size of the raster is consistent and within
+    /// `MAX_PAGE_PIXELS`
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: String| Err(RasterError::InvalidHeader(message));
+        let color_space = self.color_space()?;
+        let supported = match color_space.channels() {
+            1 => matches!(self.bits_per_color, 1 | 8 | 16),
+            _ => matches!(self.bits_per_color, 8 | 16) && self.color_order == 0,
+        };
+        if !supported {
+            return Err(self.unsupported());
+        }
+        if self.bits_per_pixel != self.bits_per_color * color_space.channels() {
+            return invalid(format!(
+                "cupsBitsPerPixel = {} with {} bits per color",
+                self.bits_per_pixel, self.bits_per_color
+            ));
+        }
+
+        if self.width == 0 || self.height == 0 {
+            return invalid(format!("page is {}x{} pixels", self.width, self.height));
+        }
+        if u64::from(self.width) * u64::from(self.height) > MAX_PAGE_PIXELS {
+            return invalid(format!(
+                "page of {}x{} pixels is too large",
+                self.width, self.height
+            ));
+        }
+        let bytes_per_line = (u64::from(self.width) * u64::from(self.bits_per_pixel)).div_ceil(8);
+        if u64::from(self.bytes_per_line) != bytes_per_line {
+            return invalid(format!(
+                "cupsBytesPerLine = {} for {} pixels of {} bits",
+                self.bytes_per_line, self.width, self.bits_per_pixel
+            ));
+        }
+        Ok(())
+    }
+
+    fn color_space(&self) -> Result<ColorSpace> {
+        ColorSpace::from_value(self.color_space).ok_or_else(|| self.unsupported())
+    }
+
+    fn unsupported(&self) -> RasterError {
+        RasterError::Unsupported(format!(
+            "color space {} with {} bits per color and color order {}",
+            self.color_space, self.bits_per_color, self.color_order
+        ))
+    }
+
+    /// Size of the uncompressed raster data in bytes
+    fn data_size(&self) -> usize {
+        self.bytes_per_line as usize * self.height as usize
+    }
+
+    /// Size of a single pixel for the purposes of compression, in bytes
+    fn compression_unit(&self) -> usize {
+        (self.bits_per_pixel as usize).div_ceil(8)
+    }
+}
+
+/// A decoded page
+pub struct Page {
+    pub header: PageHeader,
+    /// Uncompressed raster data, `header.bytes_per_line` bytes for each of the `header.height` lines
+    pub data: Vec<u8>,
+}
+impl Page {
+    /// Convert the page to an 8-bit grayscale image, where 0 is black.
+    ///
+    /// Supports 1, 8 and 16 bit luminance (W, sGray), black (K) and chunky RGB (RGB, sRGB) rasters.
+    pub fn to_luma8(&self) -> Result<GrayImage> {
+        let header = &self.header;
+        header.validate()?;
+        if self.data.len() != header.data_size() {
+            return Err(RasterError::InvalidData(format!(
+                "{} bytes of raster data for a page of {} bytes",
+                self.data.len(),
+                header.data_size()
+            )));
+        }
+        let color_space = header.color_space()?;
+        let lines = self.data.chunks_exact(header.bytes_per_line as usize);
+        // Index of the most significant byte of each color
+        let msb = if header.bits_per_color == 16 && !header.big_endian {
+            1
+        } else {
+            0
+        };
+
+        match (color_space, header.bits_per_pixel) {
+            (ColorSpace::W | ColorSpace::SGray | ColorSpace::K, 1) => {
+                let mut image = GrayImage::new(header.width, header.height);
+                for (y, line) in lines.enumerate() {
+                    for x in 0..header.width {
+                        let bit = line[x as usize / 8] >> (7 - x % 8) & 1;
+                        let white = (bit == 1) != (color_space == ColorSpace::K);
+                        image.put_pixel(x, y as u32, Luma([if white { 0xFF } else { 0x00 }]));
+                    }
+                }
+                Ok(image)
+            }
+            (ColorSpace::W | ColorSpace::SGray | ColorSpace::K, 8 | 16) => {
+                let bytes_per_pixel = header.bits_per_pixel as usize / 8;
+                let mut image = GrayImage::new(header.width, header.height);
+                for (y, line) in lines.enumerate() {
+                    for x in 0..header.width {
+                        let value = line[x as usize * bytes_per_pixel + msb];
+                        let value = if color_space == ColorSpace::K {
+                            0xFF - value
+                        } else {
+                            value
+                        };
+                        image.put_pixel(x, y as u32, Luma([value]));
+                    }
+                }
+                Ok(image)
+            }
+            (ColorSpace::Rgb | ColorSpace::SRgb, 24 | 48) if header.color_order == 0 => {
+                let bytes_per_color = header.bits_per_color as usize / 8;
+                let mut image = RgbImage::new(header.width, header.height);
+                for (y, line) in lines.enumerate() {
+                    for x in 0..header.width {
+                        let pixel = &line[x as usize * 3 * bytes_per_color + msb..];
+                        image.put_pixel(
+                            x,
+                            y as u32,
+                            image::Rgb([
+                                pixel[0],
+                                pixel[bytes_per_color],
+                                pixel[2 * bytes_per_color],
+                            ]),
+                        );
+                    }
+                }
+                Ok(image::DynamicImage::ImageRgb8(image).to_luma8())
+            }
+            _ => Err(header.unsupported()),
+        }
+    }
+}
+
+/// Reads the pages of a CUPS raster stream one at a time
+pub struct Reader<R: Read> {
+    reader: R,
+    version: Version,
+    big_endian: bool,
+}
+impl<R: Read> Reader<R> {
+    /// Read the sync word at the start of the stream.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut sync_word = [0; 4];
+        reader.read_exact(&mut sync_word)?;
+        let (version, big_endian) = match &sync_word {
+            b"RaSt" => (Version::V1, true),
+            b"tSaR" => (Version::V1, false),
+            b"RaS2" => (Version::V2, true),
+            b"2SaR" => (Version::V2, false),
+            b"RaS3" => (Version::V3, true),
+            b"3SaR" => (Version::V3, false),
+            _ => return Err(RasterError::InvalidSyncWord(sync_word)),
+        };
+        Ok(Self {
+            reader,
+            version,
+            big_endian,
+        })
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Read the next page, or `None` at the end of the stream.
+    pub fn next_page(&mut self) -> Result<Option<Page>> {
+        let mut header = [0; HEADER_SIZE];
+        match read_exact_or_eof(&mut self.reader, &mut header)? {
+            0 => return Ok(None),
+            HEADER_SIZE => (),
+            _ => {
+                return Err(RasterError::InvalidHeader(
+                    "stream ended within page header".into(),
+                ))
+            }
+        }
+        let header = PageHeader::parse(&header, self.big_endian)?;
+
+        // Bounded by `PageHeader::validate`
+        let line_size = header.bytes_per_line as usize;
+        let size = header.data_size();
+        let mut data = Vec::new();
+        match self.version {
+            Version::V1 | Version::V3 => {
+                (&mut self.reader)
+                    .take(size as u64)
+                    .read_to_end(&mut data)?;
+                if data.len() != size {
+                    return Err(RasterError::InvalidData(
+                        "stream ended within page data".into(),
+                    ));
+                }
+            }
+            Version::V2 => {
+                data.reserve(size);
+                let mut line = vec![0; line_size];
+                while data.len() < size {
+                    let repeat = self.read_byte()? as usize + 1;
+                    self.decode_line(&header, &mut line)?;
+                    for _ in 0..repeat.min((size - data.len()) / line_size) {
+                        data.extend_from_slice(&line);
+                    }
+                }
+            }
+        }
+
+        Ok(Some(Page { header, data }))
+    }
+
+    /// Decode the PackBits-like compressed pixels of a single line
+    fn decode_line(&mut self, header: &PageHeader, line: &mut [u8]) -> Result<()> {
+        let unit = header.compression_unit();
+        let white = header.color_space()?.white();
+        let mut position = 0;
+        while position < line.len() {
+            let count = self.read_byte()?;
+            match count {
+                // Repeat the next pixel 1 to 128 times
+                0..=127 => {
+                    let end = position + (count as usize + 1) * unit;
+                    if end > line.len() {
+                        return Err(RasterError::InvalidData("run exceeds line length".into()));
+                    }
+                    self.reader
+                        .read_exact(&mut line[position..position + unit])?;
+                    for i in (position + unit..end).step_by(unit) {
+                        line.copy_within(position..position + unit, i);
+                    }
+                    position = end;
+                }
+                // Fill the remainder of the line with white
+                128 => {
+                    line[position..].fill(white);
+                    position = line.len();
+                }
+                // 2 to 128 literal pixels follow
+                129..=255 => {
+                    let end = position + (257 - count as usize) * unit;
+                    if end > line.len() {
+                        return Err(RasterError::InvalidData(
+                            "literal run exceeds line length".into(),
+                        ));
+                    }
+                    self.reader.read_exact(&mut line[position..end])?;
+                    position = end;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        let mut byte = [0];
+        self.reader.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+}
+impl<R: Read> Iterator for Reader<R> {
+    type Item = Result<Page>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_page().transpose()
+    }
+}
+
+/// Like `Read::read_exact`, but returns the number of bytes read instead of failing if the stream ends early
+fn read_exact_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A page header with the given size and pixel format, in the stream's byte order
+    fn header(
+        big_endian: bool,
+        (width, height): (u32, u32),
+        color_space: u32,
+        bits_per_color: u32,
+        bits_per_pixel: u32,
+    ) -> Vec<u8> {
+        let mut header = vec![0; HEADER_SIZE];
+        let mut set = |offset: usize, value: u32| {
+            let bytes = if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            };
+            header[offset..offset + 4].copy_from_slice(&bytes);
+        };
+        set(276, 300);
+        set(280, 300);
+        set(340, 1);
+        set(372, width);
+        set(376, height);
+        set(384, bits_per_color);
+        set(388, bits_per_pixel);
+        set(
+            392,
+            (u64::from(width) * u64::from(bits_per_pixel)).div_ceil(8) as u32,
+        );
+        set(400, color_space);
+        header[1732..1740].copy_from_slice(b"62x29mm\0");
+        header
+    }
+
+    fn read_page(stream: &[u8]) -> Result<GrayImage> {
+        let mut reader = Reader::new(stream)?;
+        let page = reader.next_page()?.expect("a page");
+        assert!(reader.next_page()?.is_none());
+        page.to_luma8()
+    }
+
+    #[test]
+    fn decodes_uncompressed_pages() {
+        // 10x2 pixels of 1 bit luminance, where 1 is white
+        let mut stream = b"RaSt".to_vec();
+        stream.extend(header(true, (10, 2), 0, 1, 1));
+        stream.extend([0b1010_0000, 0b0100_0000, 0xFF, 0xC0]);
+        let image = read_page(&stream).unwrap();
+        assert_eq!(image.dimensions(), (10, 2));
+        let first: Vec<u8> = (0..10).map(|x| image.get_pixel(x, 0)[0]).collect();
+        assert_eq!(first, [255, 0, 255, 0, 0, 0, 0, 0, 0, 255]);
+        assert!((0..10).all(|x| image.get_pixel(x, 1)[0] == 255));
+
+        // 2x1 pixels of little-endian 16 bit black, where 0 is white
+        let mut stream = b"3SaR".to_vec();
+        stream.extend(header(false, (2, 1), 3, 16, 16));
+        stream.extend([0xFF, 0x00, 0x00, 0xFF]);
+        let image = read_page(&stream).unwrap();
+        assert_eq!(image.as_raw(), &[0xFF, 0x00]);
+    }
+
+    #[test]
+    fn decodes_compressed_pages() {
+        // 8x3 pixels of 8 bit sGray
+        let mut stream = b"RaS2".to_vec();
+        stream.extend(header(true, (8, 3), 18, 8, 8));
+        // First line repeated twice: a run of 3 black pixels, 2 literal pixels and white up to the end
+        stream.extend([1, 2, 0x00, 255, 0x40, 0x80, 128]);
+        // A run of 8 gray pixels
+        stream.extend([0, 7, 0x7F]);
+        let image = read_page(&stream).unwrap();
+        assert_eq!(
+            image.as_raw(),
+            &[
+                0x00, 0x00, 0x00, 0x40, 0x80, 0xFF, 0xFF, 0xFF, //
+                0x00, 0x00, 0x00, 0x40, 0x80, 0xFF, 0xFF, 0xFF, //
+                0x7F, 0x7F, 0x7F, 0x7F, 0x7F, 0x7F, 0x7F, 0x7F,
+            ]
+        );
+
+        // 3x1 pixels of 8 bit RGB, compressed a whole pixel at a time
+        let mut stream = b"2SaR".to_vec();
+        stream.extend(header(false, (3, 1), 1, 8, 24));
+        stream.extend([0, 254, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF]);
+        let image = read_page(&stream).unwrap();
+        assert_eq!(image.as_raw(), &[0xFF, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn rejects_invalid_pages() {
+        let page = |header: Vec<u8>, data: &[u8]| {
+            let mut stream = b"RaS3".to_vec();
+            stream.extend(header);
+            stream.extend_from_slice(data);
+            read_page(&stream)
+        };
+
+        // 8 bit colors in 16 bit pixels
+        let result = page(header(true, (2, 1), 0, 8, 16), &[0; 4]);
+        assert!(matches!(result, Err(RasterError::InvalidHeader(_))));
+        // Too large to allocate
+        let result = page(header(true, (u32::MAX, u32::MAX), 0, 8, 8), &[]);
+        assert!(matches!(result, Err(RasterError::InvalidHeader(_))));
+        // cupsBytesPerLine inconsistent with the width
+        let mut inconsistent = header(true, (16, 1), 0, 8, 8);
+        inconsistent[392..396].copy_from_slice(&1u32.to_be_bytes());
+        let result = page(inconsistent, &[0; 16]);
+        assert!(matches!(result, Err(RasterError::InvalidHeader(_))));
+        // CMYK
+        let result = page(header(true, (1, 1), 6, 8, 32), &[0; 4]);
+        assert!(matches!(result, Err(RasterError::Unsupported(_))));
+        // Truncated data
+        let result = page(header(true, (4, 4), 0, 8, 8), &[0; 15]);
+        assert!(matches!(result, Err(RasterError::InvalidData(_))));
+
+        // A run past the end of the line
+        let mut stream = b"RaS2".to_vec();
+        stream.extend(header(true, (4, 1), 0, 8, 8));
+        stream.extend([0, 4, 0x00]);
+        assert!(matches!(
+            read_page(&stream),
+            Err(RasterError::InvalidData(_))
+        ));
+    }
+}
@@ -0,0 +1,737 @@
+//! A minimal IPP Everywhere server, so that a `ThermalPrinter` shows up as a network printer in standard print
+//! dialogs
+//!
+//! Supports the Get-Printer-Attributes, Print-Job, Validate-Job, Get-Jobs and Get-Job-Attributes operations over
+//! HTTP at `/ipp/print`. Documents can be PWG raster (what IPP Everywhere clients send), PNG or JPEG. The media
+//! advertised is whatever label is loaded in the printer. Jobs are spooled with a `spool::Queue`, as one spool job
+//! printing every page of the document, and take its id. Only the most recent jobs are listed by Get-Jobs.
+//!
+//! Try it on loopback with e.g. `ipptool -tv ipp://localhost:8631/ipp/print get-printer-attributes.test`.
+
+use std::convert::TryFrom;
+use std::io::{self, Read};
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use image::DynamicImage;
+use thiserror::Error;
+use tiny_http::{Header, Method, Request, Response};
+
+use crate::cups_raster;
+use crate::printer::{constants::Label, PrinterError, ThermalPrinter};
+use crate::spool::{JobId, JobOptions, JobStatus, Queue, SpoolError, Spooler};
+
+use self::protocol::{group, operation, status, Attribute, Group, Message, Value};
+
+pub mod protocol;
+
+#[derive(Error, Debug)]
+pub enum IppError {
+    #[error("io: {0}")]
+    Io(#[from] io::Error),
+    #[error("http: {0}")]
+    Http(Box<dyn std::error::Error + Send + Sync>),
+    #[error("printer: {0}")]
+    Printer(#[from] PrinterError),
+    #[error("spool: {0}")]
+    Spool(#[from] SpoolError),
+}
+
+type Result<T> = std::result::Result<T, IppError>;
+
+/// Path of the printer on the server
+const RESOURCE: &str = "/ipp/print";
+
+/// Largest request accepted, to keep a single document from exhausting memory
+const MAX_REQUEST_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How often the spooler checks for new jobs while idle
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Length advertised for continuous tape, which clients need to lay out pages
+const CONTINUOUS_LENGTH_MM: u32 = 100;
+
+/// Most copies of a document that can be requested
+const MAX_COPIES: i32 = 100;
+
+/// Most jobs remembered for Get-Jobs. Past this, the oldest finished jobs are forgotten.
+const MAX_JOB_HISTORY: usize = 100;
+
+const DOCUMENT_FORMATS: [&str; 4] = [
+    "application/octet-stream",
+    "image/pwg-raster",
+    "image/png",
+    "image/jpeg",
+];
+
+// job-state values
+const JOB_PENDING: i32 = 3;
+const JOB_PROCESSING: i32 = 5;
+const JOB_ABORTED: i32 = 8;
+const JOB_COMPLETED: i32 = 9;
+
+// printer-state values
+const PRINTER_IDLE: i32 = 3;
+const PRINTER_PROCESSING: i32 = 4;
+
+struct IppJob {
+    /// The spool job id, which stays unique across restarts of the server
+    id: i32,
+    name: String,
+    user: String,
+    created: Instant,
+    spooled: JobId,
+}
+
+struct State {
+    printer: Arc<Mutex<ThermalPrinter<rusb::GlobalContext>>>,
+    spooler: Arc<Spooler>,
+    make_and_model: String,
+    serial_number: String,
+    /// Label loaded the last time the printer could be asked
+    label: Mutex<Label>,
+    jobs: Mutex<Vec<IppJob>>,
+    started: Instant,
+}
+
+/// IPP server fronting a single printer
+pub struct IppServer {
+    http: tiny_http::Server,
+    state: Arc<State>,
+}
+impl IppServer {
+    /// Serve `printer` over IPP on `addr`, spooling jobs to `spool_dir`.
+    pub fn new<A: ToSocketAddrs, P: Into<PathBuf>>(
+        printer: ThermalPrinter<rusb::GlobalContext>,
+        addr: A,
+        spool_dir: P,
+    ) -> Result<Self> {
+        let label = printer.current_label()?;
+        let spooler = Arc::new(Spooler::new(Queue::open(spool_dir)?));
+        let state = State {
+            make_and_model: format!("{} {}", printer.manufacturer, printer.model),
+            serial_number: printer.serial_number.clone(),
+            printer: Arc::new(Mutex::new(printer)),
+            spooler,
+            label: Mutex::new(label),
+            jobs: Mutex::new(Vec::new()),
+            started: Instant::now(),
+        };
+
+        let worker = Arc::clone(&state.spooler).spawn(Arc::clone(&state.printer), POLL_INTERVAL);
+        thread::spawn(move || {
+            if let Ok(Err(e)) = worker.join() {
+                eprintln!("stopped printing: {e}");
+            }
+        });
+
+        Ok(Self {
+            http: tiny_http::Server::http(addr).map_err(IppError::Http)?,
+            state: Arc::new(state),
+        })
+    }
+
+    /// Serve requests until the listening socket fails. Each request is handled on its own thread.
+    pub fn run(&self) -> Result<()> {
+        loop {
+            let request = self.http.recv()?;
+            let state = Arc::clone(&self.state);
+            thread::spawn(move || {
+                if let Err(e) = handle_request(&state, request) {
+                    eprintln!("failed to handle IPP request: {e}");
+                }
+            });
+        }
+    }
+}
+
+fn handle_request(state: &State, mut request: Request) -> io::Result<()> {
+    let path = request.url().split('?').next().unwrap_or_default();
+    if path != RESOURCE {
+        return request.respond(Response::empty(404));
+    }
+    if *request.method() != Method::Post {
+        return request.respond(Response::empty(405));
+    }
+
+    // Host is required by HTTP/1.1, so the fallback is only for very old clients
+    let host = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Host"))
+        .map(|h| h.value.as_str().to_owned())
+        .unwrap_or_else(|| "localhost".into());
+    let printer_uri = format!("ipp://{host}{RESOURCE}");
+
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_REQUEST_SIZE + 1)
+        .read_to_end(&mut body)?;
+    if body.len() as u64 > MAX_REQUEST_SIZE {
+        return request.respond(Response::empty(413));
+    }
+    let Ok(message) = Message::parse(&body) else {
+        return request.respond(Response::empty(400));
+    };
+
+    let response = handle_operation(state, &message, &printer_uri);
+    let header = Header::from_bytes("Content-Type", "application/ipp").unwrap();
+    request.respond(Response::from_data(response.serialize()).with_header(header))
+}
+
+fn handle_operation(state: &State, request: &Message, printer_uri: &str) -> Message {
+    if !matches!(request.version.0, 1 | 2) {
+        return Message::response(request, status::SERVER_ERROR_VERSION_NOT_SUPPORTED);
+    }
+    match request.code {
+        operation::GET_PRINTER_ATTRIBUTES => get_printer_attributes(state, request, printer_uri),
+        operation::PRINT_JOB => print_job(state, request, printer_uri),
+        operation::VALIDATE_JOB => validate_job(request),
+        operation::GET_JOBS => get_jobs(state, request, printer_uri),
+        operation::GET_JOB_ATTRIBUTES => get_job_attributes(state, request, printer_uri),
+        _ => Message::response(request, status::SERVER_ERROR_OPERATION_NOT_SUPPORTED),
+    }
+}
+
+fn get_printer_attributes(state: &State, request: &Message, printer_uri: &str) -> Message {
+    // Refresh the loaded label, unless the printer is busy printing
+    let mut printer_errors = Vec::new();
+    if let Ok(printer) = state.printer.try_lock() {
+        if let Ok(status) = printer.get_status() {
+            printer_errors = status.errors;
+        }
+        if let Ok(label) = printer.current_label() {
+            *lock(&state.label) = label;
+        }
+    }
+    let label = *lock(&state.label);
+
+    let requested: Vec<&str> = request
+        .group(group::OPERATION)
+        .and_then(|g| g.get("requested-attributes"))
+        .map(|a| a.values.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    let all = requested.is_empty()
+        || requested
+            .iter()
+            .any(|r| matches!(*r, "all" | "printer-description" | "job-template"));
+
+    let mut response = Message::response(request, status::SUCCESSFUL_OK);
+    let printer_group = response.group_mut(group::PRINTER);
+    for attribute in printer_attributes(state, &label, &printer_errors, printer_uri) {
+        if all || requested.contains(&attribute.name.as_str()) {
+            printer_group.push(attribute);
+        }
+    }
+    response
+}
+
+fn printer_attributes(
+    state: &State,
+    label: &Label,
+    printer_errors: &[&str],
+    printer_uri: &str,
+) -> Vec<Attribute> {
+    let keyword = |s: &str| Value::Keyword(s.into());
+    let keywords = |s: &[&str]| s.iter().map(|s| keyword(s)).collect::<Vec<_>>();
+
+    let active_jobs = lock(&state.jobs)
+        .iter()
+        .filter(|job| job_state(state, job) < JOB_ABORTED)
+        .count();
+    let printer_state = if active_jobs > 0 {
+        PRINTER_PROCESSING
+    } else {
+        PRINTER_IDLE
+    };
+    let mut state_reasons: Vec<Value> = printer_errors
+        .iter()
+        .map(|e| match *e {
+            "No media when printing" | "End of media (die-cut)" | "Replace Media" => {
+                "media-empty-error"
+            }
+            "Tape cutter jam" => "media-jam-error",
+            "Cover open" => "cover-open",
+            _ => "other-error",
+        })
+        .map(keyword)
+        .collect();
+    state_reasons.dedup();
+    if state_reasons.is_empty() {
+        state_reasons.push(keyword("none"));
+    }
+
+    let media = media_name(label);
+    let media_col = media_col(label);
+    let resolution = Value::Resolution {
+        x: 300,
+        y: 300,
+        units: protocol::DOTS_PER_INCH,
+    };
+
+    vec![
+        Attribute::new("charset-configured", Value::Charset("utf-8".into())),
+        Attribute::new("charset-supported", Value::Charset("utf-8".into())),
+        Attribute::new("color-supported", Value::Boolean(false)),
+        Attribute::new("compression-supported", keyword("none")),
+        Attribute::new("copies-default", Value::Integer(1)),
+        Attribute::new("copies-supported", Value::Range(1, MAX_COPIES)),
+        Attribute::new(
+            "document-format-default",
+            Value::MimeMediaType(DOCUMENT_FORMATS[0].into()),
+        ),
+        Attribute::with_values(
+            "document-format-supported",
+            DOCUMENT_FORMATS
+                .iter()
+                .map(|f| Value::MimeMediaType((*f).into()))
+                .collect(),
+        ),
+        Attribute::new(
+            "generated-natural-language-supported",
+            Value::NaturalLanguage("en".into()),
+        ),
+        Attribute::with_values("ipp-versions-supported", keywords(&["1.1", "2.0"])),
+        Attribute::new("media-col-default", media_col.clone()),
+        Attribute::new("media-col-ready", media_col.clone()),
+        Attribute::new("media-col-database", media_col),
+        Attribute::with_values(
+            "media-col-supported",
+            keywords(&[
+                "media-size",
+                "media-bottom-margin",
+                "media-left-margin",
+                "media-right-margin",
+                "media-top-margin",
+            ]),
+        ),
+        Attribute::new("media-default", keyword(&media)),
+        Attribute::new("media-ready", keyword(&media)),
+        Attribute::new("media-supported", keyword(&media)),
+        Attribute::new("multiple-document-jobs-supported", Value::Boolean(false)),
+        Attribute::new(
+            "natural-language-configured",
+            Value::NaturalLanguage("en".into()),
+        ),
+        Attribute::with_values(
+            "operations-supported",
+            [
+                operation::PRINT_JOB,
+                operation::VALIDATE_JOB,
+                operation::GET_JOB_ATTRIBUTES,
+                operation::GET_JOBS,
+                operation::GET_PRINTER_ATTRIBUTES,
+            ]
+            .iter()
+            .map(|&op| Value::Enum(op.into()))
+            .collect(),
+        ),
+        Attribute::new("orientation-requested-default", Value::Enum(3)),
+        Attribute::new("pdl-override-supported", keyword("attempted")),
+        Attribute::new("print-color-mode-default", keyword("monochrome")),
+        Attribute::new("print-color-mode-supported", keyword("monochrome")),
+        Attribute::new("printer-info", Value::Text(state.make_and_model.clone())),
+        Attribute::new("printer-is-accepting-jobs", Value::Boolean(true)),
+        Attribute::new("printer-kind", keyword("labels")),
+        Attribute::new(
+            "printer-make-and-model",
+            Value::Text(state.make_and_model.clone()),
+        ),
+        Attribute::new(
+            "printer-name",
+            Value::Name(format!("{} {}", state.make_and_model, state.serial_number)),
+        ),
+        Attribute::new("printer-resolution-default", resolution.clone()),
+        Attribute::new("printer-resolution-supported", resolution.clone()),
+        Attribute::new("printer-state", Value::Enum(printer_state)),
+        Attribute::with_values("printer-state-reasons", state_reasons),
+        Attribute::new(
+            "printer-up-time",
+            Value::Integer(state.started.elapsed().as_secs().max(1) as i32),
+        ),
+        Attribute::new("printer-uri-supported", Value::Uri(printer_uri.into())),
+        Attribute::new("pwg-raster-document-resolution-supported", resolution),
+        Attribute::new("pwg-raster-document-sheet-back", keyword("normal")),
+        Attribute::with_values(
+            "pwg-raster-document-type-supported",
+            keywords(&["black_1", "sgray_8", "srgb_8"]),
+        ),
+        Attribute::new("queued-job-count", Value::Integer(active_jobs as i32)),
+        Attribute::new("sides-default", keyword("one-sided")),
+        Attribute::new("sides-supported", keyword("one-sided")),
+        Attribute::new("uri-authentication-supported", keyword("none")),
+        Attribute::new("uri-security-supported", keyword("none")),
+    ]
+}
+
+/// Self-describing PWG media name of a label
+fn media_name(label: &Label) -> String {
+    let width = label.tape_size.0;
+    match label.tape_size.1 {
+        0 => format!("roll_ql-{width}_{width}x{CONTINUOUS_LENGTH_MM}mm"),
+        length => format!("om_ql-{width}x{length}_{width}x{length}mm"),
+    }
+}
+
+/// `media-col` collection describing the size and printable area of a label, in hundredths of a millimeter
+fn media_col(label: &Label) -> Value {
+    let hundredths_of_mm = |dots: u32| Value::Integer((dots * 2540 / 300) as i32);
+    let width = label.tape_size.0 * 100;
+    let length = match label.tape_size.1 {
+        0 => CONTINUOUS_LENGTH_MM * 100,
+        length => length * 100,
+    };
+    let side_margin = label.dots.0.saturating_sub(label.dots_printable.0) / 2;
+    let end_margin = label.dots.1.saturating_sub(label.dots_printable.1) / 2;
+
+    Value::Collection(vec![
+        Attribute::new(
+            "media-size",
+            Value::Collection(vec![
+                Attribute::new("x-dimension", Value::Integer(width as i32)),
+                Attribute::new("y-dimension", Value::Integer(length as i32)),
+            ]),
+        ),
+        Attribute::new("media-bottom-margin", hundredths_of_mm(end_margin)),
+        Attribute::new("media-left-margin", hundredths_of_mm(side_margin)),
+        Attribute::new("media-right-margin", hundredths_of_mm(side_margin)),
+        Attribute::new("media-top-margin", hundredths_of_mm(end_margin)),
+    ])
+}
+
+fn validate_job(request: &Message) -> Message {
+    match document_format(request) {
+        Some(_) => Message::response(request, status::SUCCESSFUL_OK),
+        None => unsupported_format(request),
+    }
+}
+
+fn print_job(state: &State, request: &Message, printer_uri: &str) -> Message {
+    let Some(format) = document_format(request) else {
+        return unsupported_format(request);
+    };
+    let pages = match decode_document(format, &request.data) {
+        Ok(pages) if !pages.is_empty() => pages,
+        Ok(_) => {
+            return error(
+                request,
+                status::CLIENT_ERROR_DOCUMENT_FORMAT_ERROR,
+                "empty document",
+            )
+        }
+        Err(e) => return error(request, status::CLIENT_ERROR_DOCUMENT_FORMAT_ERROR, &e),
+    };
+
+    let copies = request
+        .group(group::JOB)
+        .and_then(|g| g.get("copies"))
+        .and_then(|a| a.values.first())
+        .and_then(Value::as_i32)
+        .unwrap_or(1)
+        .clamp(1, MAX_COPIES) as usize;
+    let options = JobOptions {
+        copies,
+        ..JobOptions::default()
+    };
+
+    let string_attribute = |name: &str, default: &str| {
+        request
+            .operation_attribute(name)
+            .and_then(Value::as_str)
+            .unwrap_or(default)
+            .to_owned()
+    };
+
+    let spooled = match state.spooler.queue().submit_pages(&pages, options) {
+        Ok(id) => id,
+        Err(e) => return error(request, status::SERVER_ERROR_INTERNAL_ERROR, &e.to_string()),
+    };
+    let Ok(id) = i32::try_from(spooled) else {
+        return error(
+            request,
+            status::SERVER_ERROR_INTERNAL_ERROR,
+            "job id out of range",
+        );
+    };
+    let job = IppJob {
+        id,
+        name: string_attribute("job-name", "Untitled"),
+        user: string_attribute("requesting-user-name", "anonymous"),
+        created: Instant::now(),
+        spooled,
+    };
+
+    let mut response = Message::response(request, status::SUCCESSFUL_OK);
+    response.groups.push(job_group(state, &job, printer_uri));
+
+    let mut jobs = lock(&state.jobs);
+    jobs.push(job);
+    forget_finished(&mut jobs, MAX_JOB_HISTORY, |job| {
+        job_state(state, job) >= JOB_ABORTED
+    });
+    response
+}
+
+/// Drop the oldest finished jobs until at most `max` are left. Active jobs are always kept.
+fn forget_finished(jobs: &mut Vec<IppJob>, max: usize, is_finished: impl Fn(&IppJob) -> bool) {
+    let mut excess = jobs.len().saturating_sub(max);
+    jobs.retain(|job| {
+        let forget = excess > 0 && is_finished(job);
+        if forget {
+            excess -= 1;
+        }
+        !forget
+    });
+}
+
+fn get_jobs(state: &State, request: &Message, printer_uri: &str) -> Message {
+    let completed = request
+        .operation_attribute("which-jobs")
+        .and_then(Value::as_str)
+        == Some("completed");
+
+    let mut response = Message::response(request, status::SUCCESSFUL_OK);
+    for job in lock(&state.jobs).iter().rev() {
+        if (job_state(state, job) >= JOB_ABORTED) == completed {
+            response.groups.push(job_group(state, job, printer_uri));
+        }
+    }
+    response
+}
+
+fn get_job_attributes(state: &State, request: &Message, printer_uri: &str) -> Message {
+    let id = request
+        .operation_attribute("job-id")
+        .and_then(Value::as_i32)
+        .or_else(|| {
+            request
+                .operation_attribute("job-uri")
+                .and_then(Value::as_str)
+                .and_then(|uri| uri.rsplit('/').next())
+                .and_then(|id| id.parse().ok())
+        });
+
+    let jobs = lock(&state.jobs);
+    match jobs.iter().find(|job| Some(job.id) == id) {
+        Some(job) => {
+            let mut response = Message::response(request, status::SUCCESSFUL_OK);
+            response.groups.push(job_group(state, job, printer_uri));
+            response
+        }
+        None => error(request, status::CLIENT_ERROR_NOT_FOUND, "job not found"),
+    }
+}
+
+fn job_group(state: &State, job: &IppJob, printer_uri: &str) -> Group {
+    let job_state = job_state(state, job);
+    let reason = match job_state {
+        JOB_PENDING => "none",
+        JOB_PROCESSING => "job-printing",
+        JOB_ABORTED => "aborted-by-system",
+        _ => "job-completed-successfully",
+    };
+
+    let mut group = Group::new(group::JOB);
+    group.push(Attribute::new("job-id", Value::Integer(job.id)));
+    group.push(Attribute::new(
+        "job-uri",
+        Value::Uri(format!("{printer_uri}/{}", job.id)),
+    ));
+    group.push(Attribute::new(
+        "job-printer-uri",
+        Value::Uri(printer_uri.into()),
+    ));
+    group.push(Attribute::new("job-name", Value::Name(job.name.clone())));
+    group.push(Attribute::new(
+        "job-originating-user-name",
+        Value::Name(job.user.clone()),
+    ));
+    group.push(Attribute::new("job-state", Value::Enum(job_state)));
+    group.push(Attribute::new(
+        "job-state-reasons",
+        Value::Keyword(reason.into()),
+    ));
+    group.push(Attribute::new(
+        "time-at-creation",
+        Value::Integer(job.created.duration_since(state.started).as_secs() as i32),
+    ));
+    group
+}
+
+/// IPP job state derived from the status of the spooled job
+fn job_state(state: &State, job: &IppJob) -> i32 {
+    match state.spooler.queue().job(job.spooled).map(|job| job.status) {
+        Ok(JobStatus::Pending) => JOB_PENDING,
+        Ok(JobStatus::Printing) => JOB_PROCESSING,
+        Ok(JobStatus::Completed) => JOB_COMPLETED,
+        Ok(JobStatus::Failed(_)) | Err(_) => JOB_ABORTED,
+    }
+}
+
+/// The requested document format, if it is supported
+fn document_format(request: &Message) -> Option<&'static str> {
+    let format = request
+        .operation_attribute("document-format")
+        .and_then(Value::as_str)
+        .unwrap_or(DOCUMENT_FORMATS[0]);
+    DOCUMENT_FORMATS.iter().find(|f| **f == format).copied()
+}
+
+/// Decode a document into one image per page
+fn decode_document(format: &str, data: &[u8]) -> std::result::Result<Vec<DynamicImage>, String> {
+    let is_raster = match format {
+        "image/pwg-raster" => true,
+        "application/octet-stream" => data.starts_with(b"RaS2") || data.starts_with(b"2SaR"),
+        _ => false,
+    };
+    if !is_raster {
+        return image::load_from_memory(data)
+            .map(|image| vec![image])
+            .map_err(|e| e.to_string());
+    }
+
+    let reader = cups_raster::Reader::new(data).map_err(|e| e.to_string())?;
+    reader
+        .map(|page| {
+            page.and_then(|page| page.to_luma8())
+                .map(DynamicImage::ImageLuma8)
+                .map_err(|e| e.to_string())
+        })
+        .collect()
+}
+
+fn unsupported_format(request: &Message) -> Message {
+    error(
+        request,
+        status::CLIENT_ERROR_DOCUMENT_FORMAT_NOT_SUPPORTED,
+        "unsupported document format",
+    )
+}
+
+fn error(request: &Message, status: u16, message: &str) -> Message {
+    let mut response = Message::response(request, status);
+    response.group_mut(group::OPERATION).push(Attribute::new(
+        "status-message",
+        Value::Text(message.into()),
+    ));
+    response
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::printer::constants;
+
+    fn request(format: Option<&str>) -> Message {
+        let mut operation = Group::new(group::OPERATION);
+        if let Some(format) = format {
+            operation.push(Attribute::new(
+                "document-format",
+                Value::MimeMediaType(format.into()),
+            ));
+        }
+        Message {
+            version: (2, 0),
+            code: operation::VALIDATE_JOB,
+            request_id: 7,
+            groups: vec![operation],
+            data: Vec::new(),
+        }
+    }
+
+    fn png() -> Vec<u8> {
+        let mut data = io::Cursor::new(Vec::new());
+        DynamicImage::new_luma8(3, 2)
+            .write_to(&mut data, image::ImageFormat::Png)
+            .unwrap();
+        data.into_inner()
+    }
+
+    #[test]
+    fn names_media() {
+        let continuous = constants::label_data(62, None).unwrap();
+        assert_eq!(media_name(&continuous), "roll_ql-62_62x100mm");
+        let die_cut = constants::label_data(62, Some(29)).unwrap();
+        assert_eq!(media_name(&die_cut), "om_ql-62x29_62x29mm");
+
+        let Value::Collection(members) = media_col(&die_cut) else {
+            panic!("media-col is a collection");
+        };
+        let member =
+            |name: &str| members.iter().find(|a| a.name == name).unwrap().values[0].clone();
+        let Value::Collection(size) = member("media-size") else {
+            panic!("media-size is a collection");
+        };
+        assert_eq!(size[0].values, [Value::Integer(6200)]);
+        assert_eq!(size[1].values, [Value::Integer(2900)]);
+        let margin = |name: &str| member(name).as_i32().unwrap();
+        assert_eq!(margin("media-left-margin"), margin("media-right-margin"));
+        assert!((0..300).contains(&margin("media-left-margin")));
+    }
+
+    #[test]
+    fn checks_document_formats() {
+        assert_eq!(document_format(&request(None)), Some(DOCUMENT_FORMATS[0]));
+        assert_eq!(
+            document_format(&request(Some("image/png"))),
+            Some("image/png")
+        );
+        assert_eq!(document_format(&request(Some("application/pdf"))), None);
+
+        let response = validate_job(&request(Some("image/jpeg")));
+        assert_eq!(response.code, status::SUCCESSFUL_OK);
+        assert_eq!(response.request_id, 7);
+        let response = validate_job(&request(Some("text/plain")));
+        assert_eq!(
+            response.code,
+            status::CLIENT_ERROR_DOCUMENT_FORMAT_NOT_SUPPORTED
+        );
+        assert!(response.operation_attribute("status-message").is_some());
+    }
+
+    #[test]
+    fn decodes_documents() {
+        for format in ["image/png", "application/octet-stream"] {
+            let pages = decode_document(format, &png()).unwrap();
+            assert_eq!(pages.len(), 1);
+            assert_eq!((pages[0].width(), pages[0].height()), (3, 2));
+        }
+
+        assert!(decode_document("image/png", b"not an image").is_err());
+        assert!(decode_document("image/pwg-raster", &png()).is_err());
+        // Raster data is recognized by its sync word, even without the right format
+        let empty = decode_document("application/octet-stream", b"RaS2").unwrap();
+        assert!(empty.is_empty());
+        assert!(decode_document("application/octet-stream", b"RaS2 truncated").is_err());
+    }
+
+    #[test]
+    fn forgets_oldest_finished_jobs() {
+        let mut jobs = (1..=5)
+            .map(|id| IppJob {
+                id,
+                name: "Untitled".into(),
+                user: "anonymous".into(),
+                created: Instant::now(),
+                spooled: id as JobId,
+            })
+            .collect::<Vec<_>>();
+        let ids = |jobs: &[IppJob]| jobs.iter().map(|job| job.id).collect::<Vec<_>>();
+
+        // Jobs 1 and 3 are still printing
+        forget_finished(&mut jobs, 3, |job| job.id % 2 == 0 || job.id == 5);
+        assert_eq!(ids(&jobs), [1, 3, 5]);
+        forget_finished(&mut jobs, 1, |job| job.id == 5);
+        assert_eq!(ids(&jobs), [1, 3]);
+        forget_finished(&mut jobs, 3, |_| true);
+        assert_eq!(ids(&jobs), [1, 3]);
+    }
+}
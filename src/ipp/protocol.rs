@@ -0,0 +1,644 @@
+//! Encoding and decoding of IPP messages
+//!
+//! Based on [RFC 8010](https://www.rfc-editor.org/rfc/rfc8010) (Internet Printing Protocol/1.1: Encoding and
+//! Transport). A message is a version number, an operation ID (requests) or status code (responses), a request
+//! ID, groups of attributes and finally any document data.
+
+use std::convert::TryInto;
+use std::fmt;
+
+/// Delimiter tags that start a group of attributes
+pub mod group {
+    pub const OPERATION: u8 = 0x01;
+    pub const JOB: u8 = 0x02;
+    pub const END_OF_ATTRIBUTES: u8 = 0x03;
+    pub const PRINTER: u8 = 0x04;
+    pub const UNSUPPORTED: u8 = 0x05;
+}
+
+/// Operation IDs
+pub mod operation {
+    pub const PRINT_JOB: u16 = 0x0002;
+    pub const VALIDATE_JOB: u16 = 0x0004;
+    pub const CANCEL_JOB: u16 = 0x0008;
+    pub const GET_JOB_ATTRIBUTES: u16 = 0x0009;
+    pub const GET_JOBS: u16 = 0x000A;
+    pub const GET_PRINTER_ATTRIBUTES: u16 = 0x000B;
+}
+
+/// Status codes
+pub mod status {
+    pub const SUCCESSFUL_OK: u16 = 0x0000;
+    pub const SUCCESSFUL_OK_IGNORED_OR_SUBSTITUTED_ATTRIBUTES: u16 = 0x0001;
+    pub const CLIENT_ERROR_BAD_REQUEST: u16 = 0x0400;
+    pub const CLIENT_ERROR_NOT_FOUND: u16 = 0x0406;
+    pub const CLIENT_ERROR_DOCUMENT_FORMAT_NOT_SUPPORTED: u16 = 0x040A;
+    pub const CLIENT_ERROR_DOCUMENT_FORMAT_ERROR: u16 = 0x040E;
+    pub const SERVER_ERROR_INTERNAL_ERROR: u16 = 0x0500;
+    pub const SERVER_ERROR_OPERATION_NOT_SUPPORTED: u16 = 0x0501;
+    pub const SERVER_ERROR_VERSION_NOT_SUPPORTED: u16 = 0x0503;
+}
+
+/// Value tags
+mod tag {
+    pub const UNSUPPORTED: u8 = 0x10;
+    pub const UNKNOWN: u8 = 0x12;
+    pub const NO_VALUE: u8 = 0x13;
+    pub const INTEGER: u8 = 0x21;
+    pub const BOOLEAN: u8 = 0x22;
+    pub const ENUM: u8 = 0x23;
+    pub const RESOLUTION: u8 = 0x32;
+    pub const RANGE_OF_INTEGER: u8 = 0x33;
+    pub const BEGIN_COLLECTION: u8 = 0x34;
+    pub const TEXT_WITH_LANGUAGE: u8 = 0x35;
+    pub const NAME_WITH_LANGUAGE: u8 = 0x36;
+    pub const END_COLLECTION: u8 = 0x37;
+    pub const TEXT: u8 = 0x41;
+    pub const NAME: u8 = 0x42;
+    pub const KEYWORD: u8 = 0x44;
+    pub const URI: u8 = 0x45;
+    pub const URI_SCHEME: u8 = 0x46;
+    pub const CHARSET: u8 = 0x47;
+    pub const NATURAL_LANGUAGE: u8 = 0x48;
+    pub const MIME_MEDIA_TYPE: u8 = 0x49;
+    pub const MEMBER_NAME: u8 = 0x4A;
+}
+
+/// `resolution` units: dots per inch
+pub const DOTS_PER_INCH: u8 = 3;
+
+/// Deepest nesting of collections accepted, far more than any standard attribute uses (e.g. 2 for `media-col`)
+const MAX_COLLECTION_DEPTH: usize = 16;
+
+#[derive(Debug)]
+pub struct ParseError(String);
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid IPP message: {}", self.0)
+    }
+}
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Integer(i32),
+    Boolean(bool),
+    Enum(i32),
+    Resolution {
+        x: i32,
+        y: i32,
+        units: u8,
+    },
+    Range(i32, i32),
+    Text(String),
+    Name(String),
+    Keyword(String),
+    Uri(String),
+    UriScheme(String),
+    Charset(String),
+    NaturalLanguage(String),
+    MimeMediaType(String),
+    Collection(Vec<Attribute>),
+    NoValue,
+    Unknown,
+    Unsupported,
+    /// Any other value, kept as its tag and raw bytes
+    Other(u8, Vec<u8>),
+}
+impl Value {
+    /// The value as a string, for any of the string-like value types
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Text(s)
+            | Value::Name(s)
+            | Value::Keyword(s)
+            | Value::Uri(s)
+            | Value::UriScheme(s)
+            | Value::Charset(s)
+            | Value::NaturalLanguage(s)
+            | Value::MimeMediaType(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The value as an integer, for integer and enum values
+    pub fn as_i32(&self) -> Option<i32> {
+        match self {
+            Value::Integer(i) | Value::Enum(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            Value::Integer(_) => tag::INTEGER,
+            Value::Boolean(_) => tag::BOOLEAN,
+            Value::Enum(_) => tag::ENUM,
+            Value::Resolution { .. } => tag::RESOLUTION,
+            Value::Range(..) => tag::RANGE_OF_INTEGER,
+            Value::Text(_) => tag::TEXT,
+            Value::Name(_) => tag::NAME,
+            Value::Keyword(_) => tag::KEYWORD,
+            Value::Uri(_) => tag::URI,
+            Value::UriScheme(_) => tag::URI_SCHEME,
+            Value::Charset(_) => tag::CHARSET,
+            Value::NaturalLanguage(_) => tag::NATURAL_LANGUAGE,
+            Value::MimeMediaType(_) => tag::MIME_MEDIA_TYPE,
+            Value::Collection(_) => tag::BEGIN_COLLECTION,
+            Value::NoValue => tag::NO_VALUE,
+            Value::Unknown => tag::UNKNOWN,
+            Value::Unsupported => tag::UNSUPPORTED,
+            Value::Other(tag, _) => *tag,
+        }
+    }
+
+    /// Serialize a value (with the given name, empty for additional values) including its tag
+    fn write(&self, name: &str, out: &mut Vec<u8>) {
+        out.push(self.tag());
+        write_bytes(name.as_bytes(), out);
+        match self {
+            Value::Integer(i) | Value::Enum(i) => write_bytes(&i.to_be_bytes(), out),
+            Value::Boolean(b) => write_bytes(&[*b as u8], out),
+            Value::Resolution { x, y, units } => {
+                let mut bytes = Vec::with_capacity(9);
+                bytes.extend_from_slice(&x.to_be_bytes());
+                bytes.extend_from_slice(&y.to_be_bytes());
+                bytes.push(*units);
+                write_bytes(&bytes, out);
+            }
+            Value::Range(lower, upper) => {
+                let mut bytes = Vec::with_capacity(8);
+                bytes.extend_from_slice(&lower.to_be_bytes());
+                bytes.extend_from_slice(&upper.to_be_bytes());
+                write_bytes(&bytes, out);
+            }
+            Value::Text(s)
+            | Value::Name(s)
+            | Value::Keyword(s)
+            | Value::Uri(s)
+            | Value::UriScheme(s)
+            | Value::Charset(s)
+            | Value::NaturalLanguage(s)
+            | Value::MimeMediaType(s) => write_bytes(s.as_bytes(), out),
+            Value::Collection(members) => {
+                write_bytes(&[], out);
+                for member in members {
+                    out.push(tag::MEMBER_NAME);
+                    write_bytes(&[], out);
+                    write_bytes(member.name.as_bytes(), out);
+                    for value in &member.values {
+                        value.write("", out);
+                    }
+                }
+                out.push(tag::END_COLLECTION);
+                write_bytes(&[], out);
+                write_bytes(&[], out);
+            }
+            Value::NoValue | Value::Unknown | Value::Unsupported => write_bytes(&[], out),
+            Value::Other(_, bytes) => write_bytes(bytes, out),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    pub name: String,
+    pub values: Vec<Value>,
+}
+impl Attribute {
+    pub fn new<S: Into<String>>(name: S, value: Value) -> Self {
+        Self {
+            name: name.into(),
+            values: vec![value],
+        }
+    }
+
+    pub fn with_values<S: Into<String>>(name: S, values: Vec<Value>) -> Self {
+        Self {
+            name: name.into(),
+            values,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    /// One of the `group` delimiter tags
+    pub tag: u8,
+    pub attributes: Vec<Attribute>,
+}
+impl Group {
+    pub fn new(tag: u8) -> Self {
+        Self {
+            tag,
+            attributes: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, attribute: Attribute) {
+        self.attributes.push(attribute);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Attribute> {
+        self.attributes.iter().find(|a| a.name == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub version: (u8, u8),
+    /// Operation ID for requests, status code for responses
+    pub code: u16,
+    pub request_id: u32,
+    pub groups: Vec<Group>,
+    /// Document data following the attributes
+    pub data: Vec<u8>,
+}
+impl Message {
+    /// Start a response to `request` with the attributes every response needs.
+    pub fn response(request: &Message, status: u16) -> Self {
+        let mut operation = Group::new(group::OPERATION);
+        operation.push(Attribute::new(
+            "attributes-charset",
+            Value::Charset("utf-8".into()),
+        ));
+        operation.push(Attribute::new(
+            "attributes-natural-language",
+            Value::NaturalLanguage("en".into()),
+        ));
+        Self {
+            version: request.version,
+            code: status,
+            request_id: request.request_id,
+            groups: vec![operation],
+            data: Vec::new(),
+        }
+    }
+
+    /// First group with the given delimiter tag
+    pub fn group(&self, tag: u8) -> Option<&Group> {
+        self.groups.iter().find(|g| g.tag == tag)
+    }
+
+    /// First group with the given delimiter tag, added if missing
+    pub fn group_mut(&mut self, tag: u8) -> &mut Group {
+        if let Some(index) = self.groups.iter().position(|g| g.tag == tag) {
+            &mut self.groups[index]
+        } else {
+            self.groups.push(Group::new(tag));
+            self.groups.last_mut().unwrap()
+        }
+    }
+
+    /// Value of an attribute in the operation group
+    pub fn operation_attribute(&self, name: &str) -> Option<&Value> {
+        self.group(group::OPERATION)?.get(name)?.values.first()
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        let mut parser = Parser::new(bytes);
+
+        let version = (parser.u8()?, parser.u8()?);
+        let code = parser.u16()?;
+        let request_id = u32::from_be_bytes(parser.take(4)?.try_into().unwrap());
+
+        let mut groups: Vec<Group> = Vec::new();
+        loop {
+            let tag = parser.u8()?;
+            match tag {
+                group::END_OF_ATTRIBUTES => break,
+                0x00..=0x0F => groups.push(Group::new(tag)),
+                _ => {
+                    let group = groups
+                        .last_mut()
+                        .ok_or_else(|| ParseError("attribute outside of group".into()))?;
+                    let name = parser.string()?;
+                    let value = parser.value(tag)?;
+                    if name.is_empty() {
+                        // Additional value of the previous attribute
+                        group
+                            .attributes
+                            .last_mut()
+                            .ok_or_else(|| ParseError("additional value without attribute".into()))?
+                            .values
+                            .push(value);
+                    } else {
+                        group.push(Attribute::new(name, value));
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            version,
+            code,
+            request_id,
+            groups,
+            data: bytes[parser.position..].to_vec(),
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = vec![self.version.0, self.version.1];
+        out.extend_from_slice(&self.code.to_be_bytes());
+        out.extend_from_slice(&self.request_id.to_be_bytes());
+        for group in &self.groups {
+            out.push(group.tag);
+            for attribute in &group.attributes {
+                for (i, value) in attribute.values.iter().enumerate() {
+                    value.write(if i == 0 { &attribute.name } else { "" }, &mut out);
+                }
+            }
+        }
+        out.push(group::END_OF_ATTRIBUTES);
+        out.extend_from_slice(&self.data);
+        out
+    }
+}
+
+fn write_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    /// Number of collections the parser is in
+    depth: usize,
+}
+impl<'a> Parser<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            position: 0,
+            depth: 0,
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + len)
+            .ok_or_else(|| ParseError("unexpected end of message".into()))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, ParseError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ParseError> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    /// Length-prefixed bytes
+    fn bytes(&mut self) -> Result<&'a [u8], ParseError> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|e| ParseError(e.to_string()))
+    }
+
+    /// Length-prefixed value with the given tag, which has already been read
+    fn value(&mut self, tag: u8) -> Result<Value, ParseError> {
+        if tag == tag::BEGIN_COLLECTION {
+            self.bytes()?;
+            if self.depth == MAX_COLLECTION_DEPTH {
+                return Err(ParseError("collections nested too deeply".into()));
+            }
+            self.depth += 1;
+            let collection = self.collection();
+            self.depth -= 1;
+            return collection;
+        }
+
+        let bytes = self.bytes()?;
+        let invalid = || ParseError(format!("invalid value for tag {tag:#04x}"));
+        let string = || String::from_utf8(bytes.to_vec()).map_err(|_| invalid());
+        let i32_at = |offset: usize| -> Result<i32, ParseError> {
+            Ok(i32::from_be_bytes(
+                bytes
+                    .get(offset..offset + 4)
+                    .ok_or_else(invalid)?
+                    .try_into()
+                    .unwrap(),
+            ))
+        };
+        Ok(match tag {
+            tag::INTEGER => Value::Integer(i32_at(0)?),
+            tag::ENUM => Value::Enum(i32_at(0)?),
+            tag::BOOLEAN => Value::Boolean(*bytes.first().ok_or_else(invalid)? != 0),
+            tag::RESOLUTION => Value::Resolution {
+                x: i32_at(0)?,
+                y: i32_at(4)?,
+                units: *bytes.get(8).ok_or_else(invalid)?,
+            },
+            tag::RANGE_OF_INTEGER => Value::Range(i32_at(0)?, i32_at(4)?),
+            tag::TEXT => Value::Text(string()?),
+            tag::NAME => Value::Name(string()?),
+            tag::KEYWORD => Value::Keyword(string()?),
+            tag::URI => Value::Uri(string()?),
+            tag::URI_SCHEME => Value::UriScheme(string()?),
+            tag::CHARSET => Value::Charset(string()?),
+            tag::NATURAL_LANGUAGE => Value::NaturalLanguage(string()?),
+            tag::MIME_MEDIA_TYPE => Value::MimeMediaType(string()?),
+            tag::NO_VALUE => Value::NoValue,
+            tag::UNKNOWN => Value::Unknown,
+            tag::UNSUPPORTED => Value::Unsupported,
+            tag::TEXT_WITH_LANGUAGE | tag::NAME_WITH_LANGUAGE => {
+                // The natural language is dropped
+                let mut inner = Parser::new(bytes);
+                inner.bytes()?;
+                let string = inner.string()?;
+                if tag == tag::TEXT_WITH_LANGUAGE {
+                    Value::Text(string)
+                } else {
+                    Value::Name(string)
+                }
+            }
+            _ => Value::Other(tag, bytes.to_vec()),
+        })
+    }
+
+    /// Members of a collection, after its begCollection value
+    fn collection(&mut self) -> Result<Value, ParseError> {
+        let mut members: Vec<Attribute> = Vec::new();
+        loop {
+            let tag = self.u8()?;
+            // Names are always empty within collections
+            self.bytes()?;
+            match tag {
+                tag::END_COLLECTION => {
+                    self.bytes()?;
+                    return Ok(Value::Collection(members));
+                }
+                tag::MEMBER_NAME => {
+                    let name = self.string()?;
+                    members.push(Attribute::with_values(name, Vec::new()));
+                }
+                _ => {
+                    let value = self.value(tag)?;
+                    members
+                        .last_mut()
+                        .ok_or_else(|| ParseError("collection value without member name".into()))?
+                        .values
+                        .push(value);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn media_col() -> Value {
+        Value::Collection(vec![
+            Attribute::new(
+                "media-size",
+                Value::Collection(vec![
+                    Attribute::new("x-dimension", Value::Integer(6200)),
+                    Attribute::new("y-dimension", Value::Integer(10000)),
+                ]),
+            ),
+            Attribute::with_values(
+                "media-type",
+                vec![
+                    Value::Keyword("labels".into()),
+                    Value::Keyword("continuous".into()),
+                ],
+            ),
+        ])
+    }
+
+    #[test]
+    fn message_round_trip() {
+        let mut operation = Group::new(group::OPERATION);
+        operation.push(Attribute::new(
+            "attributes-charset",
+            Value::Charset("utf-8".into()),
+        ));
+        operation.push(Attribute::new(
+            "printer-uri",
+            Value::Uri("ipp://localhost/ipp/print".into()),
+        ));
+        operation.push(Attribute::new(
+            "document-format",
+            Value::MimeMediaType("image/png".into()),
+        ));
+        let mut job = Group::new(group::JOB);
+        job.push(Attribute::new("copies", Value::Integer(2)));
+        job.push(Attribute::new("print-quality", Value::Enum(4)));
+        job.push(Attribute::new("media-col", media_col()));
+        job.push(Attribute::new(
+            "printer-resolution",
+            Value::Resolution {
+                x: 300,
+                y: 600,
+                units: DOTS_PER_INCH,
+            },
+        ));
+        job.push(Attribute::with_values(
+            "page-ranges",
+            vec![Value::Range(1, 2), Value::Range(5, 7)],
+        ));
+        job.push(Attribute::new("job-hold-until", Value::NoValue));
+        job.push(Attribute::new("print-color", Value::Boolean(false)));
+        let request = Message {
+            version: (2, 0),
+            code: operation::PRINT_JOB,
+            request_id: 42,
+            groups: vec![operation, job],
+            data: b"\x89PNG".to_vec(),
+        };
+
+        let parsed = Message::parse(&request.serialize()).unwrap();
+        assert_eq!(parsed, request);
+        assert_eq!(
+            parsed
+                .operation_attribute("document-format")
+                .and_then(Value::as_str),
+            Some("image/png")
+        );
+
+        let mut response = Message::response(&parsed, status::SUCCESSFUL_OK);
+        response
+            .group_mut(group::PRINTER)
+            .push(Attribute::new("media-col-default", media_col()));
+        assert_eq!(Message::parse(&response.serialize()).unwrap(), response);
+    }
+
+    #[test]
+    fn parses_text_with_language() {
+        let mut bytes = vec![
+            1,
+            1,
+            0,
+            2,
+            0,
+            0,
+            0,
+            1,
+            group::OPERATION,
+            tag::NAME_WITH_LANGUAGE,
+        ];
+        write_bytes(b"job-name", &mut bytes);
+        let mut value = Vec::new();
+        write_bytes(b"en", &mut value);
+        write_bytes(b"Labels", &mut value);
+        write_bytes(&value, &mut bytes);
+        bytes.push(group::END_OF_ATTRIBUTES);
+
+        let message = Message::parse(&bytes).unwrap();
+        assert_eq!(
+            message.operation_attribute("job-name"),
+            Some(&Value::Name("Labels".into()))
+        );
+    }
+
+    #[test]
+    fn limits_collection_depth() {
+        let nested = |depth: usize| {
+            let mut value = Value::Integer(1);
+            for _ in 0..depth {
+                value = Value::Collection(vec![Attribute::new("member", value)]);
+            }
+            let mut group = Group::new(group::JOB);
+            group.push(Attribute::new("nested", value));
+            Message {
+                version: (2, 0),
+                code: operation::PRINT_JOB,
+                request_id: 1,
+                groups: vec![group],
+                data: Vec::new(),
+            }
+            .serialize()
+        };
+
+        assert!(Message::parse(&nested(MAX_COLLECTION_DEPTH)).is_ok());
+        assert!(Message::parse(&nested(MAX_COLLECTION_DEPTH + 1)).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_messages() {
+        let mut group = Group::new(group::JOB);
+        group.push(Attribute::new("media-col", media_col()));
+        let bytes = Message {
+            version: (2, 0),
+            code: operation::PRINT_JOB,
+            request_id: 1,
+            groups: vec![group],
+            data: Vec::new(),
+        }
+        .serialize();
+
+        for len in 0..bytes.len() {
+            assert!(Message::parse(&bytes[..len]).is_err(), "{} bytes", len);
+        }
+    }
+}
@@ -1,4 +1,19 @@
 pub mod printer;
 pub use image;
 pub mod barcode;
+pub mod cups_raster;
+#[cfg(feature = "ipp")]
+pub mod ipp;
+#[cfg(feature = "pdf")]
+pub mod pdf;
+pub mod ppd;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod spool;
+#[cfg(feature = "svg")]
+pub mod svg;
+pub mod template;
+#[cfg(feature = "text")]
+pub mod text;
+pub mod units;
 pub mod utils;
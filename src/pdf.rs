@@ -0,0 +1,167 @@
+//! Rendering the pages of PDF files, e.g. shipping labels, with PDFium
+//!
+//! PDFium is loaded at runtime: a `libpdfium.so` (or `pdfium.dll`/`libpdfium.dylib`) in the working directory is
+//! used first, then the system library.
+
+use std::path::Path;
+
+use image::DynamicImage;
+use pdfium_render::prelude::{PdfRenderConfig, Pdfium, PdfiumError};
+use thiserror::Error;
+
+use crate::printer::{constants::Label, Orientation, PrinterError};
+use crate::units::DOTS_PER_INCH;
+use crate::utils::{self, ImageOptions};
+
+#[derive(Error, Debug)]
+pub enum PdfError {
+    #[error("pdfium: {0}")]
+    Pdfium(#[from] PdfiumError),
+    #[error("{0}")]
+    Printer(#[from] PrinterError),
+    #[error("pdf has no pages")]
+    Empty,
+}
+type Result<T> = std::result::Result<T, PdfError>;
+
+/// PDF points per inch
+const POINTS_PER_INCH: f32 = 72.0;
+
+/// Gray level below which a pixel counts as content when cropping
+const CROP_THRESHOLD: u8 = 250;
+
+/// How PDF pages are fitted to the label
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PdfOptions {
+    pub image: ImageOptions,
+    /// Turn each page to whichever way prints it larger, instead of using `image.orientation`
+    pub auto_rotate: bool,
+    /// Crop the blank margins around the content of each page
+    pub crop: bool,
+}
+impl Default for PdfOptions {
+    fn default() -> Self {
+        Self {
+            image: ImageOptions::default(),
+            auto_rotate: true,
+            crop: true,
+        }
+    }
+}
+
+pub struct PdfRenderer {
+    pdfium: Pdfium,
+}
+impl PdfRenderer {
+    /// Loads PDFium from the working directory or the system
+    pub fn new() -> Result<Self> {
+        let bindings = Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path("./"))
+            .or_else(|_| Pdfium::bind_to_system_library())?;
+        Ok(Self {
+            pdfium: Pdfium::new(bindings),
+        })
+    }
+    /// Loads PDFium from the library file at `path`
+    pub fn with_library(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            pdfium: Pdfium::new(Pdfium::bind_to_library(path)?),
+        })
+    }
+    /// Renders every page of a PDF file at 300 dots per inch
+    pub fn render(&self, path: impl AsRef<Path>) -> Result<Vec<DynamicImage>> {
+        let document = self.pdfium.load_pdf_from_file(path.as_ref(), None)?;
+        let config = PdfRenderConfig::new().scale_page_by_factor(DOTS_PER_INCH / POINTS_PER_INCH);
+        let pages = document
+            .pages()
+            .iter()
+            .map(|page| Ok(page.render_with_config(&config)?.as_image()))
+            .collect::<Result<Vec<_>>>()?;
+        if pages.is_empty() {
+            return Err(PdfError::Empty);
+        }
+        Ok(pages)
+    }
+    /// Renders and rasterizes every page of a PDF file for `label`, one set of raster lines per page. Print them
+    /// as a single job with `ThermalPrinter::print_pages`.
+    pub fn rasterize(
+        &self,
+        path: impl AsRef<Path>,
+        label: &Label,
+        options: &PdfOptions,
+    ) -> Result<Vec<Vec<[u8; 90]>>> {
+        self.render(path)?
+            .into_iter()
+            .map(|page| {
+                let page = if options.crop {
+                    crop_to_content(page)
+                } else {
+                    page
+                };
+                let mut image_options = options.image;
+                if options.auto_rotate {
+                    image_options.orientation = Orientation::Auto;
+                }
+                Ok(utils::rasterize_image_with_options(
+                    page,
+                    label,
+                    &image_options,
+                )?)
+            })
+            .collect()
+    }
+}
+
+/// Crops an image to the smallest rectangle containing all of its non-blank pixels
+fn crop_to_content(image: DynamicImage) -> DynamicImage {
+    let gray = utils::preprocess::flatten_alpha(image.clone());
+    let content = gray
+        .enumerate_pixels()
+        .filter(|(_, _, pixel)| pixel[0] < CROP_THRESHOLD);
+    let bounds = content.fold(None, |bounds, (x, y, _)| match bounds {
+        None => Some((x, y, x, y)),
+        Some((x0, y0, x1, y1)) => Some((x0.min(x), y0.min(y), x1.max(x), y1.max(y))),
+    });
+    match bounds {
+        Some((x0, y0, x1, y1)) => image.crop_imm(x0, y0, x1 - x0 + 1, y1 - y0 + 1),
+        None => image,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma, Rgba, RgbaImage};
+
+    #[test]
+    fn crops_to_content() {
+        let mut page = GrayImage::from_pixel(40, 30, Luma([255]));
+        page.put_pixel(5, 20, Luma([0]));
+        page.put_pixel(30, 8, Luma([200]));
+        // Too light to count as content
+        page.put_pixel(35, 2, Luma([CROP_THRESHOLD]));
+        let cropped = crop_to_content(DynamicImage::ImageLuma8(page));
+        assert_eq!((cropped.width(), cropped.height()), (26, 13));
+        assert_eq!(cropped.to_luma8().get_pixel(0, 12)[0], 0);
+
+        // Transparent pixels are blank, whatever their color
+        let mut page = RgbaImage::from_pixel(10, 10, Rgba([0, 0, 0, 0]));
+        page.put_pixel(4, 6, Rgba([0, 0, 0, 255]));
+        let cropped = crop_to_content(DynamicImage::ImageRgba8(page));
+        assert_eq!((cropped.width(), cropped.height()), (1, 1));
+
+        let blank = DynamicImage::ImageLuma8(GrayImage::from_pixel(10, 5, Luma([255])));
+        let cropped = crop_to_content(blank);
+        assert_eq!((cropped.width(), cropped.height()), (10, 5));
+    }
+
+    #[test]
+    fn fails_without_library() {
+        let missing = std::env::temp_dir()
+            .join("brother-ql-missing")
+            .join("libpdfium.so");
+        assert!(matches!(
+            PdfRenderer::with_library(missing),
+            Err(PdfError::Pdfium(_))
+        ));
+    }
+}
@@ -0,0 +1,269 @@
+//! PostScript Printer Description (PPD) files for using Brother QL printers through CUPS
+//!
+//! The generated PPD lists every label type known by `constants::label_data` and tells CUPS to send CUPS raster to
+//! the `rastertobrotherql` filter shipped with this crate.
+
+use std::fmt::Write;
+
+use crate::printer::constants::{self, Label};
+
+/// Length offered for continuous tape when no custom page size is requested, in mm
+const CONTINUOUS_LENGTH_MM: u32 = 100;
+
+/// Longest custom page size offered for continuous tape, in mm
+const MAX_CONTINUOUS_LENGTH_MM: u32 = 1000;
+
+/// Name of the PPD page size for a label, e.g. `62x29mm` for die-cut labels or `62mm` for continuous tape
+pub fn media_name(label: &Label) -> String {
+    match label.tape_size.1 {
+        0 => format!("{}mm", label.tape_size.0),
+        length => format!("{}x{}mm", label.tape_size.0, length),
+    }
+}
+
+/// Look up a label from a page size name created by `media_name`
+pub fn label_from_media_name(name: &str) -> Option<Label> {
+    let size = name.strip_suffix("mm")?;
+    match size.split_once('x') {
+        Some((width, length)) => {
+            constants::label_data(width.parse().ok()?, Some(length.parse().ok()?))
+        }
+        None => constants::label_data(size.parse().ok()?, None),
+    }
+}
+
+fn mm_to_points(mm: u32) -> f64 {
+    f64::from(mm) * 72.0 / 25.4
+}
+
+fn dots_to_points(dots: u32) -> f64 {
+    f64::from(dots) * 72.0 / 300.0
+}
+
+/// Margins around the imageable area of a label's page, across and along the tape, in points. They are
+/// symmetric around the printable area.
+pub fn imageable_margins(label: &Label) -> (f64, f64) {
+    (
+        dots_to_points(label.dots.0.saturating_sub(label.dots_printable.0) / 2),
+        dots_to_points(label.dots.1.saturating_sub(label.dots_printable.1) / 2),
+    )
+}
+
+/// Generate a PPD file for a printer model, e.g. `QL-800`
+pub fn generate(model: &str) -> String {
+    let labels: Vec<Label> = constants::labels().collect();
+    let default = labels
+        .iter()
+        .find(|l| l.tape_size.0 == 62 && l.tape_size.1 == 0)
+        .map_or_else(|| media_name(&labels[0]), media_name);
+
+    let mut ppd = String::new();
+    // Writing to a String cannot fail
+    let mut line = |s: String| writeln!(ppd, "{s}").unwrap();
+
+    line("*PPD-Adobe: \"4.3\"".into());
+    line("*FormatVersion: \"4.3\"".into());
+    line("*FileVersion: \"1.0\"".into());
+    line("*LanguageVersion: English".into());
+    line("*LanguageEncoding: ISOLatin1".into());
+    line(format!(
+        "*PCFileName: \"{}.ppd\"",
+        model.to_ascii_lowercase()
+    ));
+    line("*Manufacturer: \"Brother\"".into());
+    line(format!("*Product: \"(Brother {model})\""));
+    line(format!("*ModelName: \"Brother {model}\""));
+    line(format!("*ShortNickName: \"Brother {model}\""));
+    line(format!("*NickName: \"Brother {model}, brother-ql-rs\""));
+    line("*PSVersion: \"(3010.000) 0\"".into());
+    line("*LanguageLevel: \"3\"".into());
+    line("*ColorDevice: False".into());
+    line("*DefaultColorSpace: Gray".into());
+    line("*FileSystem: False".into());
+    line("*Throughput: \"1\"".into());
+    line("*LandscapeOrientation: Plus90".into());
+    line("*TTRasterizer: Type42".into());
+    line("*cupsVersion: 2.2".into());
+    line("*cupsModelNumber: 0".into());
+    line("*cupsManualCopies: False".into());
+    line("*cupsFilter: \"application/vnd.cups-raster 0 rastertobrotherql\"".into());
+
+    line("*OpenUI *ColorModel/Color Mode: PickOne".into());
+    line("*OrderDependency: 10 AnySetup *ColorModel".into());
+    line("*DefaultColorModel: Gray".into());
+    line("*ColorModel Gray/Grayscale: \"<</cupsColorOrder 0/cupsColorSpace 0/cupsBitsPerColor 8>>setpagedevice\"".into());
+    line("*CloseUI: *ColorModel".into());
+
+    line("*OpenUI *Resolution/Resolution: PickOne".into());
+    line("*OrderDependency: 10 AnySetup *Resolution".into());
+    line("*DefaultResolution: 300dpi".into());
+    line("*Resolution 300dpi/300 DPI: \"<</HWResolution[300 300]>>setpagedevice\"".into());
+    line("*CloseUI: *Resolution".into());
+
+    line("*OpenUI *Dither/Dither Photos: Boolean".into());
+    line("*OrderDependency: 10 AnySetup *Dither".into());
+    line("*DefaultDither: False".into());
+    line("*Dither True/Yes: \"\"".into());
+    line("*Dither False/No: \"\"".into());
+    line("*CloseUI: *Dither".into());
+
+    let size = |label: &Label| {
+        let width = label.tape_size.0;
+        let length = match label.tape_size.1 {
+            0 => CONTINUOUS_LENGTH_MM,
+            length => length,
+        };
+        (mm_to_points(width), mm_to_points(length))
+    };
+    let text = |label: &Label| match label.tape_size.1 {
+        0 => format!("{}mm continuous", label.tape_size.0),
+        length => format!("{}mm x {}mm die-cut", label.tape_size.0, length),
+    };
+
+    line("*OpenUI *PageSize/Media Size: PickOne".into());
+    line("*OrderDependency: 10 AnySetup *PageSize".into());
+    line(format!("*DefaultPageSize: {default}"));
+    for label in &labels {
+        let (width, length) = size(label);
+        line(format!(
+            "*PageSize {}/{}: \"<</PageSize[{width:.2} {length:.2}]/ImagingBBox null>>setpagedevice\"",
+            media_name(label),
+            text(label),
+        ));
+    }
+    line("*CloseUI: *PageSize".into());
+
+    line("*OpenUI *PageRegion/Media Size: PickOne".into());
+    line("*OrderDependency: 10 AnySetup *PageRegion".into());
+    line(format!("*DefaultPageRegion: {default}"));
+    for label in &labels {
+        let (width, length) = size(label);
+        line(format!(
+            "*PageRegion {}/{}: \"<</PageSize[{width:.2} {length:.2}]/ImagingBBox null>>setpagedevice\"",
+            media_name(label),
+            text(label),
+        ));
+    }
+    line("*CloseUI: *PageRegion".into());
+
+    line(format!("*DefaultImageableArea: {default}"));
+    for label in &labels {
+        let (width, length) = size(label);
+        let (side_margin, end_margin) = imageable_margins(label);
+        line(format!(
+            "*ImageableArea {}/{}: \"{side_margin:.2} {end_margin:.2} {:.2} {:.2}\"",
+            media_name(label),
+            text(label),
+            width - side_margin,
+            length - end_margin,
+        ));
+    }
+
+    line(format!("*DefaultPaperDimension: {default}"));
+    for label in &labels {
+        let (width, length) = size(label);
+        line(format!(
+            "*PaperDimension {}/{}: \"{width:.2} {length:.2}\"",
+            media_name(label),
+            text(label),
+        ));
+    }
+
+    // Continuous tape can be cut at any length
+    let widths: Vec<u32> = labels
+        .iter()
+        .filter(|l| l.tape_size.1 == 0)
+        .map(|l| l.tape_size.0)
+        .collect();
+    if let (Some(min), Some(max)) = (widths.iter().min(), widths.iter().max()) {
+        line("*VariablePaperSize: True".into());
+        line("*CustomPageSize True: \"pop pop pop <</PageSize[5 -2 roll]/ImagingBBox null>>setpagedevice\"".into());
+        line(format!(
+            "*ParamCustomPageSize Width: 1 points {:.2} {:.2}",
+            mm_to_points(*min),
+            mm_to_points(*max)
+        ));
+        line(format!(
+            "*ParamCustomPageSize Height: 2 points {:.2} {:.2}",
+            mm_to_points(1),
+            mm_to_points(MAX_CONTINUOUS_LENGTH_MM)
+        ));
+        line("*ParamCustomPageSize WidthOffset: 3 points 0 0".into());
+        line("*ParamCustomPageSize HeightOffset: 4 points 0 0".into());
+        line("*ParamCustomPageSize Orientation: 5 int 0 0".into());
+        line(format!("*MaxMediaWidth: \"{:.2}\"", mm_to_points(*max)));
+        line(format!(
+            "*MaxMediaHeight: \"{:.2}\"",
+            mm_to_points(MAX_CONTINUOUS_LENGTH_MM)
+        ));
+        line("*HWMargins: 0 0 0 0".into());
+    }
+
+    line("*DefaultFont: Courier".into());
+    line("*Font Courier: Standard \"(002.004S)\" Standard ROM".into());
+    ppd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn media_names_round_trip() {
+        for label in constants::labels() {
+            let name = media_name(&label);
+            let found = label_from_media_name(&name).expect(&name);
+            assert_eq!(media_name(&found), name);
+        }
+        assert_eq!(
+            media_name(&label_from_media_name("62x29mm").unwrap()),
+            "62x29mm"
+        );
+        assert_eq!(media_name(&label_from_media_name("62mm").unwrap()), "62mm");
+        assert!(label_from_media_name("62x30mm").is_none());
+        assert!(label_from_media_name("62").is_none());
+        assert!(label_from_media_name("ax29mm").is_none());
+    }
+
+    #[test]
+    fn generates_ppd() {
+        let ppd = generate("QL-800");
+        assert!(ppd.starts_with("*PPD-Adobe: \"4.3\"\n"));
+        assert!(ppd.contains("*PCFileName: \"ql-800.ppd\"\n"));
+        assert!(ppd.contains("*Product: \"(Brother QL-800)\"\n"));
+        assert!(ppd.contains("*cupsFilter: \"application/vnd.cups-raster 0 rastertobrotherql\"\n"));
+        assert!(ppd.contains("*DefaultPageSize: 62mm\n"));
+        assert!(ppd.contains(
+            "*PageSize 62x29mm/62mm x 29mm die-cut: \"<</PageSize[175.75 82.20]/ImagingBBox null>>setpagedevice\"\n"
+        ));
+        assert!(ppd.contains("*PaperDimension 62mm/62mm continuous: \"175.75 283.46\"\n"));
+
+        // Every UI group is closed and every label has a page size, region, area and dimension
+        assert_eq!(
+            ppd.matches("*OpenUI").count(),
+            ppd.matches("*CloseUI").count()
+        );
+        let labels = constants::labels().count();
+        for keyword in [
+            "*PageSize ",
+            "*PageRegion ",
+            "*ImageableArea ",
+            "*PaperDimension ",
+        ] {
+            let count = ppd.lines().filter(|l| l.starts_with(keyword)).count();
+            assert_eq!(count, labels, "{}", keyword);
+        }
+    }
+
+    #[test]
+    fn imageable_area_is_within_page() {
+        for label in constants::labels() {
+            let (side, end) = imageable_margins(&label);
+            assert!(side > 0.0 && side < mm_to_points(label.tape_size.0) / 2.0);
+            match label.tape_size.1 {
+                0 => assert_eq!(end, 0.0),
+                length => assert!(end >= 0.0 && end < mm_to_points(length) / 2.0),
+            }
+        }
+    }
+}
@@ -4,6 +4,7 @@
 //! Updated and now verified on the [800 Series Documentation](https://download.brother.com/welcome/docp100278/cv_ql800_eng_raster_101.pdf)
 
 use std::convert::TryInto;
+use std::io::{BufRead, Seek};
 use std::time::Duration;
 use std::{thread, time::Instant};
 
@@ -11,14 +12,17 @@ use image::DynamicImage;
 use thiserror::Error;
 
 use crate::printer::status::{PhaseType, StatusType};
-use crate::utils;
+use crate::utils::{self, ImageOptions, LineStream};
 
 use self::constants::{PRINTER_STATUS_SIZE, TIMEOUTS};
 
 pub mod constants;
 pub mod job;
+pub mod options;
 pub mod status;
 
+pub use options::PrintOptions;
+
 #[derive(Error, Debug)]
 pub enum PrinterError {
     #[error("usb")]
@@ -27,6 +31,31 @@ pub enum PrinterError {
     Device(String),
     #[error("printer error: {0}")]
     Printer(String),
+    #[error("printer busy: {0}")]
+    Busy(String),
+    #[error("invalid print options: {0}")]
+    InvalidOptions(String),
+    #[error("content is {0} dots long but the label only fits {1}")]
+    ContentTooLong(usize, u32),
+    #[error("content is {0} dots wide but the label only fits {1}")]
+    ContentTooWide(u32, u32),
+    #[error("image is empty")]
+    EmptyImage,
+    #[error("image: {0}")]
+    Image(String),
+}
+impl PrinterError {
+    /// Whether the operation may succeed if retried later, e.g. because the printer was cooling down or
+    /// still busy with a previous job.
+    pub fn is_recoverable(&self) -> bool {
+        matches!(
+            self,
+            PrinterError::Busy(_)
+                | PrinterError::Usb(rusb::Error::Busy)
+                | PrinterError::Usb(rusb::Error::Timeout)
+                | PrinterError::Usb(rusb::Error::Interrupted)
+        )
+    }
 }
 
 type Result<T> = std::result::Result<T, PrinterError>;
@@ -49,8 +78,6 @@ pub fn printers() -> Vec<rusb::Device<rusb::GlobalContext>> {
         .collect()
 }
 
-const RASTER_LINE_LENGTH: u8 = 90;
-
 /// The primary interface for dealing with Brother QL printers. Handles all USB communication with the printer.
 pub struct ThermalPrinter<T: rusb::UsbContext> {
     pub manufacturer: String,
@@ -83,9 +110,17 @@ enum State {
 ///
 /// Normal: label is printed so that you can read text when looking straight on
 /// Rotated: label is printed so that you have to turn your head to read the text being printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum Orientation {
+    #[default]
     Normal,
+    /// Turned 90 degrees clockwise
     Rotated,
+    Rotated180,
+    /// Turned 90 degrees counterclockwise
+    Rotated270,
+    /// `Normal` or `Rotated`, whichever prints the image larger on the loaded label
+    Auto,
 }
 impl<T: rusb::UsbContext> ThermalPrinter<T> {
     /// Create a new `ThermalPrinter` instance using a `rusb` USB device handle.
@@ -153,36 +188,152 @@ impl<T: rusb::UsbContext> ThermalPrinter<T> {
     /// and the height of the image when scaled to the original aspect ratio (for Orientation::Normal) and
     /// rotated 90 degrees (for Orientation::Rotated).
     ///
-    /// Only supported on endless labels. Untested behavior on die-cut labels
+    /// On die-cut labels, images that are too long are shrunk to fit the printable area and shorter images are
+    /// centered. Use `utils::rasterize_image_with_options` and `print_lines` to fit them differently.
     pub fn print_image(
         &self,
         image: DynamicImage,
         orientation: Orientation,
         dither: bool,
         copies: usize,
+    ) -> Result<status::Response> {
+        let image_options = ImageOptions {
+            orientation,
+            dither: dither.into(),
+            ..ImageOptions::default()
+        };
+        self.print_image_with_options(image, &image_options, copies, &PrintOptions::default())
+    }
+
+    /// Like `print_image`, with control over how the image is rasterized and how the job is cut and printed
+    pub fn print_image_with_options(
+        &self,
+        image: DynamicImage,
+        image_options: &ImageOptions,
+        copies: usize,
+        options: &PrintOptions,
     ) -> Result<status::Response> {
         let status = self.get_status()?;
 
-        // Resize and Rotate
-        let image = utils::resize_and_rotate_image(
-            image,
-            orientation,
-            status.media.to_label().dots_printable.0,
-        );
+        let lines =
+            utils::rasterize_image_with_options(image, &status.media.to_label(), image_options)?;
+
+        self.print_lines_with_options(lines, copies, options)
+    }
+
+    /// Sends already rasterized lines (see `utils::rasterize_image`) to the printer, cutting after every
+    /// `cut_each` copies.
+    pub fn print_lines(
+        &self,
+        lines: Vec<[u8; 90]>,
+        copies: usize,
+        cut_each: u8,
+    ) -> Result<status::Response> {
+        self.print_lines_with_options(lines, copies, &PrintOptions::cut_each(cut_each))
+    }
+
+    /// Like `print_lines`, with control over cutting and print priority
+    pub fn print_lines_with_options(
+        &self,
+        lines: Vec<[u8; 90]>,
+        copies: usize,
+        options: &PrintOptions,
+    ) -> Result<status::Response> {
+        let pages = std::iter::repeat_n(lines.as_slice(), copies);
+        self.cmd_print(pages, options, &mut 0)?;
 
-        // Grayscale
-        let mut image = utils::convert_image_to_luma_u8(image);
+        self.cmd_status_request()
+    }
+
+    /// Resizes and rasterizes each image like `print_image` and sends them to the printer as a single job, one
+    /// label per image. Printing many different labels this way avoids restarting the printer between each one.
+    pub fn print_images(
+        &self,
+        images: impl IntoIterator<Item = DynamicImage>,
+        image_options: &ImageOptions,
+        options: &PrintOptions,
+    ) -> Result<status::Response> {
+        let label = self.get_status()?.media.to_label();
+
+        let pages = images
+            .into_iter()
+            .map(|image| utils::rasterize_image_with_options(image, &label, image_options))
+            .collect::<Result<Vec<_>>>()?;
+
+        self.print_pages(&pages, options)
+    }
+
+    /// Rasterizes an image like `print_image_with_options` and sends each line to the printer as soon as it is
+    /// ready, so that very long labels on continuous tape print in bounded memory. See `utils::LineStream` for the
+    /// supported options.
+    pub fn print_image_streaming(
+        &self,
+        image: DynamicImage,
+        image_options: &ImageOptions,
+        options: &PrintOptions,
+    ) -> Result<status::Response> {
+        let label = self.get_status()?.media.to_label();
+        let lines = LineStream::new(image, &label, image_options)?;
+        self.print_line_stream(lines, options)
+    }
 
-        // Dither
-        if dither {
-            utils::dither_luma8_image(&mut image);
+    /// Prints a PNG file like `print_image_streaming`, decoding it one row at a time so that memory use doesn't
+    /// grow with the length of the label. If the file can't be decoded partway through, the rest of the label is
+    /// left blank and the error is returned once it has printed.
+    pub fn print_png_streaming<R: BufRead + Seek + Send + 'static>(
+        &self,
+        png: R,
+        image_options: &ImageOptions,
+        options: &PrintOptions,
+    ) -> Result<status::Response> {
+        let label = self.get_status()?.media.to_label();
+        let lines = LineStream::from_png(png, &label, image_options)?;
+        self.print_line_stream(lines, options)
+    }
+
+    fn print_line_stream(
+        &self,
+        mut lines: LineStream,
+        options: &PrintOptions,
+    ) -> Result<status::Response> {
+        let response = self.print_stream(&mut lines, options)?;
+        match lines.take_error() {
+            Some(e) => Err(e),
+            None => Ok(response),
         }
+    }
 
-        // Rasterize
-        let lines = utils::rasterize_image_to_ql_tiff(image);
+    /// Sends raster lines to the printer as they are produced, as a single label on continuous tape. Blank lines
+    /// can't be trimmed, since the length of the label has to be sent first.
+    pub fn print_stream(
+        &self,
+        lines: impl ExactSizeIterator<Item = [u8; 90]>,
+        options: &PrintOptions,
+    ) -> Result<status::Response> {
+        self.cmd_print_stream(lines, options)?;
 
-        // Print
-        self.cmd_print(lines, copies, 1)?;
+        self.cmd_status_request()
+    }
+
+    /// Sends already rasterized pages to the printer as a single job, one label per page
+    pub fn print_pages(
+        &self,
+        pages: &[Vec<[u8; 90]>],
+        options: &PrintOptions,
+    ) -> Result<status::Response> {
+        self.print_pages_counted(pages, options, &mut 0)
+    }
+
+    /// Like `print_pages`, adding one to `printed` for every page that the printer reports as printed. If the job
+    /// fails, sending the pages from `printed` on finishes it without repeating any label that came out, although
+    /// the page that was printing when the job failed may have been printed already.
+    pub fn print_pages_counted(
+        &self,
+        pages: &[Vec<[u8; 90]>],
+        options: &PrintOptions,
+        printed: &mut usize,
+    ) -> Result<status::Response> {
+        self.cmd_print(pages.iter().map(Vec::as_slice), options, printed)?;
 
         self.cmd_status_request()
     }
@@ -192,7 +343,7 @@ impl<T: rusb::UsbContext> ThermalPrinter<T> {
     /// Send 400 bytes of 0x00
     fn cmd_invalidate(&self) {
         loop {
-            match self.write(&[0x00_u8; 400]) {
+            match self.write(&job::INVALIDATE) {
                 Ok(_) => break,
                 Err(PrinterError::Usb(rusb::Error::Busy)) => {
                     thread::sleep(Duration::from_millis(100));
@@ -236,14 +387,84 @@ impl<T: rusb::UsbContext> ThermalPrinter<T> {
     }
 
     /// Send control codes
-    fn cmd_control_codes(&self, media: status::Media, num_lines: u32, cut_each: u8) -> Result<()> {
-        let mut new_job = job::Info::new(media, num_lines);
-        new_job.cut_each = cut_each;
+    fn cmd_control_codes(
+        &self,
+        media: status::Media,
+        num_lines: u32,
+        page: job::Page,
+        options: &PrintOptions,
+    ) -> Result<()> {
+        let mut new_job = job::Info::with_options(media, num_lines, options);
+        new_job.page = page;
         self.write_with_timeout(new_job.serialize().as_slice(), TIMEOUTS.general)
     }
 
     /// Send raster data/main print loop
-    fn cmd_print(&self, lines: Vec<[u8; 90]>, copies: usize, cut_each: u8) -> Result<()> {
+    fn cmd_print<'a>(
+        &self,
+        pages: impl IntoIterator<Item = &'a [[u8; 90]]>,
+        options: &PrintOptions,
+        printed: &mut usize,
+    ) -> Result<()> {
+        let media = self.cmd_start(options)?;
+        let label = media.to_label();
+        let mut state = State::Waiting;
+
+        // Print Loop
+        let mut pages = pages.into_iter().peekable();
+        let mut page = job::Page::Starting;
+        while let Some(lines) = pages.next() {
+            let lines = job::fit_lines(options.prepare_lines(lines), &label)?;
+            let last = pages.peek().is_none();
+            let lines = (lines.len(), lines.iter().copied());
+            self.cmd_page(&mut state, media, lines, page, last, options)?;
+            *printed += 1;
+            page = job::Page::Other;
+        }
+        Ok(())
+    }
+
+    /// Print a single label on continuous tape, sending each line as soon as it is produced
+    fn cmd_print_stream(
+        &self,
+        lines: impl ExactSizeIterator<Item = [u8; 90]>,
+        options: &PrintOptions,
+    ) -> Result<()> {
+        if options.trim_blank {
+            return Err(PrinterError::InvalidOptions(
+                "blank lines cannot be trimmed from a stream".into(),
+            ));
+        }
+        let media = self.cmd_start(options)?;
+        if media.to_label().tape_size.1 != 0 {
+            return Err(PrinterError::InvalidOptions(
+                "only continuous tape can be printed from a stream".into(),
+            ));
+        }
+
+        let padding = (
+            options.padding.0.to_dots() as usize,
+            options.padding.1.to_dots() as usize,
+        );
+        let num_lines = padding.0 + lines.len() + padding.1;
+        let lines = std::iter::repeat_n([0; 90], padding.0)
+            .chain(lines)
+            .chain(std::iter::repeat_n([0; 90], padding.1));
+        let mut state = State::Waiting;
+        self.cmd_page(
+            &mut state,
+            media,
+            (num_lines, lines),
+            job::Page::Starting,
+            true,
+            options,
+        )
+    }
+
+    /// Checks the options and prepares the printer for a job, returning the loaded media
+    fn cmd_start(&self, options: &PrintOptions) -> Result<status::Media> {
+        options.validate(&self.model)?;
+
         // Invalidate
         self.cmd_invalidate();
 
@@ -253,69 +474,69 @@ impl<T: rusb::UsbContext> ThermalPrinter<T> {
         // Status Information Request
         let status = self.cmd_status_request()?;
         let PhaseType::WaitingToReceive = status.phase_type else {
-            return Err(PrinterError::Printer("printer in invalid phase".into()));
+            return Err(PrinterError::Busy("printer in invalid phase".into()));
         };
+        options.validate_media(&status.media.to_label())?;
+        Ok(status.media)
+    }
 
-        let mut state = State::Waiting;
-
-        // Print Loop
-        let mut printed_copies = 0;
-        loop {
-            // Control Codes
-            self.cmd_control_codes(
-                status.media,
-                lines
-                    .len()
-                    .try_into()
-                    .expect("cannot cast result from lines.len() into u32"),
-                cut_each,
-            )?;
-
-            // Send raster data
-            for line in lines.iter() {
-                let mut raster_command = vec![0x67, 0x00, RASTER_LINE_LENGTH];
-                raster_command.extend_from_slice(line);
-                match state {
-                    State::Waiting | State::PrintingStarted => (),
-                    e => {
-                        return Err(PrinterError::Printer(format!(
-                            "unexpected status at start of line print: {e:?}"
-                        )))
-                    }
-                }
-                if let Err(e) = self.write_with_timeout(&raster_command, TIMEOUTS.line_print) {
-                    // Only acceptable error in sending raster line here is for cooling
-                    self.read_loop(&mut state, State::PrintingStarted);
-                    let State::PrintingStarted = state else {
-                        return Err(PrinterError::Printer(format!(
-                            "unexpected state during cooldown: {state:?} - encountered error {e}"
-                        )));
-                    };
+    /// Send a single page of `num_lines` lines and wait for it to be printed
+    fn cmd_page(
+        &self,
+        state: &mut State,
+        media: status::Media,
+        (num_lines, lines): (usize, impl Iterator<Item = [u8; 90]>),
+        page: job::Page,
+        last: bool,
+        options: &PrintOptions,
+    ) -> Result<()> {
+        // Control Codes
+        self.cmd_control_codes(
+            media,
+            num_lines
+                .try_into()
+                .expect("cannot cast number of lines into u32"),
+            page,
+            options,
+        )?;
+
+        // Send raster data
+        for line in lines {
+            let raster_command = job::raster_command(&line);
+            match state {
+                State::Waiting | State::PrintingStarted => (),
+                e => {
+                    return Err(PrinterError::Printer(format!(
+                        "unexpected status at start of line print: {e:?}"
+                    )))
                 }
             }
-
-            if copies > (printed_copies + 1) {
-                // Print without feeding
-                self.write_with_timeout(&[0x0c], TIMEOUTS.line_print)?;
-            } else {
-                // Print with feeding
-                self.write_with_timeout(&[0x1a], TIMEOUTS.line_print)?;
-            };
-
-            // Verify
-            self.read_loop(&mut state, State::Waiting);
-            let State::Waiting = state else {
-                return Err(PrinterError::Printer(format!(
-                    "unexpected state during verification: {state:?}"
-                )));
-            };
-
-            printed_copies += 1;
-
-            if printed_copies >= copies {
-                break;
+            if let Err(e) = self.write_with_timeout(&raster_command, TIMEOUTS.line_print) {
+                // Only acceptable error in sending raster line here is for cooling
+                self.read_loop(state, State::PrintingStarted);
+                let State::PrintingStarted = state else {
+                    return Err(PrinterError::Busy(format!(
+                        "unexpected state during cooldown: {state:?} - encountered error {e}"
+                    )));
+                };
             }
         }
+
+        if last {
+            // Print with feeding
+            self.write_with_timeout(&job::PRINT_WITH_FEEDING, TIMEOUTS.line_print)?;
+        } else {
+            // Print without feeding
+            self.write_with_timeout(&job::PRINT, TIMEOUTS.line_print)?;
+        };
+
+        // Verify
+        self.read_loop(state, State::Waiting);
+        let State::Waiting = state else {
+            return Err(PrinterError::Printer(format!(
+                "unexpected state during verification: {state:?}"
+            )));
+        };
         Ok(())
     }
 
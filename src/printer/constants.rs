@@ -14,6 +14,34 @@ pub struct Label {
     pub feed_margin: u8,
 }
 
+/// Width and length (`None` for continuous tape) in mm of every label type known by `label_data`
+pub const MEDIA: [(u8, Option<u8>); 17] = [
+    (17, Some(54)),
+    (17, Some(87)),
+    (23, Some(23)),
+    (29, Some(42)),
+    (29, Some(90)),
+    (38, Some(90)),
+    (39, Some(48)),
+    (52, Some(29)),
+    (62, Some(29)),
+    (62, Some(100)),
+    (12, None),
+    (29, None),
+    (38, None),
+    (50, None),
+    (54, None),
+    (62, None),
+    (102, None),
+];
+
+/// Returns all label types known by `label_data`
+pub fn labels() -> impl Iterator<Item = Label> {
+    MEDIA
+        .iter()
+        .filter_map(|&(width, length)| label_data(width, length))
+}
+
 /// Returns a corresponding label type given dimensions returned by the printer
 ///
 /// These are predefined label rolls types sold by Brother and defined in the spec
@@ -56,7 +84,9 @@ pub fn label_data(width: u8, length: Option<u8>) -> Option<Label> {
                 right_margin: 6,
                 feed_margin: 0,
             }),
-            (39, 90) => Some(Label {
+            // DK-11208 is 38mm wide in the media table of the raster command reference. 39 is what this table
+            // used to call it, and is kept for callers that still look it up that way.
+            (38, 90) | (39, 90) => Some(Label {
                 tape_size: WidthLength(38, 90),
                 dots: WidthLength(449, 1061),
                 dots_printable: WidthLength(413, 991),
@@ -1,6 +1,108 @@
+use std::borrow::Cow;
+
 use crate::printer::status;
 
+use super::constants::Label;
+use super::options::{CutMode, PrintOptions, Priority};
 use super::status::Media;
+use super::PrinterError;
+
+/// Number of bytes in a single raster line
+pub const RASTER_LINE_LENGTH: u8 = 90;
+
+/// Invalidate command: clears out any partially received command from a previous job
+pub const INVALIDATE: [u8; 400] = [0x00; 400];
+
+/// Initialize command
+pub const INITIALIZE: [u8; 2] = [0x1B, 0x40];
+
+/// Print command for every page but the last
+pub const PRINT: [u8; 1] = [0x0C];
+
+/// Print command for the last page of a job, which feeds (and cuts) the media
+pub const PRINT_WITH_FEEDING: [u8; 1] = [0x1A];
+
+/// Raster graphics transfer command for a single line
+pub fn raster_command(line: &[u8; RASTER_LINE_LENGTH as usize]) -> Vec<u8> {
+    let mut command = Vec::with_capacity(3 + line.len());
+    command.extend_from_slice(&[0x67, 0x00, RASTER_LINE_LENGTH]);
+    command.extend_from_slice(line);
+    command
+}
+
+/// Pads the raster lines of a page on a die-cut label to the exact printable length, centering them. Fails if
+/// there are more lines than fit on the label. Pages on continuous tape are left as is.
+pub fn fit_lines<'a>(
+    lines: Cow<'a, [[u8; RASTER_LINE_LENGTH as usize]]>,
+    label: &Label,
+) -> Result<Cow<'a, [[u8; RASTER_LINE_LENGTH as usize]]>, PrinterError> {
+    let length = match label.tape_size.1 {
+        0 => return Ok(lines),
+        _ => label.dots_printable.1 as usize,
+    };
+    if lines.len() > length {
+        return Err(PrinterError::ContentTooLong(lines.len(), length as u32));
+    }
+    if lines.len() == length {
+        return Ok(lines);
+    }
+
+    let leading = (length - lines.len()) / 2;
+    let mut fitted = vec![[0; RASTER_LINE_LENGTH as usize]; length];
+    fitted[leading..leading + lines.len()].copy_from_slice(&lines);
+    Ok(Cow::Owned(fitted))
+}
+
+/// Serialize a complete job printing `copies` copies of the given raster lines, exactly as it would be sent
+/// to a printer with `media` loaded.
+///
+/// Useful for saving jobs to a file and sending them to a printer later, e.g. with `cat job.bin > /dev/usb/lp0`.
+pub fn serialize_job(
+    media: Media,
+    lines: &[[u8; RASTER_LINE_LENGTH as usize]],
+    copies: usize,
+    cut_each: u8,
+) -> Result<Vec<u8>, PrinterError> {
+    serialize_pages(
+        media,
+        std::iter::repeat_n(lines, copies),
+        &PrintOptions::cut_each(cut_each),
+    )
+}
+
+/// Serialize a job printing each of `pages` once, in order, as with `ThermalPrinter::print_pages`
+pub fn serialize_pages<'a>(
+    media: Media,
+    pages: impl IntoIterator<Item = &'a [[u8; RASTER_LINE_LENGTH as usize]]>,
+    options: &PrintOptions,
+) -> Result<Vec<u8>, PrinterError> {
+    let label = media.to_label();
+    options.validate_media(&label)?;
+
+    let mut command = Vec::new();
+    command.extend_from_slice(&INVALIDATE);
+    command.extend_from_slice(&INITIALIZE);
+
+    let mut pages = pages.into_iter().peekable();
+    let mut page = Page::Starting;
+    while let Some(lines) = pages.next() {
+        let lines = fit_lines(options.prepare_lines(lines), &label)?;
+        let mut info = Info::with_options(media, lines.len() as u32, options);
+        info.page = page;
+        command.extend(info.serialize());
+        page = Page::Other;
+
+        for line in lines.iter() {
+            command.extend(raster_command(line));
+        }
+        if pages.peek().is_some() {
+            command.extend_from_slice(&PRINT);
+        } else {
+            command.extend_from_slice(&PRINT_WITH_FEEDING);
+        }
+    }
+    Ok(command)
+}
 
 pub struct Info {
     pub media: Media,
@@ -11,8 +113,11 @@ pub struct Info {
     pub auto_cut: bool,
     pub cut_at_end: bool,
     pub high_resolution: bool,
+    /// Feed margin in dots
+    pub feed_margin: u16,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Page {
     Starting,
     Other,
@@ -29,6 +134,18 @@ impl Info {
             auto_cut: true,
             cut_at_end: true,
             high_resolution: false,
+            feed_margin: u16::from(media.to_label().feed_margin),
+        }
+    }
+    /// Job information for printing with `options`
+    pub fn with_options(media: Media, num_lines: u32, options: &PrintOptions) -> Self {
+        Self {
+            prioritize_quality: options.priority == Priority::Quality,
+            cut_each: options.cut_each,
+            auto_cut: options.cut == CutMode::Auto,
+            cut_at_end: !options.chain,
+            feed_margin: options.feed_margin(&media.to_label()),
+            ..Self::new(media, num_lines)
         }
     }
     pub fn serialize(&self) -> Vec<u8> {
@@ -98,11 +215,117 @@ impl Info {
         {
             // margins
             let mut command_fragment = [0x1B, 0x69, 0x64, 0, 0];
-            command_fragment[3..3 + 2]
-                .copy_from_slice(&(self.media.to_label().feed_margin as u16).to_le_bytes());
+            command_fragment[3..3 + 2].copy_from_slice(&self.feed_margin.to_le_bytes());
             command.extend(command_fragment);
         }
 
         command
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::printer::constants::label_data;
+
+    /// Length of the information commands before each page
+    const INFO_LENGTH: usize = 30;
+
+    const HEADER_LENGTH: usize = INVALIDATE.len() + INITIALIZE.len();
+
+    fn continuous() -> Media {
+        Media::from_label(&label_data(62, None).unwrap())
+    }
+
+    fn page(lines: usize, byte: u8) -> Vec<[u8; RASTER_LINE_LENGTH as usize]> {
+        vec![[byte; RASTER_LINE_LENGTH as usize]; lines]
+    }
+
+    /// Length of the commands for a page of `lines` lines, including its print command
+    fn page_length(lines: usize) -> usize {
+        INFO_LENGTH + lines * (3 + RASTER_LINE_LENGTH as usize) + 1
+    }
+
+    #[test]
+    fn serializes_pages() {
+        let media = continuous();
+        let job = serialize_job(media, &page(3, 0xAA), 1, 1).unwrap();
+        assert_eq!(job.len(), HEADER_LENGTH + page_length(3));
+        assert_eq!(job[..INVALIDATE.len()], INVALIDATE);
+        assert_eq!(job[INVALIDATE.len()..HEADER_LENGTH], INITIALIZE);
+
+        let info = &job[HEADER_LENGTH..HEADER_LENGTH + INFO_LENGTH];
+        let margin = media.to_label().feed_margin;
+        assert_eq!(
+            info,
+            [
+                0x1B, 0x69, 0x7A, 0xCE, 0x0A, 62, 0, 3, 0, 0, 0, 0, 0, // print information
+                0x1B, 0x69, 0x41, 1, // cut each page
+                0x1B, 0x69, 0x4D, 0x40, // auto cut
+                0x1B, 0x69, 0x4B, 0x08, // cut at end
+                0x1B, 0x69, 0x64, margin, 0, // feed margin
+            ]
+        );
+        let raster = &job[HEADER_LENGTH + INFO_LENGTH..job.len() - 1];
+        for command in raster.chunks(3 + RASTER_LINE_LENGTH as usize) {
+            assert_eq!(command[..3], [0x67, 0x00, RASTER_LINE_LENGTH]);
+            assert!(command[3..].iter().all(|&byte| byte == 0xAA));
+        }
+        assert_eq!(job.last(), PRINT_WITH_FEEDING.last());
+    }
+
+    #[test]
+    fn serializes_multiple_pages_as_one_job() {
+        let media = continuous();
+        let (first, second) = (page(2, 1), page(5, 2));
+        let job =
+            serialize_pages(media, [&first[..], &second[..]], &PrintOptions::default()).unwrap();
+        assert_eq!(job.len(), HEADER_LENGTH + page_length(2) + page_length(5));
+
+        // Only the first page starts the job, and only the last page feeds
+        let second_info = HEADER_LENGTH + page_length(2);
+        assert_eq!(job[HEADER_LENGTH + 11], 0);
+        assert_eq!(job[second_info + 11], 1);
+        assert_eq!(job[second_info + 7], 5);
+        assert_eq!(job[second_info - 1], PRINT[0]);
+        assert_eq!(job.last(), PRINT_WITH_FEEDING.last());
+
+        // Copies are the same page repeated
+        let copies = serialize_job(media, &first, 2, 1).unwrap();
+        let repeated = serialize_pages(media, [&first[..], &first[..]], &PrintOptions::default());
+        assert_eq!(copies, repeated.unwrap());
+
+        // Chained jobs don't cut after the last page
+        let chained = PrintOptions {
+            chain: true,
+            ..PrintOptions::default()
+        };
+        let job = serialize_pages(media, [&first[..]], &chained).unwrap();
+        assert_eq!(job[HEADER_LENGTH + 24], 0);
+    }
+
+    #[test]
+    fn fits_lines_to_die_cut_labels() {
+        let lines = page(4, 0xFF);
+        let continuous = label_data(62, None).unwrap();
+        let fitted = fit_lines(Cow::Borrowed(&lines[..]), &continuous).unwrap();
+        assert!(matches!(fitted, Cow::Borrowed(_)));
+
+        let die_cut = label_data(62, Some(29)).unwrap();
+        let length = die_cut.dots_printable.1 as usize;
+        let fitted = fit_lines(Cow::Borrowed(&lines[..]), &die_cut).unwrap();
+        assert_eq!(fitted.len(), length);
+        let leading = (length - 4) / 2;
+        let printed: Vec<_> = (0..length).filter(|&i| fitted[i][0] == 0xFF).collect();
+        assert_eq!(printed, (leading..leading + 4).collect::<Vec<_>>());
+
+        let exact = page(length, 0xFF);
+        let fitted = fit_lines(Cow::Borrowed(&exact[..]), &die_cut).unwrap();
+        assert!(matches!(fitted, Cow::Borrowed(_)));
+        let long = page(length + 1, 0xFF);
+        assert!(matches!(
+            fit_lines(Cow::Borrowed(&long[..]), &die_cut),
+            Err(PrinterError::ContentTooLong(..))
+        ));
+    }
+}
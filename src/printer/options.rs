@@ -0,0 +1,279 @@
+//! Options for cutting and printing a job, and the models that support them
+
+use std::borrow::Cow;
+use std::convert::TryFrom;
+
+use super::constants::Label;
+use super::PrinterError;
+use crate::units::Length;
+
+/// Smallest feed margin on continuous tape, in dots (3mm). From "ESC i d: Specify margin amount" in the
+/// [QL-800 Series Raster Command Reference](https://download.brother.com/welcome/docp100278/cv_ql800_eng_raster_101.pdf),
+/// which gives 35 to 1000 dots for continuous length tape.
+pub const MIN_FEED_MARGIN: u16 = 35;
+/// Largest feed margin on continuous tape, in dots (85mm), from the same command
+pub const MAX_FEED_MARGIN: u16 = 1000;
+
+/// Whether the printer cuts the tape between labels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CutMode {
+    /// Cut after every `cut_each` labels. Printers without a cutter leave the tape uncut if `cut_each` is 1, the
+    /// default, and reject other intervals.
+    #[default]
+    Auto,
+    /// Leave the tape uncut, to be torn or cut by hand
+    Off,
+}
+
+/// What the printer optimizes for when printing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    #[default]
+    Quality,
+    Speed,
+}
+
+/// How a job is cut and printed. Check the options against a printer with `validate` before printing;
+/// `ThermalPrinter` does this for every job.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrintOptions {
+    pub cut: CutMode,
+    /// Number of labels between cuts, from 1 to 255
+    pub cut_each: u8,
+    /// Chain printing: don't feed and cut after the last label, so that the next job starts without a blank
+    /// margin. The last label comes out when the next job is printed.
+    pub chain: bool,
+    pub priority: Priority,
+    /// Blank tape fed before and after each label on continuous tape, from `MIN_FEED_MARGIN` to `MAX_FEED_MARGIN`
+    /// dots. `None` uses the label's default margin.
+    pub feed_margin: Option<Length>,
+    /// Remove blank raster lines from the start and end of each label
+    pub trim_blank: bool,
+    /// Blank space to add to the start and end of each label, after trimming
+    pub padding: (Length, Length),
+}
+impl Default for PrintOptions {
+    fn default() -> Self {
+        Self {
+            cut: CutMode::Auto,
+            cut_each: 1,
+            chain: false,
+            priority: Priority::Quality,
+            feed_margin: None,
+            trim_blank: false,
+            padding: (Length::dots(0), Length::dots(0)),
+        }
+    }
+}
+impl PrintOptions {
+    /// Options that cut after every `cut_each` labels, with everything else left at its default
+    pub fn cut_each(cut_each: u8) -> Self {
+        Self {
+            cut_each,
+            ..Self::default()
+        }
+    }
+    /// Checks that the options are valid and supported by `model`, e.g. "QL-700". Models that aren't in the
+    /// capability table are assumed to support everything.
+    pub fn validate(&self, model: &str) -> Result<(), PrinterError> {
+        if self.cut == CutMode::Auto && self.cut_each == 0 {
+            return Err(PrinterError::InvalidOptions(
+                "cut interval must be at least 1".into(),
+            ));
+        }
+        let capabilities = match Capabilities::for_model(model) {
+            Some(capabilities) => capabilities,
+            None => return Ok(()),
+        };
+        // The default of cutting after every label is accepted everywhere, as it always has been
+        if self.cut == CutMode::Auto && self.cut_each != 1 && !capabilities.cutter {
+            return Err(PrinterError::InvalidOptions(format!(
+                "{model} has no automatic cutter to cut every {} labels",
+                self.cut_each
+            )));
+        }
+        if self.chain && !capabilities.chain_printing {
+            return Err(PrinterError::InvalidOptions(format!(
+                "{model} does not support chain printing"
+            )));
+        }
+        Ok(())
+    }
+    /// Checks that the options can be used with the loaded label
+    pub fn validate_media(&self, label: &Label) -> Result<(), PrinterError> {
+        let feed_margin = self.feed_margin.map(Length::to_dots);
+        let margins = u32::from(MIN_FEED_MARGIN)..=u32::from(MAX_FEED_MARGIN);
+        match (feed_margin, label.tape_size.1) {
+            (Some(margin), 0) if !margins.contains(&margin) => Err(PrinterError::InvalidOptions(
+                format!("feed margin must be from {MIN_FEED_MARGIN} to {MAX_FEED_MARGIN} dots, not {margin}"),
+            )),
+            (Some(margin), length) if length > 0 && margin > 0 => Err(
+                PrinterError::InvalidOptions("die-cut labels have no feed margin".into()),
+            ),
+            _ => Ok(()),
+        }
+    }
+    /// Feed margin to use with `label`, in dots. Panics if the margin doesn't fit the margin command, which
+    /// `validate_media` rules out.
+    pub fn feed_margin(&self, label: &Label) -> u16 {
+        match self.feed_margin {
+            Some(margin) => u16::try_from(margin.to_dots()).expect("feed margin is validated"),
+            None => u16::from(label.feed_margin),
+        }
+    }
+    /// Applies `trim_blank` and `padding` to the raster lines of a label. At least one line is kept so that
+    /// blank labels still print.
+    pub fn prepare_lines<'a>(&self, lines: &'a [[u8; 90]]) -> Cow<'a, [[u8; 90]]> {
+        let mut lines = lines;
+        if self.trim_blank {
+            let is_blank = |line: &[u8; 90]| line.iter().all(|&byte| byte == 0);
+            let start = lines.iter().position(|line| !is_blank(line));
+            let end = lines.iter().rposition(|line| !is_blank(line));
+            lines = match (start, end) {
+                (Some(start), Some(end)) => &lines[start..=end],
+                _ => &lines[..lines.len().min(1)],
+            };
+        }
+        let leading = self.padding.0.to_dots() as usize;
+        let trailing = self.padding.1.to_dots() as usize;
+        if leading == 0 && trailing == 0 {
+            return Cow::Borrowed(lines);
+        }
+
+        let mut padded = Vec::with_capacity(leading + lines.len() + trailing);
+        padded.resize(leading, [0; 90]);
+        padded.extend_from_slice(lines);
+        padded.resize(padded.len() + trailing, [0; 90]);
+        Cow::Owned(padded)
+    }
+}
+
+/// Features of a printer model that affect which `PrintOptions` it accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Has an automatic cutter
+    pub cutter: bool,
+    /// Supports turning off the feed and cut after the last label
+    pub chain_printing: bool,
+}
+impl Capabilities {
+    /// Look up a model by name, as reported by `printer_name_from_id` or the USB product string
+    pub fn for_model(model: &str) -> Option<Self> {
+        MODELS
+            .iter()
+            .find(|(name, _)| *name == model)
+            .map(|(_, capabilities)| *capabilities)
+    }
+}
+
+const fn capabilities(cutter: bool, chain_printing: bool) -> Capabilities {
+    Capabilities {
+        cutter,
+        chain_printing,
+    }
+}
+
+const MODELS: [(&str, Capabilities); 10] = [
+    ("QL-500", capabilities(false, false)),
+    ("QL-550", capabilities(true, false)),
+    ("QL-560", capabilities(true, true)),
+    ("QL-570", capabilities(true, true)),
+    ("QL-580N", capabilities(true, true)),
+    ("QL-650TD", capabilities(true, true)),
+    ("QL-700", capabilities(true, true)),
+    ("QL-800", capabilities(true, true)),
+    ("QL-1050", capabilities(true, true)),
+    ("QL-1060N", capabilities(true, true)),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::printer::constants;
+
+    fn invalid(result: Result<(), PrinterError>) -> bool {
+        matches!(result, Err(PrinterError::InvalidOptions(_)))
+    }
+
+    #[test]
+    fn validates_against_model() {
+        let defaults = PrintOptions::default();
+        for model in ["QL-500", "QL-550", "QL-700", "QL-9000"] {
+            assert!(defaults.validate(model).is_ok(), "{}", model);
+        }
+
+        let every_other = PrintOptions::cut_each(2);
+        assert!(invalid(every_other.validate("QL-500")));
+        assert!(every_other.validate("QL-700").is_ok());
+        assert!(invalid(PrintOptions::cut_each(0).validate("QL-700")));
+        let uncut = PrintOptions {
+            cut: CutMode::Off,
+            cut_each: 0,
+            ..PrintOptions::default()
+        };
+        assert!(uncut.validate("QL-500").is_ok());
+
+        let chain = PrintOptions {
+            chain: true,
+            ..PrintOptions::default()
+        };
+        assert!(invalid(chain.validate("QL-550")));
+        assert!(chain.validate("QL-800").is_ok());
+        assert!(chain.validate("QL-9000").is_ok());
+    }
+
+    #[test]
+    fn validates_feed_margin() {
+        let continuous = constants::label_data(62, None).unwrap();
+        let die_cut = constants::label_data(62, Some(29)).unwrap();
+        let margin = |margin: Length| PrintOptions {
+            feed_margin: Some(margin),
+            ..PrintOptions::default()
+        };
+
+        assert!(margin(Length::mm(10.0)).validate_media(&continuous).is_ok());
+        assert!(margin(Length::dots(35)).validate_media(&continuous).is_ok());
+        assert!(margin(Length::dots(1000))
+            .validate_media(&continuous)
+            .is_ok());
+        assert!(margin(Length::dots(0)).validate_media(&die_cut).is_ok());
+        assert!(invalid(margin(Length::mm(10.0)).validate_media(&die_cut)));
+        for dots in [0, 34, 1001, 70_000] {
+            assert!(
+                invalid(margin(Length::dots(dots)).validate_media(&continuous)),
+                "{}",
+                dots
+            );
+        }
+
+        assert_eq!(PrintOptions::default().feed_margin(&continuous), 35);
+        assert_eq!(margin(Length::dots(100)).feed_margin(&continuous), 100);
+    }
+
+    #[test]
+    fn trims_and_pads_lines() {
+        let mut line = [0; 90];
+        line[10] = 0xFF;
+        let lines = [[0; 90], line, [0; 90], line, [0; 90], [0; 90]];
+
+        assert_eq!(PrintOptions::default().prepare_lines(&lines).len(), 6);
+        let trimmed = PrintOptions {
+            trim_blank: true,
+            ..PrintOptions::default()
+        };
+        assert_eq!(&*trimmed.prepare_lines(&lines), &lines[1..4]);
+        assert_eq!(trimmed.prepare_lines(&[[0; 90]; 5]).len(), 1);
+
+        let padded = PrintOptions {
+            padding: (Length::dots(2), Length::dots(1)),
+            ..trimmed
+        };
+        let prepared = padded.prepare_lines(&lines);
+        assert_eq!(prepared.len(), 6);
+        assert_eq!(&prepared[2..5], &lines[1..4]);
+        assert!(prepared[..2]
+            .iter()
+            .chain(&prepared[5..])
+            .all(|l| *l == [0; 90]));
+    }
+}
@@ -21,6 +21,18 @@ pub struct Media {
     pub length: u8,
 }
 impl Media {
+    /// The media a printer reports when the given label is loaded
+    pub fn from_label(label: &Label) -> Self {
+        Self {
+            media_type: if label.tape_size.1 == 0 {
+                MediaType::ContinuousTape
+            } else {
+                MediaType::DieCutLabels
+            },
+            width: label.tape_size.0 as u8,
+            length: label.tape_size.1 as u8,
+        }
+    }
     pub fn to_label(&self) -> Label {
         let length = if self.length == 0 {
             None
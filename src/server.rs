@@ -0,0 +1,719 @@
+//! A small HTTP API for printing from anything that can make HTTP requests
+//!
+//! Endpoints:
+//! * `GET /printers`: attached printers
+//! * `GET /printers/{serial}/status`: decoded status of a printer
+//! * `POST /printers/{serial}/print`: queue a print job. The body is either a PNG/JPEG image (with optional
+//!   `orientation`, `dither` and `copies` query parameters) or a JSON label description. Returns the job ID.
+//!   Copies are limited to `MAX_COPIES`.
+//! * `GET /printers/{serial}/jobs`: all jobs queued on a printer
+//! * `GET /printers/{serial}/jobs/{id}`: status of a single job
+//!
+//! Jobs are spooled to disk with a `spool::Queue` per printer and printed in the background, so they survive
+//! restarts of the server. Every response allows cross-origin requests, so that web pages served from elsewhere
+//! can print, and `OPTIONS` preflight requests are answered for every endpoint.
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, TryLockError};
+use std::thread;
+use std::time::Duration;
+
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tiny_http::{Header, Method, Request, Response};
+
+use crate::barcode::{self, BarcodeError};
+use crate::printer::{self, status, Orientation, PrinterError, ThermalPrinter};
+use crate::spool::{Job, JobOptions, JobStatus, Queue, SpoolError, Spooler};
+
+#[derive(Error, Debug)]
+pub enum ServerError {
+    #[error("io: {0}")]
+    Io(#[from] io::Error),
+    #[error("http: {0}")]
+    Http(Box<dyn std::error::Error + Send + Sync>),
+    #[error("printer: {0}")]
+    Printer(#[from] PrinterError),
+    #[error("spool: {0}")]
+    Spool(#[from] SpoolError),
+}
+
+type Result<T> = std::result::Result<T, ServerError>;
+
+/// Largest request body accepted, to keep a single upload from exhausting memory
+const MAX_BODY_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Most copies of a label a single request can print
+const MAX_COPIES: usize = 100;
+
+/// How often idle printers check their queue for new jobs
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Number of requests handled at once. Further requests wait for a worker to become free.
+const WORKERS: usize = 8;
+
+/// Headers sent with every response so that browsers allow requests from pages on other origins
+const CORS_HEADERS: [(&str, &str); 3] = [
+    ("Access-Control-Allow-Origin", "*"),
+    ("Access-Control-Allow-Methods", "GET, POST, OPTIONS"),
+    ("Access-Control-Allow-Headers", "Content-Type"),
+];
+
+type Printer = Mutex<ThermalPrinter<rusb::GlobalContext>>;
+
+struct PrinterHandle {
+    info: PrinterInfo,
+    printer: Arc<dyn StatusSource>,
+    spooler: Arc<Spooler>,
+}
+
+/// Where the status of a printer comes from, so that routes don't need a printer attached in tests
+trait StatusSource: Send + Sync {
+    /// Current status of the printer, without waiting for a job to finish printing
+    fn status(&self) -> std::result::Result<status::Response, HttpError>;
+}
+impl StatusSource for Printer {
+    fn status(&self) -> std::result::Result<status::Response, HttpError> {
+        let printer = match self.try_lock() {
+            Ok(printer) => printer,
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(TryLockError::WouldBlock) => return Err(HttpError::new(503, "printer is busy")),
+        };
+        Ok(printer.get_status()?)
+    }
+}
+
+/// HTTP print server for all printers attached when it is created
+pub struct Server {
+    http: tiny_http::Server,
+    printers: HashMap<String, PrinterHandle>,
+}
+impl Server {
+    /// Opens all attached printers, each with a spool directory named after its serial number in `spool_dir`,
+    /// starts printing any jobs left in those queues, and listens for HTTP requests on `addr`.
+    pub fn new<A: ToSocketAddrs, P: Into<PathBuf>>(addr: A, spool_dir: P) -> Result<Self> {
+        let spool_dir = spool_dir.into();
+
+        let mut printers = HashMap::new();
+        for device in printer::printers() {
+            let printer = ThermalPrinter::new(device)?;
+            let serial_number = printer.serial_number.clone();
+            let queue = Queue::open(spool_dir.join(&serial_number))?;
+            let info = PrinterInfo {
+                serial_number: serial_number.clone(),
+                manufacturer: printer.manufacturer.clone(),
+                model: printer.model.clone(),
+            };
+            let printer = Arc::new(Mutex::new(printer));
+            let spooler = Arc::new(Spooler::new(queue));
+            start_worker(&spooler, Arc::clone(&printer), serial_number.clone());
+            let handle = PrinterHandle {
+                info,
+                printer,
+                spooler,
+            };
+            printers.insert(serial_number, handle);
+        }
+
+        Ok(Self {
+            http: tiny_http::Server::http(addr).map_err(ServerError::Http)?,
+            printers,
+        })
+    }
+
+    /// Serve requests until the listening socket fails, handling up to `WORKERS` requests at once
+    pub fn run(&self) -> Result<()> {
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..WORKERS).map(|_| scope.spawn(|| self.serve())).collect();
+            workers
+                .into_iter()
+                .try_for_each(|worker| worker.join().expect("request workers don't panic"))
+        })
+    }
+
+    fn serve(&self) -> Result<()> {
+        loop {
+            let mut request = self.http.recv()?;
+            let response = respond(&self.printers, &mut request);
+            if let Err(e) = request.respond(response) {
+                eprintln!("failed to send response: {e}");
+            }
+        }
+    }
+}
+
+fn start_worker(spooler: &Arc<Spooler>, printer: Arc<Printer>, serial_number: String) {
+    let worker = Arc::clone(spooler).spawn(printer, POLL_INTERVAL);
+    thread::spawn(move || {
+        if let Ok(Err(e)) = worker.join() {
+            eprintln!("stopped printing on {serial_number}: {e}");
+        }
+    });
+}
+
+/// An error response
+#[derive(Debug)]
+struct HttpError {
+    status: u16,
+    message: String,
+}
+impl HttpError {
+    fn new<S: Into<String>>(status: u16, message: S) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+impl From<PrinterError> for HttpError {
+    fn from(e: PrinterError) -> Self {
+        let status = if e.is_recoverable() { 503 } else { 500 };
+        Self::new(status, e.to_string())
+    }
+}
+impl From<SpoolError> for HttpError {
+    fn from(e: SpoolError) -> Self {
+        match e {
+            SpoolError::NotFound(_) => Self::new(404, e.to_string()),
+            e => Self::new(500, e.to_string()),
+        }
+    }
+}
+impl From<BarcodeError> for HttpError {
+    fn from(e: BarcodeError) -> Self {
+        Self::new(422, e.to_string())
+    }
+}
+
+type HttpResult = std::result::Result<(u16, serde_json::Value), HttpError>;
+
+/// Handles a request, with the CORS headers added to whatever the response is
+fn respond(
+    printers: &HashMap<String, PrinterHandle>,
+    request: &mut Request,
+) -> Response<io::Cursor<Vec<u8>>> {
+    let response = match route(printers, request) {
+        Ok((status, body)) => json_response(status, &body),
+        Err(e) => json_response(e.status, &serde_json::json!({ "error": e.message })),
+    };
+    CORS_HEADERS
+        .iter()
+        .fold(response, |response, &(field, value)| {
+            response.with_header(Header::from_bytes(field, value).unwrap())
+        })
+}
+
+fn route(printers: &HashMap<String, PrinterHandle>, request: &mut Request) -> HttpResult {
+    let url = request.url().to_owned();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    let printer = |serial: &str| {
+        printers
+            .get(serial)
+            .ok_or_else(|| HttpError::new(404, format!("no printer with serial number {serial}")))
+    };
+
+    match (request.method(), segments.as_slice()) {
+        (Method::Get, ["printers"]) => {
+            let list: Vec<&PrinterInfo> = printers.values().map(|h| &h.info).collect();
+            ok(&list)
+        }
+        (Method::Get, ["printers", serial, "status"]) => {
+            ok(&StatusInfo::from(printer(serial)?.printer.status()?))
+        }
+        (Method::Post, ["printers", serial, "print"]) => {
+            let handle = printer(serial)?;
+            let (image, options) = parse_print_request(request, query)?;
+            let id = handle.spooler.queue().submit(&image, options)?;
+            let job = handle.spooler.queue().job(id)?;
+            Ok((202, to_json(&JobInfo::from(job))))
+        }
+        (Method::Get, ["printers", serial, "jobs"]) => {
+            let handle = printer(serial)?;
+            let jobs: Vec<JobInfo> = handle
+                .spooler
+                .queue()
+                .jobs()?
+                .into_iter()
+                .map(JobInfo::from)
+                .collect();
+            ok(&jobs)
+        }
+        (Method::Get, ["printers", serial, "jobs", id]) => {
+            let handle = printer(serial)?;
+            let id = id
+                .parse()
+                .map_err(|_| HttpError::new(400, format!("invalid job id {id:?}")))?;
+            ok(&JobInfo::from(handle.spooler.queue().job(id)?))
+        }
+        // Preflight requests only need the CORS headers
+        (Method::Options, segments) if is_endpoint(segments) => Ok((204, serde_json::Value::Null)),
+        (_, segments) if is_endpoint(segments) => Err(HttpError::new(405, "method not allowed")),
+        _ => Err(HttpError::new(404, "not found")),
+    }
+}
+
+/// Whether a path names an endpoint, whichever methods it allows
+fn is_endpoint(segments: &[&str]) -> bool {
+    matches!(
+        segments,
+        ["printers"] | ["printers", _, "status" | "print" | "jobs"] | ["printers", _, "jobs", _]
+    )
+}
+
+fn ok<T: Serialize>(body: &T) -> HttpResult {
+    Ok((200, to_json(body)))
+}
+
+fn to_json<T: Serialize>(body: &T) -> serde_json::Value {
+    serde_json::to_value(body).expect("response types always serialize")
+}
+
+/// A response with `body` as JSON, or without a body if it is null
+fn json_response(status: u16, body: &serde_json::Value) -> Response<io::Cursor<Vec<u8>>> {
+    if body.is_null() {
+        return Response::from_data(Vec::new()).with_status_code(status);
+    }
+    let header = Header::from_bytes("Content-Type", "application/json").unwrap();
+    Response::from_data(body.to_string())
+        .with_status_code(status)
+        .with_header(header)
+}
+
+/// Read the image and options of a print request, either from an uploaded image and query parameters, or from
+/// a JSON label description.
+fn parse_print_request(
+    request: &mut Request,
+    query: &str,
+) -> std::result::Result<(DynamicImage, JobOptions), HttpError> {
+    let content_type = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Content-Type"))
+        .map(|h| h.value.as_str().to_ascii_lowercase());
+
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_BODY_SIZE + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| HttpError::new(400, e.to_string()))?;
+    if body.len() as u64 > MAX_BODY_SIZE {
+        return Err(HttpError::new(413, "request body too large"));
+    }
+
+    if content_type.is_some_and(|c| c.starts_with("application/json")) {
+        let request: PrintRequest =
+            serde_json::from_slice(&body).map_err(|e| HttpError::new(400, e.to_string()))?;
+        let mut options = JobOptions {
+            copies: request.copies.clamp(1, MAX_COPIES),
+            ..JobOptions::default()
+        };
+        options.image.orientation = request.orientation.into();
+        options.image.dither = request.dither.into();
+        return Ok((request.label.render()?, options));
+    }
+
+    let image = image::load_from_memory(&body).map_err(|e| HttpError::new(415, e.to_string()))?;
+    let mut options = JobOptions::default();
+    for (key, value) in query.split('&').filter_map(|p| p.split_once('=')) {
+        let (key, value) = match (decode_query(key), decode_query(value)) {
+            (Some(key), Some(value)) => (key, value),
+            _ => {
+                return Err(HttpError::new(
+                    400,
+                    format!("invalid query parameter {key}={value}"),
+                ))
+            }
+        };
+        let invalid = || HttpError::new(400, format!("invalid {key} {value:?}"));
+        match key.as_str() {
+            "orientation" => {
+                options.image.orientation = match value.as_str() {
+                    "normal" => Orientation::Normal,
+                    "rotated" => Orientation::Rotated,
+                    "rotated180" => Orientation::Rotated180,
+                    "rotated270" => Orientation::Rotated270,
+                    "auto" => Orientation::Auto,
+                    _ => return Err(invalid()),
+                }
+            }
+            "dither" => {
+                let dither: bool = value.parse().map_err(|_| invalid())?;
+                options.image.dither = dither.into();
+            }
+            "copies" => {
+                let copies: usize = value.parse().map_err(|_| invalid())?;
+                options.copies = copies.clamp(1, MAX_COPIES);
+            }
+            _ => (),
+        }
+    }
+    Ok((image, options))
+}
+
+/// Decodes a percent-encoded query string key or value, with `+` for spaces
+fn decode_query(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut chars = s.bytes();
+    while let Some(byte) = chars.next() {
+        bytes.push(match byte {
+            b'+' => b' ',
+            b'%' => {
+                let mut digit = || char::from(chars.next()?).to_digit(16);
+                (digit()? * 16 + digit()?) as u8
+            }
+            byte => byte,
+        });
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[derive(Serialize, Clone)]
+struct PrinterInfo {
+    serial_number: String,
+    manufacturer: String,
+    model: String,
+}
+
+#[derive(Serialize)]
+struct StatusInfo {
+    model: &'static str,
+    status_type: String,
+    phase_type: String,
+    notification: String,
+    media: MediaInfo,
+    errors: Vec<&'static str>,
+}
+impl From<status::Response> for StatusInfo {
+    fn from(response: status::Response) -> Self {
+        Self {
+            model: response.model,
+            status_type: format!("{:?}", response.status_type),
+            phase_type: format!("{:?}", response.phase_type),
+            notification: format!("{:?}", response.notification),
+            media: MediaInfo {
+                media_type: format!("{:?}", response.media.media_type),
+                width: response.media.width,
+                length: response.media.length,
+            },
+            errors: response.errors,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct MediaInfo {
+    media_type: String,
+    /// Width in mm
+    width: u8,
+    /// Length in mm, 0 for continuous tape
+    length: u8,
+}
+
+#[derive(Serialize)]
+struct JobInfo {
+    id: u64,
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    attempts: u32,
+}
+impl From<Job> for JobInfo {
+    fn from(job: Job) -> Self {
+        let (status, error) = match job.status {
+            JobStatus::Pending => ("pending", None),
+            JobStatus::Printing => ("printing", None),
+            JobStatus::Completed => ("completed", None),
+            JobStatus::Failed(e) => ("failed", Some(e)),
+        };
+        Self {
+            id: job.id,
+            status,
+            error,
+            attempts: job.attempts,
+        }
+    }
+}
+
+/// JSON body of a print request that describes the label instead of uploading an image
+#[derive(Deserialize)]
+struct PrintRequest {
+    #[serde(flatten)]
+    label: LabelDescription,
+    #[serde(default)]
+    orientation: OrientationDescription,
+    #[serde(default)]
+    dither: bool,
+    #[serde(default = "default_copies")]
+    copies: usize,
+}
+
+fn default_copies() -> usize {
+    1
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum OrientationDescription {
+    #[default]
+    Normal,
+    Rotated,
+    Rotated180,
+    Rotated270,
+    Auto,
+}
+impl From<OrientationDescription> for Orientation {
+    fn from(orientation: OrientationDescription) -> Self {
+        match orientation {
+            OrientationDescription::Normal => Orientation::Normal,
+            OrientationDescription::Rotated => Orientation::Rotated,
+            OrientationDescription::Rotated180 => Orientation::Rotated180,
+            OrientationDescription::Rotated270 => Orientation::Rotated270,
+            OrientationDescription::Auto => Orientation::Auto,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum LabelDescription {
+    /// EAN-13 barcode of 12 or 13 digits
+    Ean13 { data: String },
+    /// EAN-13 barcode with an encoded SKU and price
+    EncodedPrice { sku: usize, price: f32 },
+    /// Large price barcode with an optional QR code link
+    BarcodeLarge {
+        sku: usize,
+        price: f32,
+        link: Option<String>,
+    },
+}
+impl LabelDescription {
+    fn render(self) -> std::result::Result<DynamicImage, BarcodeError> {
+        let image = match self {
+            LabelDescription::Ean13 { data } => barcode::generate_ean13_barcode(
+                barcode::EAN13Data::Simple(data),
+                String::new(),
+                String::new(),
+                None,
+            )?,
+            LabelDescription::EncodedPrice { sku, price } => barcode::generate_ean13_barcode(
+                barcode::EAN13Data::EncodedPrice { sku, price },
+                String::new(),
+                String::new(),
+                None,
+            )?,
+            LabelDescription::BarcodeLarge { sku, price, link } => {
+                barcode::generate_barcode_large(sku, price, String::new(), String::new(), link)?
+            }
+        };
+        Ok(DynamicImage::ImageRgba8(image))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tiny_http::TestRequest;
+
+    use super::*;
+    use crate::printer::status::{MediaType, Notification, PhaseType, StatusType};
+
+    /// A spool directory that is removed when the test ends
+    struct TempDir(PathBuf);
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir()
+                .join(format!("brother-ql-server-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            Self(dir)
+        }
+    }
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A printer with 62mm continuous tape loaded
+    struct Idle;
+    impl StatusSource for Idle {
+        fn status(&self) -> std::result::Result<status::Response, HttpError> {
+            Ok(status::Response {
+                model: "QL-800",
+                status_type: StatusType::ReplyToStatusRequest,
+                errors: Vec::new(),
+                phase_type: PhaseType::WaitingToReceive,
+                notification: Notification::NotAvailable,
+                media: status::Media {
+                    media_type: MediaType::ContinuousTape,
+                    width: 62,
+                    length: 0,
+                },
+            })
+        }
+    }
+
+    fn printers(dir: &TempDir) -> HashMap<String, PrinterHandle> {
+        let handle = PrinterHandle {
+            info: PrinterInfo {
+                serial_number: "000A1B2C3D".into(),
+                manufacturer: "Brother".into(),
+                model: "QL-800".into(),
+            },
+            printer: Arc::new(Idle),
+            spooler: Arc::new(Spooler::new(Queue::open(&dir.0).unwrap())),
+        };
+        HashMap::from([("000A1B2C3D".to_owned(), handle)])
+    }
+
+    fn json_request(method: Method, path: &str, body: &'static str) -> Request {
+        let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+        TestRequest::new()
+            .with_method(method)
+            .with_path(path)
+            .with_header(content_type)
+            .with_body(body)
+            .into()
+    }
+
+    fn get(printers: &HashMap<String, PrinterHandle>, path: &str) -> HttpResult {
+        route(printers, &mut json_request(Method::Get, path, ""))
+    }
+
+    fn status(result: HttpResult) -> u16 {
+        match result {
+            Ok((status, _)) => status,
+            Err(e) => e.status,
+        }
+    }
+
+    #[test]
+    fn routes_requests() {
+        let dir = TempDir::new("routes");
+        let printers = printers(&dir);
+
+        let (status_code, body) = get(&printers, "/printers").unwrap();
+        assert_eq!(status_code, 200);
+        assert_eq!(body[0]["serial_number"], "000A1B2C3D");
+
+        let (status_code, body) = get(&printers, "/printers/000A1B2C3D/status").unwrap();
+        assert_eq!(status_code, 200);
+        assert_eq!(body["model"], "QL-800");
+        assert_eq!(body["media"]["width"], 62);
+
+        let (status_code, body) = get(&printers, "/printers/000A1B2C3D/jobs?all").unwrap();
+        assert_eq!(status_code, 200);
+        assert_eq!(body, serde_json::json!([]));
+
+        assert_eq!(status(get(&printers, "/printers/XYZ/status")), 404);
+        assert_eq!(status(get(&printers, "/printers/000A1B2C3D/jobs/7")), 404);
+        assert_eq!(
+            status(get(&printers, "/printers/000A1B2C3D/jobs/seven")),
+            400
+        );
+        assert_eq!(status(get(&printers, "/printers/000A1B2C3D/print")), 405);
+        assert_eq!(status(get(&printers, "/scanners")), 404);
+        let mut delete = json_request(Method::Delete, "/printers", "");
+        assert_eq!(status(route(&printers, &mut delete)), 405);
+    }
+
+    #[test]
+    fn allows_cross_origin_requests() {
+        let dir = TempDir::new("cors");
+        let printers = printers(&dir);
+        let header = |response: &Response<_>, field: &'static str| {
+            response
+                .headers()
+                .iter()
+                .find(|h| h.field.equiv(field))
+                .map(|h| h.value.to_string())
+        };
+
+        let mut preflight = json_request(Method::Options, "/printers/000A1B2C3D/print", "");
+        let response = respond(&printers, &mut preflight);
+        assert_eq!(response.status_code().0, 204);
+        assert_eq!(header(&response, "Content-Type"), None);
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Origin").as_deref(),
+            Some("*")
+        );
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Methods").as_deref(),
+            Some("GET, POST, OPTIONS")
+        );
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Headers").as_deref(),
+            Some("Content-Type")
+        );
+
+        // Errors need the headers too, or browsers hide them from the page
+        let mut missing = json_request(Method::Get, "/printers/XYZ/status", "");
+        let response = respond(&printers, &mut missing);
+        assert_eq!(response.status_code().0, 404);
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Origin").as_deref(),
+            Some("*")
+        );
+
+        let mut unknown = json_request(Method::Options, "/scanners", "");
+        assert_eq!(respond(&printers, &mut unknown).status_code().0, 404);
+    }
+
+    #[test]
+    fn submits_jobs() {
+        let dir = TempDir::new("submit");
+        let printers = printers(&dir);
+        let body = r#"{"type": "ean13", "data": "012345678905", "copies": 500}"#;
+        let mut request = json_request(Method::Post, "/printers/000A1B2C3D/print", body);
+        let (status_code, job) = route(&printers, &mut request).unwrap();
+        assert_eq!(status_code, 202);
+        assert_eq!(job["status"], "pending");
+        assert_eq!(job["attempts"], 0);
+
+        let id = job["id"].as_u64().unwrap();
+        let queue = printers["000A1B2C3D"].spooler.queue();
+        assert_eq!(queue.job(id).unwrap().options.copies, MAX_COPIES);
+        let (status_code, listed) = get(&printers, "/printers/000A1B2C3D/jobs").unwrap();
+        assert_eq!(status_code, 200);
+        assert_eq!(listed, serde_json::json!([job]));
+        let path = format!("/printers/000A1B2C3D/jobs/{id}");
+        assert_eq!(get(&printers, &path).unwrap(), (200, job));
+
+        let invalid = [
+            (r#"{"type": "ean13"}"#, 400),
+            (r#"{"type": "ean13", "data": "12"}"#, 422),
+            ("not json", 400),
+        ];
+        for (body, expected) in invalid {
+            let mut request = json_request(Method::Post, "/printers/000A1B2C3D/print", body);
+            assert_eq!(status(route(&printers, &mut request)), expected, "{body}");
+        }
+        let mut not_an_image = TestRequest::new()
+            .with_method(Method::Post)
+            .with_path("/printers/000A1B2C3D/print")
+            .with_body("GIF89a?")
+            .into();
+        assert_eq!(status(route(&printers, &mut not_an_image)), 415);
+        assert_eq!(queue.jobs().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn decodes_query_strings() {
+        assert_eq!(decode_query("rotated").as_deref(), Some("rotated"));
+        assert_eq!(decode_query("a+b%20c").as_deref(), Some("a b c"));
+        assert_eq!(decode_query("caf%C3%A9").as_deref(), Some("café"));
+        assert_eq!(decode_query("100%25").as_deref(), Some("100%"));
+        assert_eq!(decode_query("%2"), None);
+        assert_eq!(decode_query("%zz"), None);
+        assert_eq!(decode_query("%+1"), None);
+        assert_eq!(decode_query("%FF"), None);
+    }
+}
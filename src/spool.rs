@@ -0,0 +1,914 @@
+//! A durable on-disk print queue for `ThermalPrinter`
+//!
+//! Jobs submitted to a `Queue` are written to a spool directory as PNG images plus a small plain-text job
+//! file, so they survive restarts of the process that is printing them. A `Spooler` takes jobs off the queue
+//! in submission order, retries them while the printer reports recoverable errors (cooling, busy) and records
+//! the final status of every job.
+//!
+//! Spool directory layout:
+//! * `<id>.png`, `<id>-2.png`, ...: the images to print, one label each
+//! * `<id>.job`: options, attempt count and status of the job as `key=value` lines
+//! * `next-id`: identifier of the next job to be submitted
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use image::DynamicImage;
+use thiserror::Error;
+
+use crate::printer::options::{CutMode, Priority};
+use crate::printer::{Orientation, PrintOptions, PrinterError, ThermalPrinter};
+use crate::units::Length;
+use crate::utils::{self, preprocess::Sharpen, Align, Dither, Fit, ImageOptions, Mirror, Overflow};
+
+#[derive(Error, Debug)]
+pub enum SpoolError {
+    #[error("io: {0}")]
+    Io(#[from] io::Error),
+    #[error("image: {0}")]
+    Image(#[from] image::ImageError),
+    #[error("printer: {0}")]
+    Printer(#[from] PrinterError),
+    #[error("no such job: {0}")]
+    NotFound(JobId),
+    #[error("corrupt job file {0}: {1}")]
+    Corrupt(PathBuf, String),
+    #[error("job has no pages")]
+    Empty,
+}
+
+type Result<T> = std::result::Result<T, SpoolError>;
+
+/// Identifier of a spooled job. Identifiers increase monotonically in submission order and are never reused,
+/// even after jobs are removed.
+pub type JobId = u64;
+
+/// How a spooled image should be printed. See `ThermalPrinter::print_image_with_options`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JobOptions {
+    pub image: ImageOptions,
+    pub print: PrintOptions,
+    pub copies: usize,
+}
+impl Default for JobOptions {
+    fn default() -> Self {
+        Self {
+            image: ImageOptions::default(),
+            print: PrintOptions::default(),
+            copies: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobStatus {
+    /// Waiting to be printed
+    Pending,
+    /// Currently being sent to the printer
+    Printing,
+    /// Printed successfully
+    Completed,
+    /// Gave up on the job, with the last error encountered
+    Failed(String),
+}
+impl JobStatus {
+    /// Whether the job has reached a final status and will not be printed (again).
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobStatus::Completed | JobStatus::Failed(_))
+    }
+}
+
+/// A job as recorded in the spool directory
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub id: JobId,
+    pub options: JobOptions,
+    pub status: JobStatus,
+    /// Number of times the spooler has tried to print this job
+    pub attempts: u32,
+    /// Number of images in the job, printed in order as one label each
+    pub pages: usize,
+    /// Number of labels already printed, counting each copy. Attempts after an error start from the next label.
+    pub printed: usize,
+}
+impl Job {
+    fn serialize(&self) -> String {
+        let (status, error) = match &self.status {
+            JobStatus::Pending => ("pending", ""),
+            JobStatus::Printing => ("printing", ""),
+            JobStatus::Completed => ("completed", ""),
+            JobStatus::Failed(e) => ("failed", e.as_str()),
+        };
+        let JobOptions {
+            image,
+            print,
+            copies,
+        } = &self.options;
+        let optional = |length: Option<Length>| length.map_or(String::new(), |l| l.to_string());
+        let fields = [
+            ("orientation", image.orientation.keyword().to_owned()),
+            ("mirror", image.mirror.keyword().to_owned()),
+            ("dither", image.dither.keyword().to_owned()),
+            ("fit", image.placement.fit.keyword().to_owned()),
+            ("align", image.placement.align.keyword().to_owned()),
+            (
+                "vertical_align",
+                image.placement.vertical_align.keyword().to_owned(),
+            ),
+            ("length", optional(image.placement.length)),
+            ("overflow", image.placement.overflow.keyword().to_owned()),
+            ("flatten_alpha", image.preprocess.flatten_alpha.to_string()),
+            ("auto_levels", image.preprocess.auto_levels.to_string()),
+            ("brightness", image.preprocess.brightness.to_string()),
+            ("contrast", image.preprocess.contrast.to_string()),
+            ("gamma", image.preprocess.gamma.to_string()),
+            (
+                "sharpen",
+                image.preprocess.sharpen.map_or(String::new(), |sharpen| {
+                    format!("{},{}", sharpen.sigma, sharpen.threshold)
+                }),
+            ),
+            ("invert", image.preprocess.invert.to_string()),
+            ("threshold", image.preprocess.threshold.to_string()),
+            ("cut", print.cut.keyword().to_owned()),
+            ("cut_each", print.cut_each.to_string()),
+            ("chain", print.chain.to_string()),
+            ("priority", print.priority.keyword().to_owned()),
+            ("feed_margin", optional(print.feed_margin)),
+            ("trim_blank", print.trim_blank.to_string()),
+            (
+                "padding",
+                format!("{},{}", print.padding.0, print.padding.1),
+            ),
+            ("copies", copies.to_string()),
+            ("pages", self.pages.to_string()),
+            ("printed", self.printed.to_string()),
+            ("attempts", self.attempts.to_string()),
+            ("status", status.to_owned()),
+            // Keep the file line-oriented
+            ("error", error.replace('\n', " ")),
+        ];
+        fields
+            .iter()
+            .map(|(key, value)| format!("{key}={value}\n"))
+            .collect()
+    }
+
+    fn deserialize(id: JobId, path: &Path, contents: &str) -> Result<Self> {
+        let corrupt = |message: String| SpoolError::Corrupt(path.to_owned(), message);
+
+        let mut options = JobOptions::default();
+        let mut attempts = 0;
+        let mut pages = 1;
+        let mut printed = 0;
+        let mut status = None;
+        let mut error = String::new();
+        for line in contents.lines().filter(|l| !l.is_empty()) {
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| corrupt(format!("invalid line {line:?}")))?;
+            match key {
+                "status" => status = Some(value.to_owned()),
+                "error" => error = value.to_owned(),
+                "attempts" => {
+                    attempts = value
+                        .parse()
+                        .map_err(|_| corrupt(format!("invalid attempts {value:?}")))?
+                }
+                "pages" => {
+                    pages = value
+                        .parse()
+                        .map_err(|_| corrupt(format!("invalid pages {value:?}")))?
+                }
+                "printed" => {
+                    printed = value
+                        .parse()
+                        .map_err(|_| corrupt(format!("invalid printed {value:?}")))?
+                }
+                key => set_option(&mut options, key, value)
+                    .ok_or_else(|| corrupt(format!("invalid {key} {value:?}")))?,
+            }
+        }
+        let status = match status.as_deref() {
+            Some("pending") => JobStatus::Pending,
+            Some("printing") => JobStatus::Printing,
+            Some("completed") => JobStatus::Completed,
+            Some("failed") => JobStatus::Failed(error),
+            Some(s) => return Err(corrupt(format!("invalid status {s:?}"))),
+            None => return Err(corrupt("missing status".into())),
+        };
+
+        Ok(Self {
+            id,
+            options,
+            status,
+            attempts,
+            pages,
+            printed,
+        })
+    }
+}
+
+/// Sets the option stored in a job file under `key`, or returns `None` if `value` is invalid
+fn set_option(options: &mut JobOptions, key: &str, value: &str) -> Option<()> {
+    fn optional<T: std::str::FromStr>(value: &str) -> Option<Option<T>> {
+        match value {
+            "" => Some(None),
+            value => value.parse().ok().map(Some),
+        }
+    }
+
+    let image = &mut options.image;
+    let print = &mut options.print;
+    match key {
+        "orientation" => image.orientation = Keyword::from_keyword(value)?,
+        "mirror" => image.mirror = Keyword::from_keyword(value)?,
+        "dither" => image.dither = Keyword::from_keyword(value)?,
+        "fit" => image.placement.fit = Keyword::from_keyword(value)?,
+        "align" => image.placement.align = Keyword::from_keyword(value)?,
+        "vertical_align" => image.placement.vertical_align = Keyword::from_keyword(value)?,
+        "length" => image.placement.length = optional(value)?,
+        "overflow" => image.placement.overflow = Keyword::from_keyword(value)?,
+        "flatten_alpha" => image.preprocess.flatten_alpha = value.parse().ok()?,
+        "auto_levels" => image.preprocess.auto_levels = value.parse().ok()?,
+        "brightness" => image.preprocess.brightness = value.parse().ok()?,
+        "contrast" => image.preprocess.contrast = value.parse().ok()?,
+        "gamma" => image.preprocess.gamma = value.parse().ok()?,
+        "sharpen" => {
+            image.preprocess.sharpen = match value.split_once(',') {
+                Some((sigma, threshold)) => Some(Sharpen {
+                    sigma: sigma.parse().ok()?,
+                    threshold: threshold.parse().ok()?,
+                }),
+                None if value.is_empty() => None,
+                None => return None,
+            }
+        }
+        "invert" => image.preprocess.invert = value.parse().ok()?,
+        "threshold" => image.preprocess.threshold = value.parse().ok()?,
+        "cut" => print.cut = Keyword::from_keyword(value)?,
+        "cut_each" => print.cut_each = value.parse().ok()?,
+        "chain" => print.chain = value.parse().ok()?,
+        "priority" => print.priority = Keyword::from_keyword(value)?,
+        "feed_margin" => print.feed_margin = optional(value)?,
+        "trim_blank" => print.trim_blank = value.parse().ok()?,
+        "padding" => {
+            let (start, end) = value.split_once(',')?;
+            print.padding = (start.parse().ok()?, end.parse().ok()?);
+        }
+        "copies" => options.copies = value.parse().ok()?,
+        // Ignore unknown keys so that newer job files can still be read
+        _ => (),
+    }
+    Some(())
+}
+
+/// Options stored in job files by name
+trait Keyword: Copy + PartialEq + 'static {
+    const KEYWORDS: &'static [(&'static str, Self)];
+
+    fn keyword(self) -> &'static str {
+        Self::KEYWORDS
+            .iter()
+            .find(|(_, value)| *value == self)
+            .map(|(keyword, _)| *keyword)
+            .expect("every value has a keyword")
+    }
+    fn from_keyword(keyword: &str) -> Option<Self> {
+        Self::KEYWORDS
+            .iter()
+            .find(|(k, _)| *k == keyword)
+            .map(|(_, value)| *value)
+    }
+}
+impl Keyword for Orientation {
+    const KEYWORDS: &'static [(&'static str, Self)] = &[
+        ("normal", Orientation::Normal),
+        ("rotated", Orientation::Rotated),
+        ("rotated180", Orientation::Rotated180),
+        ("rotated270", Orientation::Rotated270),
+        ("auto", Orientation::Auto),
+    ];
+}
+impl Keyword for Mirror {
+    const KEYWORDS: &'static [(&'static str, Self)] = &[
+        ("none", Mirror::None),
+        ("horizontal", Mirror::Horizontal),
+        ("vertical", Mirror::Vertical),
+    ];
+}
+impl Keyword for Dither {
+    const KEYWORDS: &'static [(&'static str, Self)] = &[
+        ("threshold", Dither::Threshold),
+        ("floyd_steinberg", Dither::FloydSteinberg),
+        ("atkinson", Dither::Atkinson),
+        ("jarvis_judice_ninke", Dither::JarvisJudiceNinke),
+        ("stucki", Dither::Stucki),
+        ("sierra", Dither::Sierra),
+        ("bayer4", Dither::Bayer4),
+        ("bayer8", Dither::Bayer8),
+    ];
+}
+impl Keyword for Fit {
+    const KEYWORDS: &'static [(&'static str, Self)] = &[
+        ("width", Fit::Width),
+        ("contain", Fit::Contain),
+        ("cover", Fit::Cover),
+        ("stretch", Fit::Stretch),
+        ("none", Fit::None),
+    ];
+}
+impl Keyword for Align {
+    const KEYWORDS: &'static [(&'static str, Self)] = &[
+        ("start", Align::Start),
+        ("center", Align::Center),
+        ("end", Align::End),
+    ];
+}
+impl Keyword for Overflow {
+    const KEYWORDS: &'static [(&'static str, Self)] = &[
+        ("shrink", Overflow::Shrink),
+        ("crop", Overflow::Crop),
+        ("error", Overflow::Error),
+    ];
+}
+impl Keyword for CutMode {
+    const KEYWORDS: &'static [(&'static str, Self)] =
+        &[("auto", CutMode::Auto), ("off", CutMode::Off)];
+}
+impl Keyword for Priority {
+    const KEYWORDS: &'static [(&'static str, Self)] =
+        &[("quality", Priority::Quality), ("speed", Priority::Speed)];
+}
+
+/// A print queue persisted in a spool directory.
+///
+/// A `Queue` may be shared between threads, e.g. one thread accepting jobs and another running a `Spooler`.
+/// Only one process should use a given spool directory at a time.
+pub struct Queue {
+    dir: PathBuf,
+    lock: Mutex<()>,
+}
+impl Queue {
+    /// Open (and create if needed) the spool directory at `dir`.
+    ///
+    /// Jobs that were interrupted while printing, e.g. because the process was killed, are put back in the
+    /// queue as pending.
+    pub fn open<P: Into<PathBuf>>(dir: P) -> Result<Self> {
+        let queue = Self {
+            dir: dir.into(),
+            lock: Mutex::new(()),
+        };
+        fs::create_dir_all(&queue.dir)?;
+        if !queue.next_id_path().exists() {
+            let next_id = queue.job_ids()?.last().map_or(1, |id| id + 1);
+            queue.write_next_id(next_id)?;
+        }
+
+        for mut job in queue.jobs()? {
+            if job.status == JobStatus::Printing {
+                job.status = JobStatus::Pending;
+                queue.write_job(&job)?;
+            }
+        }
+        Ok(queue)
+    }
+
+    /// Path of the spool directory
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Add an image to the end of the queue.
+    pub fn submit(&self, image: &DynamicImage, options: JobOptions) -> Result<JobId> {
+        self.submit_pages(std::slice::from_ref(image), options)
+    }
+
+    /// Add a job that prints several images, one label each, to the end of the queue. The pages are printed as
+    /// a single job, with copies collated.
+    pub fn submit_pages(&self, images: &[DynamicImage], options: JobOptions) -> Result<JobId> {
+        if images.is_empty() {
+            return Err(SpoolError::Empty);
+        }
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let id = self.read_next_id()?;
+        self.write_next_id(id + 1)?;
+
+        // Write the images first: a job only exists once its job file does
+        for (page, image) in images.iter().enumerate() {
+            let image_path = self.image_path(id, page);
+            let tmp_path = image_path.with_extension("png.tmp");
+            image.save_with_format(&tmp_path, image::ImageFormat::Png)?;
+            fs::rename(tmp_path, image_path)?;
+        }
+
+        self.write_job(&Job {
+            id,
+            options,
+            status: JobStatus::Pending,
+            attempts: 0,
+            pages: images.len(),
+            printed: 0,
+        })?;
+        Ok(id)
+    }
+
+    /// All jobs in the queue, including finished ones, in submission order.
+    pub fn jobs(&self) -> Result<Vec<Job>> {
+        self.job_ids()?.into_iter().map(|id| self.job(id)).collect()
+    }
+
+    /// Look up a single job.
+    pub fn job(&self, id: JobId) -> Result<Job> {
+        let path = self.job_path(id);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(SpoolError::NotFound(id)),
+            Err(e) => return Err(e.into()),
+        };
+        Job::deserialize(id, &path, &contents)
+    }
+
+    /// The image that will be printed for a job, or its first page.
+    pub fn image(&self, id: JobId) -> Result<DynamicImage> {
+        self.page(id, 0)
+    }
+
+    /// The images that will be printed for a job, one per page.
+    pub fn images(&self, id: JobId) -> Result<Vec<DynamicImage>> {
+        let pages = self.job(id)?.pages;
+        (0..pages).map(|page| self.page(id, page)).collect()
+    }
+
+    fn page(&self, id: JobId, page: usize) -> Result<DynamicImage> {
+        match image::open(self.image_path(id, page)) {
+            Err(image::ImageError::IoError(e)) if e.kind() == io::ErrorKind::NotFound => {
+                Err(SpoolError::NotFound(id))
+            }
+            result => Ok(result?),
+        }
+    }
+
+    /// The oldest job that is still waiting to be printed.
+    pub fn next_pending(&self) -> Result<Option<Job>> {
+        Ok(self
+            .jobs()?
+            .into_iter()
+            .find(|job| job.status == JobStatus::Pending))
+    }
+
+    /// Remove a job and its images from the spool directory.
+    pub fn remove(&self, id: JobId) -> Result<()> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        // Remove at least the first image of a corrupt job
+        let pages = self.job(id).map_or(1, |job| job.pages);
+        match fs::remove_file(self.job_path(id)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(SpoolError::NotFound(id)),
+            result => result?,
+        }
+        for page in 0..pages {
+            match fs::remove_file(self.image_path(id, page)) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove all completed and failed jobs from the spool directory.
+    pub fn purge_finished(&self) -> Result<()> {
+        for job in self.jobs()? {
+            if job.status.is_finished() {
+                self.remove(job.id)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn job_ids(&self) -> Result<Vec<JobId>> {
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("job") {
+                continue;
+            }
+            if let Some(id) = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse().ok())
+            {
+                ids.push(id);
+            }
+        }
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    fn read_next_id(&self) -> Result<JobId> {
+        let path = self.next_id_path();
+        let contents = fs::read_to_string(&path)?;
+        contents
+            .trim()
+            .parse()
+            .map_err(|_| SpoolError::Corrupt(path, format!("invalid next id {contents:?}")))
+    }
+
+    fn write_next_id(&self, id: JobId) -> Result<()> {
+        let path = self.next_id_path();
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, format!("{id}\n"))?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    fn next_id_path(&self) -> PathBuf {
+        self.dir.join("next-id")
+    }
+
+    fn write_job(&self, job: &Job) -> Result<()> {
+        // Write then rename so that a crash never leaves a half-written job file behind
+        let path = self.job_path(job.id);
+        let tmp_path = path.with_extension("job.tmp");
+        fs::write(&tmp_path, job.serialize())?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    fn job_path(&self, id: JobId) -> PathBuf {
+        self.dir.join(format!("{id:020}.job"))
+    }
+
+    /// Path of the image of a page, counting from 0
+    fn image_path(&self, id: JobId, page: usize) -> PathBuf {
+        match page {
+            0 => self.dir.join(format!("{id:020}.png")),
+            page => self.dir.join(format!("{id:020}-{}.png", page + 1)),
+        }
+    }
+}
+
+/// Prints jobs from a `Queue` in order.
+pub struct Spooler {
+    queue: Queue,
+    /// How many times a job is tried before it is marked as failed
+    pub max_attempts: u32,
+    /// How long to wait before retrying a job that failed with a recoverable error
+    pub retry_delay: Duration,
+}
+impl Spooler {
+    pub fn new(queue: Queue) -> Self {
+        Self {
+            queue,
+            max_attempts: 10,
+            retry_delay: Duration::from_secs(5),
+        }
+    }
+
+    pub fn queue(&self) -> &Queue {
+        &self.queue
+    }
+
+    /// Print the oldest pending job, if any, and return it with its final status.
+    ///
+    /// Recoverable printer errors (see `PrinterError::is_recoverable`) are retried after `retry_delay` up to
+    /// `max_attempts` times, starting from the first label that hadn't printed yet. The label that was printing
+    /// when the error happened is printed again, since the printer can't tell whether it came out. Any other
+    /// printer error fails the job immediately. Errors accessing the spool directory itself are returned.
+    pub fn process_next<T: rusb::UsbContext>(
+        &self,
+        printer: &ThermalPrinter<T>,
+    ) -> Result<Option<Job>> {
+        self.process_next_with(|job, images, printed| print_job(printer, job, images, printed))
+    }
+
+    /// Like `process_next`, printing each attempt with `print`, which counts the labels it printed
+    fn process_next_with(
+        &self,
+        mut print: impl FnMut(
+            &Job,
+            &[DynamicImage],
+            &mut usize,
+        ) -> std::result::Result<(), PrinterError>,
+    ) -> Result<Option<Job>> {
+        let Some(mut job) = self.queue.next_pending()? else {
+            return Ok(None);
+        };
+
+        let images = match self.queue.images(job.id) {
+            Ok(images) => images,
+            Err(e @ (SpoolError::Image(_) | SpoolError::NotFound(_))) => {
+                job.status = JobStatus::Failed(e.to_string());
+                self.queue.write_job(&job)?;
+                return Ok(Some(job));
+            }
+            Err(e) => return Err(e),
+        };
+
+        job.status = loop {
+            job.attempts += 1;
+            job.status = JobStatus::Printing;
+            self.queue.write_job(&job)?;
+
+            let mut printed = 0;
+            let result = print(&job, &images, &mut printed);
+            job.printed += printed;
+            match result {
+                Ok(()) => break JobStatus::Completed,
+                Err(e) if e.is_recoverable() && job.attempts < self.max_attempts => {
+                    thread::sleep(self.retry_delay);
+                }
+                Err(e) => break JobStatus::Failed(e.to_string()),
+            }
+        };
+        self.queue.write_job(&job)?;
+        Ok(Some(job))
+    }
+
+    /// Print jobs as they are submitted, polling the queue every `poll_interval` while it is empty.
+    ///
+    /// Only returns if the spool directory becomes inaccessible.
+    pub fn run<T: rusb::UsbContext>(
+        &self,
+        printer: &ThermalPrinter<T>,
+        poll_interval: Duration,
+    ) -> Result<()> {
+        loop {
+            if self.process_next(printer)?.is_none() {
+                thread::sleep(poll_interval);
+            }
+        }
+    }
+
+    /// Like `run`, but on a background thread and only locking `printer` while a job is being sent to it, so that
+    /// the printer can also be used by other threads, e.g. to query its status. The lock is released while
+    /// waiting to retry a job.
+    pub fn spawn<T: rusb::UsbContext + 'static>(
+        self: Arc<Self>,
+        printer: Arc<Mutex<ThermalPrinter<T>>>,
+        poll_interval: Duration,
+    ) -> thread::JoinHandle<Result<()>> {
+        thread::spawn(move || loop {
+            let processed = self.process_next_with(|job, images, printed| {
+                let printer = printer.lock().unwrap_or_else(|e| e.into_inner());
+                print_job(&printer, job, images, printed)
+            })?;
+            if processed.is_none() {
+                thread::sleep(poll_interval);
+            }
+        })
+    }
+}
+
+fn print_job<T: rusb::UsbContext>(
+    printer: &ThermalPrinter<T>,
+    job: &Job,
+    images: &[DynamicImage],
+    printed: &mut usize,
+) -> std::result::Result<(), PrinterError> {
+    let options = &job.options;
+    let label = printer.current_label()?;
+    let pages = images
+        .iter()
+        .map(|image| utils::rasterize_image_with_options(image.clone(), &label, &options.image))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let labels = remaining_labels(&pages, options.copies, job.printed);
+    printer.print_pages_counted(&labels, &options.print, printed)?;
+    Ok(())
+}
+
+/// The labels of a job with collated copies of `pages`, skipping the first `printed`
+fn remaining_labels<T: Clone>(pages: &[T], copies: usize, printed: usize) -> Vec<T> {
+    pages
+        .iter()
+        .cycle()
+        .take(pages.len() * copies)
+        .skip(printed)
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Placement;
+
+    /// A fresh spool directory that is removed when the test ends
+    struct TempDir(PathBuf);
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir()
+                .join(format!("brother-ql-spool-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            Self(dir)
+        }
+    }
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn image() -> DynamicImage {
+        DynamicImage::ImageLuma8(image::GrayImage::new(4, 4))
+    }
+
+    #[test]
+    fn job_file_round_trip() {
+        let mut options = JobOptions {
+            copies: 3,
+            ..JobOptions::default()
+        };
+        options.image.orientation = Orientation::Rotated270;
+        options.image.mirror = Mirror::Horizontal;
+        options.image.dither = Dither::JarvisJudiceNinke;
+        options.image.placement = Placement {
+            fit: Fit::Contain,
+            align: Align::End,
+            vertical_align: Align::Start,
+            length: Some(Length::mm(50.5)),
+            overflow: Overflow::Crop,
+        };
+        options.image.preprocess.brightness = -0.25;
+        options.image.preprocess.sharpen = Some(Sharpen {
+            sigma: 1.5,
+            threshold: 4,
+        });
+        options.image.preprocess.threshold = 100;
+        options.print.cut = CutMode::Off;
+        options.print.chain = true;
+        options.print.priority = Priority::Speed;
+        options.print.feed_margin = Some(Length::dots(50));
+        options.print.padding = (Length::mm(1.0), Length::inch(0.5));
+        let job = Job {
+            id: 7,
+            options,
+            status: JobStatus::Failed("out of\npaper".into()),
+            attempts: 2,
+            pages: 3,
+            printed: 5,
+        };
+
+        let path = Path::new("7.job");
+        let read = Job::deserialize(7, path, &job.serialize()).unwrap();
+        assert_eq!(read.options, job.options);
+        assert_eq!(read.status, JobStatus::Failed("out of paper".into()));
+        assert_eq!(read.attempts, 2);
+        assert_eq!((read.pages, read.printed), (3, 5));
+
+        for invalid in [
+            "fit=sideways\nstatus=pending\n",
+            "dither=true\nstatus=pending\n",
+        ] {
+            assert!(matches!(
+                Job::deserialize(1, Path::new("1.job"), invalid),
+                Err(SpoolError::Corrupt(..))
+            ));
+        }
+    }
+
+    #[test]
+    fn ids_are_never_reused() {
+        let dir = TempDir::new("ids");
+        let queue = Queue::open(&dir.0).unwrap();
+        let first = queue.submit(&image(), JobOptions::default()).unwrap();
+        let second = queue.submit(&image(), JobOptions::default()).unwrap();
+        assert!(second > first);
+
+        queue.remove(second).unwrap();
+        let third = queue.submit(&image(), JobOptions::default()).unwrap();
+        assert!(third > second);
+
+        queue.remove(first).unwrap();
+        queue.remove(third).unwrap();
+        drop(queue);
+        let queue = Queue::open(&dir.0).unwrap();
+        assert!(queue.jobs().unwrap().is_empty());
+        assert!(queue.submit(&image(), JobOptions::default()).unwrap() > third);
+    }
+
+    #[test]
+    fn spools_pages_as_one_job() {
+        let dir = TempDir::new("pages");
+        let spooler = Spooler::new(Queue::open(&dir.0).unwrap());
+        let queue = spooler.queue();
+        let pages = [
+            image(),
+            DynamicImage::ImageLuma8(image::GrayImage::new(2, 3)),
+            DynamicImage::ImageLuma8(image::GrayImage::new(5, 1)),
+        ];
+        assert!(matches!(
+            queue.submit_pages(&[], JobOptions::default()),
+            Err(SpoolError::Empty)
+        ));
+        let id = queue.submit_pages(&pages, JobOptions::default()).unwrap();
+        assert_eq!(queue.jobs().unwrap().len(), 1);
+
+        let job = spooler
+            .process_next_with(|_, images, _| {
+                let sizes: Vec<_> = images.iter().map(|i| (i.width(), i.height())).collect();
+                assert_eq!(sizes, [(4, 4), (2, 3), (5, 1)]);
+                Ok(())
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!((job.id, job.pages), (id, 3));
+
+        queue.remove(id).unwrap();
+        assert_eq!(
+            fs::read_dir(&dir.0).unwrap().count(),
+            1,
+            "only next-id is left"
+        );
+    }
+
+    #[test]
+    fn retries_recoverable_errors() {
+        let dir = TempDir::new("retries");
+        let mut spooler = Spooler::new(Queue::open(&dir.0).unwrap());
+        spooler.retry_delay = Duration::ZERO;
+        spooler.max_attempts = 3;
+        let queue = spooler.queue();
+
+        let busy = queue.submit(&image(), JobOptions::default()).unwrap();
+        let mut attempts = 0;
+        let job = spooler
+            .process_next_with(|_, _, _| {
+                attempts += 1;
+                match attempts {
+                    1 => Err(PrinterError::Busy("cooling".into())),
+                    _ => Ok(()),
+                }
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!((job.id, job.attempts), (busy, 2));
+        assert_eq!(queue.job(busy).unwrap().status, JobStatus::Completed);
+
+        let failing = queue.submit(&image(), JobOptions::default()).unwrap();
+        let job = spooler
+            .process_next_with(|_, _, _| Err(PrinterError::Busy("cooling".into())))
+            .unwrap()
+            .unwrap();
+        assert_eq!((job.id, job.attempts), (failing, 3));
+        assert!(matches!(job.status, JobStatus::Failed(_)));
+
+        let unrecoverable = queue.submit(&image(), JobOptions::default()).unwrap();
+        let job = spooler
+            .process_next_with(|_, _, _| Err(PrinterError::Printer("no media".into())))
+            .unwrap()
+            .unwrap();
+        assert_eq!((job.id, job.attempts), (unrecoverable, 1));
+        assert!(spooler
+            .process_next_with(|_, _, _| Ok(()))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn resumes_after_printed_labels() {
+        let dir = TempDir::new("resume");
+        let mut spooler = Spooler::new(Queue::open(&dir.0).unwrap());
+        spooler.retry_delay = Duration::ZERO;
+        let queue = spooler.queue();
+        let options = JobOptions {
+            copies: 2,
+            ..JobOptions::default()
+        };
+        let id = queue
+            .submit_pages(&[image(), image(), image()], options)
+            .unwrap();
+
+        let mut resumed_at = Vec::new();
+        let job = spooler
+            .process_next_with(|job, _, printed| {
+                resumed_at.push(job.printed);
+                match job.attempts {
+                    // Fails on the fifth label, then again before printing anything
+                    1 => *printed += 4,
+                    2 => *printed += 0,
+                    _ => {
+                        *printed += 2;
+                        return Ok(());
+                    }
+                }
+                Err(PrinterError::Busy("cooling".into()))
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(resumed_at, [0, 4, 4]);
+        assert_eq!((job.id, job.printed), (id, 6));
+        assert_eq!(queue.job(id).unwrap().printed, 6);
+
+        assert_eq!(
+            remaining_labels(&['a', 'b', 'c'], 2, 0),
+            ['a', 'b', 'c', 'a', 'b', 'c']
+        );
+        assert_eq!(remaining_labels(&['a', 'b', 'c'], 2, 4), ['b', 'c']);
+        assert!(remaining_labels(&['a'], 3, 3).is_empty());
+    }
+}
@@ -0,0 +1,157 @@
+//! Rendering SVG files directly at the printer's resolution, so that vector text and barcodes stay sharp
+
+use std::path::Path;
+
+use image::{DynamicImage, RgbaImage};
+use resvg::{tiny_skia, usvg};
+use thiserror::Error;
+
+use crate::printer::{constants::Label, Orientation, PrinterError};
+use crate::units::DOTS_PER_INCH;
+use crate::utils::{self, ImageOptions};
+
+#[derive(Error, Debug)]
+pub enum SvgError {
+    #[error("io: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("svg: {0}")]
+    Parse(#[from] usvg::Error),
+    #[error("{0}")]
+    Printer(#[from] PrinterError),
+    #[error("svg is too large to render at {0}x{1} dots")]
+    TooLarge(u32, u32),
+}
+type Result<T> = std::result::Result<T, SvgError>;
+
+/// SVG user units per inch
+const PX_PER_INCH: f32 = 96.0;
+
+/// A parsed SVG document. Text is drawn with the system fonts, and with the embedded default font if enabled.
+pub struct Svg {
+    tree: usvg::Tree,
+}
+impl Svg {
+    pub fn from_data(data: &[u8]) -> Result<Self> {
+        Self::parse(data, usvg::Options::default())
+    }
+    /// Loads an SVG file, resolving relative image paths from its directory
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let options = usvg::Options {
+            resources_dir: path.parent().map(Path::to_path_buf),
+            ..usvg::Options::default()
+        };
+        Self::parse(&std::fs::read(path)?, options)
+    }
+    fn parse(data: &[u8], mut options: usvg::Options) -> Result<Self> {
+        let fonts = options.fontdb_mut();
+        fonts.load_system_fonts();
+        #[cfg(feature = "embedded-font")]
+        {
+            fonts.load_font_data(dejavu::sans::regular().to_vec());
+            fonts.load_font_data(dejavu::sans::bold().to_vec());
+        }
+        Ok(Self {
+            tree: usvg::Tree::from_data(data, &options)?,
+        })
+    }
+    /// Size of the document at its physical size, in dots
+    pub fn size(&self) -> (f32, f32) {
+        let size = self.tree.size();
+        let scale = DOTS_PER_INCH / PX_PER_INCH;
+        (size.width() * scale, size.height() * scale)
+    }
+    /// Renders the document stretched to `width` by `height` dots on a white background
+    pub fn render(&self, width: u32, height: u32) -> Result<DynamicImage> {
+        let mut pixmap =
+            tiny_skia::Pixmap::new(width, height).ok_or(SvgError::TooLarge(width, height))?;
+        pixmap.fill(tiny_skia::Color::WHITE);
+        let size = self.tree.size();
+        let transform = tiny_skia::Transform::from_scale(
+            width as f32 / size.width(),
+            height as f32 / size.height(),
+        );
+        resvg::render(&self.tree, transform, &mut pixmap.as_mut());
+
+        // The background is opaque, so the premultiplied pixels are the same as straight ones
+        let image = RgbaImage::from_raw(width, height, pixmap.take())
+            .expect("pixmap has 4 bytes per pixel");
+        Ok(DynamicImage::ImageRgba8(image))
+    }
+    /// Renders the document at the size it takes up on `label` and rasterizes it like
+    /// `utils::rasterize_image_with_options`, without scaling the rendered image
+    pub fn rasterize(&self, label: &Label, options: &ImageOptions) -> Result<Vec<[u8; 90]>> {
+        let (width, height) = self.size();
+        let size = (
+            width.round().max(1.0) as u32,
+            height.round().max(1.0) as u32,
+        );
+        let placement = &options.placement;
+        let mut options = *options;
+        if options.orientation == Orientation::Auto {
+            options.orientation = utils::auto_orientation(size, label, placement);
+        }
+        let rotated = matches!(
+            options.orientation,
+            Orientation::Rotated | Orientation::Rotated270
+        );
+        let size = if rotated { (size.1, size.0) } else { size };
+
+        let length = placement.length_on(label);
+        let (size, _) = utils::placed_size(size, label.dots_printable.0, length, placement)?;
+        let size = if rotated { (size.1, size.0) } else { size };
+
+        let image = self.render(size.0, size.1)?;
+        Ok(utils::rasterize_image_with_options(image, label, &options)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::printer::constants;
+
+    /// A document 96 px (one inch, 300 dots) wide with a black rectangle over its left half
+    const HALF_BLACK: &str = r#"<svg xmlns="http://www.w3.org/2000/svg" width="96" height="48">
+        <rect x="0" y="0" width="48" height="48" fill="black"/>
+    </svg>"#;
+
+    #[test]
+    fn renders_at_physical_size() {
+        let svg = Svg::from_data(HALF_BLACK.as_bytes()).unwrap();
+        assert_eq!(svg.size(), (300.0, 150.0));
+
+        let image = svg.render(300, 150).unwrap().to_luma8();
+        assert_eq!(image.get_pixel(0, 0)[0], 0);
+        assert_eq!(image.get_pixel(149, 149)[0], 0);
+        assert_eq!(image.get_pixel(150, 0)[0], 255);
+        assert_eq!(image.get_pixel(299, 149)[0], 255);
+
+        assert!(matches!(Svg::from_data(b"<svg"), Err(SvgError::Parse(_))));
+        assert!(matches!(svg.render(0, 10), Err(SvgError::TooLarge(0, 10))));
+    }
+
+    #[test]
+    fn rasterizes_without_resampling() {
+        let svg = Svg::from_data(HALF_BLACK.as_bytes()).unwrap();
+        let label = constants::label_data(62, None).unwrap();
+        let options = ImageOptions {
+            orientation: Orientation::Normal,
+            ..ImageOptions::default()
+        };
+        let lines = svg.rasterize(&label, &options).unwrap();
+        let expected =
+            utils::rasterize_image_with_options(svg.render(696, 348).unwrap(), &label, &options)
+                .unwrap();
+        assert_eq!(lines.len(), 348);
+        assert_eq!(lines, expected);
+
+        // Turned by 90 degrees, the short side runs across the tape
+        let turned = ImageOptions {
+            orientation: Orientation::Rotated,
+            ..options
+        };
+        let lines = svg.rasterize(&label, &turned).unwrap();
+        assert_eq!(lines.len(), 1392);
+    }
+}
@@ -0,0 +1,785 @@
+//! Labels described as data: the label media and a list of positioned text boxes, images, barcodes, QR codes,
+//! lines, and rectangles
+//!
+//! Positions are measured from the top left corner of the printable area in the template's `units` (`dots`, `mm`,
+//! `inch`, or `pt`), with x running across the tape and y running along it. With the `template` feature, templates can be loaded from JSON or TOML:
+//!
+//! ```json
+//! {
+//!     "media": { "width": 62, "length": 29 },
+//!     "units": "mm",
+//!     "elements": [
+//!         { "type": "text", "x": 2, "y": 2, "width": 35, "height": 20, "text": "Hello" },
+//!         { "type": "qr", "x": 40, "y": 2, "size": 20, "data": "https://example.com" },
+//!         { "type": "line", "x1": 0, "y1": 23, "x2": 58, "y2": 23, "thickness": 0.5 }
+//!     ]
+//! }
+//! ```
+//!
+//! Each image element chooses its own halftoning with `dither`, while text, barcodes, QR codes, and shapes are
+//! always thresholded, so a dithered photo and a crisp barcode can share a label.
+
+use std::fmt;
+use std::path::PathBuf;
+
+use image::{imageops, GrayImage, Luma};
+use qrcodegen::QrCodeEcc;
+#[cfg(feature = "template")]
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::barcode::{self, BarcodeError, Symbology};
+use crate::printer::constants::{self, Label};
+#[cfg(feature = "text")]
+use crate::text::{layout, Alignment, FontRegistry, LayoutOptions, TextError, Weight};
+pub use crate::units::Units;
+use crate::utils::{preprocess::flatten_alpha, Dither};
+
+pub mod merge;
+
+#[derive(Error, Debug)]
+pub enum TemplateError {
+    #[error("io: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("image: {0}")]
+    Image(#[from] image::ImageError),
+    #[error("barcode: {0}")]
+    Barcode(#[from] BarcodeError),
+    #[cfg(feature = "text")]
+    #[error("text: {0}")]
+    Text(#[from] TextError),
+    #[error("unknown label media {0}")]
+    UnknownMedia(String),
+    #[error("templates for continuous tape need a length")]
+    MissingLength,
+    #[cfg(feature = "template")]
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[cfg(feature = "template")]
+    #[error("toml: {0}")]
+    Toml(#[from] toml::de::Error),
+}
+type Result<T> = std::result::Result<T, TemplateError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "template", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "template", serde(rename_all = "snake_case"))]
+pub enum Color {
+    #[default]
+    Black,
+    White,
+}
+impl Color {
+    fn luma(self) -> Luma<u8> {
+        match self {
+            Color::Black => Luma([0]),
+            Color::White => Luma([255]),
+        }
+    }
+}
+
+/// Label media that a template is designed for, as passed to `constants::label_data`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "template", derive(Serialize, Deserialize))]
+pub struct LabelMedia {
+    /// Tape width in mm
+    pub width: u8,
+    /// Label length in mm for die-cut labels, or `None` for continuous tape
+    #[cfg_attr(feature = "template", serde(default))]
+    pub length: Option<u8>,
+}
+impl LabelMedia {
+    pub fn label(&self) -> Result<Label> {
+        constants::label_data(self.width, self.length)
+            .ok_or_else(|| TemplateError::UnknownMedia(self.to_string()))
+    }
+}
+impl fmt::Display for LabelMedia {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.length {
+            Some(length) => write!(f, "{}x{}mm", self.width, length),
+            None => write!(f, "{}mm", self.width),
+        }
+    }
+}
+impl From<&Label> for LabelMedia {
+    fn from(label: &Label) -> Self {
+        Self {
+            width: label.tape_size.0 as u8,
+            length: match label.tape_size.1 {
+                0 => None,
+                length => Some(length as u8),
+            },
+        }
+    }
+}
+
+/// A label layout that can be rendered to an image
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "template", derive(Serialize, Deserialize))]
+pub struct Template {
+    pub media: LabelMedia,
+    pub units: Units,
+    /// Length of labels printed on continuous tape, in `units`
+    #[cfg_attr(feature = "template", serde(default))]
+    pub length: Option<f32>,
+    /// Elements are drawn in order, so later elements are drawn on top of earlier ones
+    #[cfg_attr(feature = "template", serde(default))]
+    pub elements: Vec<Element>,
+}
+impl Template {
+    /// An empty template. Set `length` for continuous tape.
+    pub fn new(media: LabelMedia, units: Units) -> Self {
+        Self {
+            media,
+            units,
+            length: None,
+            elements: Vec::new(),
+        }
+    }
+    #[cfg(feature = "template")]
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+    #[cfg(feature = "template")]
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        Ok(toml::from_str(toml)?)
+    }
+    #[cfg(feature = "template")]
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+    pub fn label(&self) -> Result<Label> {
+        self.media.label()
+    }
+    /// Size of the rendered image in dots: the printable width of the label by the printable length of die-cut
+    /// labels or the template's `length` for continuous tape
+    pub fn size(&self) -> Result<(u32, u32)> {
+        let label = self.label()?;
+        let length = match label.tape_size.1 {
+            0 => {
+                let length = self.length.ok_or(TemplateError::MissingLength)?;
+                self.units.to_dots(length).round().max(1.0) as u32
+            }
+            _ => label.dots_printable.1,
+        };
+        Ok((label.dots_printable.0, length))
+    }
+    /// Renders the template with the embedded default font, if enabled
+    pub fn render(&self) -> Result<GrayImage> {
+        Renderer::default().render(self)
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "template", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "template", serde(tag = "type", rename_all = "snake_case"))]
+pub enum Element {
+    #[cfg(feature = "text")]
+    Text(TextElement),
+    Image(ImageElement),
+    Barcode(BarcodeElement),
+    Qr(QrElement),
+    Line(LineElement),
+    Rectangle(RectangleElement),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "template", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "template", serde(rename_all = "snake_case"))]
+pub enum VerticalAlignment {
+    Top,
+    #[default]
+    Center,
+    Bottom,
+}
+
+/// Text laid out in a box, see `text::layout`
+#[cfg(feature = "text")]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "template", derive(Serialize, Deserialize))]
+pub struct TextElement {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub text: String,
+    /// Largest font size, as the height of a line. Defaults to the height of the box.
+    #[cfg_attr(feature = "template", serde(default))]
+    pub font_size: Option<f32>,
+    /// Smallest font size that the text shrinks to while trying to fit
+    #[cfg_attr(feature = "template", serde(default))]
+    pub min_font_size: Option<f32>,
+    #[cfg_attr(feature = "template", serde(default = "default_line_spacing"))]
+    pub line_spacing: f32,
+    #[cfg_attr(feature = "template", serde(default))]
+    pub align: Alignment,
+    #[cfg_attr(feature = "template", serde(default))]
+    pub vertical_align: VerticalAlignment,
+    #[cfg_attr(feature = "template", serde(default))]
+    pub max_lines: Option<usize>,
+    #[cfg_attr(feature = "template", serde(default = "default_wrap"))]
+    pub wrap: bool,
+    /// Font family from the renderer's `FontRegistry`, defaulting to its first family
+    #[cfg_attr(feature = "template", serde(default))]
+    pub family: Option<String>,
+    #[cfg_attr(feature = "template", serde(default))]
+    pub bold: bool,
+    #[cfg_attr(feature = "template", serde(default))]
+    pub color: Color,
+}
+#[cfg(feature = "text")]
+impl TextElement {
+    pub fn new(x: f32, y: f32, width: f32, height: f32, text: impl Into<String>) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+            text: text.into(),
+            font_size: None,
+            min_font_size: None,
+            line_spacing: 1.0,
+            align: Alignment::Left,
+            vertical_align: VerticalAlignment::Center,
+            max_lines: None,
+            wrap: true,
+            family: None,
+            bold: false,
+            color: Color::Black,
+        }
+    }
+}
+
+#[cfg(all(feature = "template", feature = "text"))]
+fn default_line_spacing() -> f32 {
+    1.0
+}
+
+#[cfg(all(feature = "template", feature = "text"))]
+fn default_wrap() -> bool {
+    true
+}
+
+/// An image file, scaled to fit within its box and centered
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "template", derive(Serialize, Deserialize))]
+pub struct ImageElement {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub path: PathBuf,
+    /// How the image is turned into black and white, e.g. `atkinson` for photos. Only applies to this element;
+    /// everything else on the label is thresholded so that text and barcodes keep sharp edges.
+    #[cfg_attr(feature = "template", serde(default))]
+    pub dither: Dither,
+}
+
+/// A linear barcode, centered in its box with bars a whole number of dots wide
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "template", derive(Serialize, Deserialize))]
+pub struct BarcodeElement {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub symbology: Symbology,
+    pub data: String,
+    /// Width of the narrowest bar, in the template's units. By default, bars are as wide as fits in the box.
+    #[cfg_attr(feature = "template", serde(default))]
+    pub module_width: Option<f32>,
+    #[cfg_attr(feature = "template", serde(default))]
+    pub color: Color,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "template", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "template", serde(rename_all = "snake_case"))]
+pub enum ErrorCorrection {
+    Low,
+    #[default]
+    Medium,
+    Quartile,
+    High,
+}
+impl From<ErrorCorrection> for QrCodeEcc {
+    fn from(ecc: ErrorCorrection) -> Self {
+        match ecc {
+            ErrorCorrection::Low => QrCodeEcc::Low,
+            ErrorCorrection::Medium => QrCodeEcc::Medium,
+            ErrorCorrection::Quartile => QrCodeEcc::Quartile,
+            ErrorCorrection::High => QrCodeEcc::High,
+        }
+    }
+}
+
+/// A QR code, centered in a square box with modules a whole number of dots wide
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "template", derive(Serialize, Deserialize))]
+pub struct QrElement {
+    pub x: f32,
+    pub y: f32,
+    pub size: f32,
+    pub data: String,
+    #[cfg_attr(feature = "template", serde(default))]
+    pub error_correction: ErrorCorrection,
+    #[cfg_attr(feature = "template", serde(default))]
+    pub color: Color,
+}
+
+/// A straight line between two points
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "template", derive(Serialize, Deserialize))]
+pub struct LineElement {
+    pub x1: f32,
+    pub y1: f32,
+    pub x2: f32,
+    pub y2: f32,
+    /// Defaults to one dot
+    #[cfg_attr(feature = "template", serde(default))]
+    pub thickness: Option<f32>,
+    #[cfg_attr(feature = "template", serde(default))]
+    pub color: Color,
+}
+
+/// A filled rectangle, or the outline of one if `outline` is set
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "template", derive(Serialize, Deserialize))]
+pub struct RectangleElement {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    /// Thickness of the outline, drawn inside the rectangle
+    #[cfg_attr(feature = "template", serde(default))]
+    pub outline: Option<f32>,
+    #[cfg_attr(feature = "template", serde(default))]
+    pub color: Color,
+}
+
+/// Draws templates and their elements, with text drawn using the fonts in its registry
+#[derive(Clone)]
+pub struct Renderer {
+    #[cfg(feature = "text")]
+    fonts: FontRegistry,
+}
+impl Default for Renderer {
+    /// A renderer using the embedded default font, if enabled
+    fn default() -> Self {
+        Self {
+            #[cfg(feature = "embedded-font")]
+            fonts: FontRegistry::embedded(),
+            #[cfg(all(feature = "text", not(feature = "embedded-font")))]
+            fonts: FontRegistry::new(),
+        }
+    }
+}
+impl Renderer {
+    #[cfg(feature = "text")]
+    pub fn with_fonts(fonts: FontRegistry) -> Self {
+        Self { fonts }
+    }
+    /// Renders a template onto a white image sized by `Template::size`
+    pub fn render(&self, template: &Template) -> Result<GrayImage> {
+        let (width, length) = template.size()?;
+        let mut image = GrayImage::from_pixel(width, length, Luma([255]));
+        for element in &template.elements {
+            self.draw(&mut image, element, template.units)?;
+        }
+        Ok(image)
+    }
+    /// Draws a single element onto an image, with positions and sizes in `units`
+    pub fn draw(&self, image: &mut GrayImage, element: &Element, units: Units) -> Result<()> {
+        let dots = |value: f32| units.to_dots(value);
+        match element {
+            #[cfg(feature = "text")]
+            Element::Text(text) => {
+                let weight = if text.bold {
+                    Weight::Bold
+                } else {
+                    Weight::Regular
+                };
+                let fonts = self
+                    .fonts
+                    .chain(text.family.as_deref(), weight)
+                    .ok_or(TextError::NoFonts)?;
+                let (width, height) = (dots(text.width).max(0.0), dots(text.height).max(0.0));
+                let font_size = dots(text.font_size.unwrap_or(text.height));
+                let options = LayoutOptions {
+                    font_size,
+                    min_font_size: text
+                        .min_font_size
+                        .map_or(LayoutOptions::default().min_font_size, dots)
+                        .min(font_size),
+                    line_spacing: text.line_spacing,
+                    alignment: text.align,
+                    max_lines: text.max_lines,
+                    wrap: text.wrap,
+                };
+                let laid_out = layout::layout(
+                    &fonts,
+                    &text.text,
+                    width as u32,
+                    Some(height as u32),
+                    &options,
+                );
+                let space = (height - laid_out.height as f32).max(0.0);
+                let top = match text.vertical_align {
+                    VerticalAlignment::Top => 0.0,
+                    VerticalAlignment::Center => space / 2.0,
+                    VerticalAlignment::Bottom => space,
+                };
+                let offset = (
+                    dots(text.x).round() as i32,
+                    (dots(text.y) + top).round() as i32,
+                );
+                laid_out.draw(image, offset, text.color == Color::White);
+            }
+            Element::Image(element) => {
+                let source = flatten_alpha(image::open(&element.path)?);
+                let (width, height) = (dots(element.width), dots(element.height));
+                // Contain the image within its box, keeping its aspect ratio
+                let ratio = (width / source.width() as f32).min(height / source.height() as f32);
+                let new_width = (source.width() as f32 * ratio).round().max(1.0) as u32;
+                let new_height = (source.height() as f32 * ratio).round().max(1.0) as u32;
+                let mut resized = imageops::resize(
+                    &source,
+                    new_width,
+                    new_height,
+                    imageops::FilterType::Triangle,
+                );
+                element.dither.apply(&mut resized);
+                let x = dots(element.x) + (width - new_width as f32) / 2.0;
+                let y = dots(element.y) + (height - new_height as f32) / 2.0;
+                imageops::replace(image, &resized, x.round() as i64, y.round() as i64);
+            }
+            Element::Barcode(barcode) => {
+                let modules = barcode::encode(barcode.symbology, &barcode.data)?;
+                let (x, y) = (dots(barcode.x), dots(barcode.y));
+                let (width, height) = (dots(barcode.width), dots(barcode.height));
+                // Whole dots per module keep the bars sharp
+                let count = modules.len() as f32;
+                let module = match barcode.module_width {
+                    Some(module_width) => dots(module_width).round().max(1.0),
+                    None => (width / count).floor().max(1.0),
+                };
+                let left = x + (width - module * count).max(0.0) / 2.0;
+                for (i, _) in modules.iter().enumerate().filter(|(_, &bar)| bar == 1) {
+                    let bar_x = left + i as f32 * module;
+                    fill_rect(image, bar_x, y, bar_x + module, y + height, barcode.color);
+                }
+            }
+            Element::Qr(qr) => {
+                let code = barcode::encode_qr(&qr.data, qr.error_correction.into())?;
+                let count = code.size();
+                let size = dots(qr.size);
+                let module = (size / count as f32).floor().max(1.0);
+                let margin = (size - module * count as f32).max(0.0) / 2.0;
+                let (left, top) = (dots(qr.x) + margin, dots(qr.y) + margin);
+                for row in 0..count {
+                    for column in (0..count).filter(|&column| code.get_module(column, row)) {
+                        let module_x = left + column as f32 * module;
+                        let module_y = top + row as f32 * module;
+                        fill_rect(
+                            image,
+                            module_x,
+                            module_y,
+                            module_x + module,
+                            module_y + module,
+                            qr.color,
+                        );
+                    }
+                }
+            }
+            Element::Line(line) => {
+                let thickness = line.thickness.map_or(1.0, dots);
+                draw_line(
+                    image,
+                    (dots(line.x1), dots(line.y1)),
+                    (dots(line.x2), dots(line.y2)),
+                    thickness,
+                    line.color,
+                );
+            }
+            Element::Rectangle(rectangle) => {
+                let (x0, y0) = (dots(rectangle.x), dots(rectangle.y));
+                let (x1, y1) = (x0 + dots(rectangle.width), y0 + dots(rectangle.height));
+                let color = rectangle.color;
+                match rectangle.outline.map(dots) {
+                    None => fill_rect(image, x0, y0, x1, y1, color),
+                    Some(t) => {
+                        fill_rect(image, x0, y0, x1, y0 + t, color);
+                        fill_rect(image, x0, y1 - t, x1, y1, color);
+                        fill_rect(image, x0, y0, x0 + t, y1, color);
+                        fill_rect(image, x1 - t, y0, x1, y1, color);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fills the pixels between two corners, clipped to the image
+fn fill_rect(image: &mut GrayImage, x0: f32, y0: f32, x1: f32, y1: f32, color: Color) {
+    let clip = |value: f32, max: u32| (value.round().max(0.0) as u32).min(max);
+    let (x0, x1) = (
+        clip(x0.min(x1), image.width()),
+        clip(x0.max(x1), image.width()),
+    );
+    let (y0, y1) = (
+        clip(y0.min(y1), image.height()),
+        clip(y0.max(y1), image.height()),
+    );
+    for y in y0..y1 {
+        for x in x0..x1 {
+            image.put_pixel(x, y, color.luma());
+        }
+    }
+}
+
+/// Draws a line between the centers of two pixels, filling every pixel whose center is within half the thickness
+fn draw_line(
+    image: &mut GrayImage,
+    from: (f32, f32),
+    to: (f32, f32),
+    thickness: f32,
+    color: Color,
+) {
+    let radius = thickness.max(1.0) / 2.0;
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let length_squared = dx * dx + dy * dy;
+
+    let clip = |value: f32, max: u32| value.max(0.0).min(max as f32) as u32;
+    let (x0, x1) = (from.0.min(to.0) - radius, from.0.max(to.0) + radius);
+    let (y0, y1) = (from.1.min(to.1) - radius, from.1.max(to.1) + radius);
+    for y in clip(y0.floor(), image.height())..clip(y1.ceil() + 1.0, image.height()) {
+        for x in clip(x0.floor(), image.width())..clip(x1.ceil() + 1.0, image.width()) {
+            let (px, py) = (x as f32 - from.0, y as f32 - from.1);
+            // Distance from the pixel to the closest point on the line segment
+            let t = if length_squared > 0.0 {
+                ((px * dx + py * dy) / length_squared).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let (ex, ey) = (px - t * dx, py - t * dy);
+            if (ex * ex + ey * ey).sqrt() <= radius {
+                image.put_pixel(x, y, color.luma());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dots_template(elements: Vec<Element>) -> Template {
+        Template {
+            length: Some(50.0),
+            elements,
+            ..Template::new(
+                LabelMedia {
+                    width: 62,
+                    length: None,
+                },
+                Units::Dots,
+            )
+        }
+    }
+
+    fn black(image: &GrayImage) -> Vec<(u32, u32)> {
+        image
+            .enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel[0] == 0)
+            .map(|(x, y, _)| (x, y))
+            .collect()
+    }
+
+    #[test]
+    fn sizes_templates() {
+        let die_cut = Template::new(
+            LabelMedia {
+                width: 62,
+                length: Some(29),
+            },
+            Units::Mm,
+        );
+        let label = constants::label_data(62, Some(29)).unwrap();
+        let printable = label.dots_printable;
+        assert_eq!(die_cut.size().unwrap(), (printable.0, printable.1));
+
+        let mut continuous = Template::new(
+            LabelMedia {
+                width: 62,
+                length: None,
+            },
+            Units::Mm,
+        );
+        assert!(matches!(
+            continuous.size(),
+            Err(TemplateError::MissingLength)
+        ));
+        continuous.length = Some(25.4);
+        assert_eq!(continuous.size().unwrap(), (696, 300));
+
+        let unknown = Template::new(
+            LabelMedia {
+                width: 63,
+                length: None,
+            },
+            Units::Mm,
+        );
+        assert!(matches!(
+            unknown.size(),
+            Err(TemplateError::UnknownMedia(media)) if media == "63mm"
+        ));
+    }
+
+    #[test]
+    fn draws_shapes() {
+        let rectangle = |x, y, width, height, outline, color| {
+            Element::Rectangle(RectangleElement {
+                x,
+                y,
+                width,
+                height,
+                outline,
+                color,
+            })
+        };
+        let image = dots_template(vec![rectangle(10.0, 5.0, 4.0, 3.0, None, Color::Black)])
+            .render()
+            .unwrap();
+        let expected: Vec<_> = (5..8).flat_map(|y| (10..14).map(move |x| (x, y))).collect();
+        assert_eq!(black(&image), expected);
+
+        // Later elements are drawn on top
+        let image = dots_template(vec![
+            rectangle(10.0, 5.0, 4.0, 4.0, Some(1.0), Color::Black),
+            rectangle(10.0, 5.0, 1.0, 1.0, None, Color::White),
+        ])
+        .render()
+        .unwrap();
+        assert_eq!(black(&image).len(), 11);
+        assert_eq!(image.get_pixel(10, 5)[0], 255);
+        assert_eq!(image.get_pixel(11, 6)[0], 255);
+
+        let image = dots_template(vec![Element::Line(LineElement {
+            x1: 20.0,
+            y1: 10.0,
+            x2: 29.0,
+            y2: 10.0,
+            thickness: Some(3.0),
+            color: Color::Black,
+        })])
+        .render()
+        .unwrap();
+        // Lines have round ends
+        let expected: Vec<_> = (9..12)
+            .flat_map(|y| (19..31).map(move |x| (x, y)))
+            .collect();
+        assert_eq!(black(&image), expected);
+
+        // Shapes are clipped to the label
+        let image = dots_template(vec![rectangle(-10.0, 40.0, 20.0, 20.0, None, Color::Black)])
+            .render()
+            .unwrap();
+        assert_eq!(black(&image).len(), 10 * 10);
+    }
+
+    #[test]
+    fn draws_codes_with_whole_dot_modules() {
+        let image = dots_template(vec![Element::Qr(QrElement {
+            x: 0.0,
+            y: 0.0,
+            size: 50.0,
+            data: "hello".to_string(),
+            error_correction: ErrorCorrection::Low,
+            color: Color::Black,
+        })])
+        .render()
+        .unwrap();
+        // A version 1 code is 21 modules, so 2 dots each with a margin of 4
+        assert_eq!(image.get_pixel(3, 4)[0], 255);
+        assert_eq!(image.get_pixel(4, 4)[0], 0);
+        assert_eq!(image.get_pixel(45, 4)[0], 0);
+        assert_eq!(image.get_pixel(46, 4)[0], 255);
+        assert_eq!(image.get_pixel(4, 45)[0], 0);
+        assert_eq!(image.get_pixel(4, 46)[0], 255);
+
+        let invalid = dots_template(vec![Element::Barcode(BarcodeElement {
+            x: 0.0,
+            y: 0.0,
+            width: 200.0,
+            height: 50.0,
+            symbology: barcode::Symbology::Ean13,
+            data: "abc".to_string(),
+            module_width: None,
+            color: Color::Black,
+        })]);
+        assert!(matches!(invalid.render(), Err(TemplateError::Barcode(_))));
+    }
+
+    #[cfg(feature = "template")]
+    #[test]
+    fn parses_templates() {
+        let json = r#"{
+            "media": { "width": 62, "length": 29 },
+            "units": "mm",
+            "elements": [
+                { "type": "qr", "x": 40, "y": 2, "size": 20, "data": "https://example.com" },
+                { "type": "line", "x1": 0, "y1": 23, "x2": 58, "y2": 23, "thickness": 0.5 }
+            ]
+        }"#;
+        let template = Template::from_json(json).unwrap();
+        assert_eq!(
+            template.media,
+            LabelMedia {
+                width: 62,
+                length: Some(29)
+            }
+        );
+        assert_eq!(template.units, Units::Mm);
+        assert!(matches!(
+            &template.elements[..],
+            [Element::Qr(_), Element::Line(LineElement { thickness: Some(t), .. })] if *t == 0.5
+        ));
+
+        let toml = r#"
+            units = "dots"
+            length = 100
+
+            [media]
+            width = 29
+
+            [[elements]]
+            type = "rectangle"
+            x = 0
+            y = 0
+            width = 10
+            height = 10
+            color = "white"
+        "#;
+        let template = Template::from_toml(toml).unwrap();
+        assert_eq!(template.size().unwrap(), (306, 100));
+        assert!(matches!(
+            &template.elements[..],
+            [Element::Rectangle(RectangleElement {
+                color: Color::White,
+                outline: None,
+                ..
+            })]
+        ));
+
+        let round_trip = Template::from_json(&template.to_json().unwrap()).unwrap();
+        assert_eq!(round_trip.size().unwrap(), (306, 100));
+        assert_eq!(round_trip.elements.len(), 1);
+
+        assert!(matches!(
+            Template::from_json(r#"{ "media": { "width": 62 }, "units": "furlongs" }"#),
+            Err(TemplateError::Json(_))
+        ));
+    }
+}
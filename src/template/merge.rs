@@ -0,0 +1,296 @@
+//! Mail merge: fill the `{{placeholders}}` in a template from a list of records and print one label per record
+//!
+//! Placeholders can appear in text, barcode and QR code data, and image paths. Each record maps placeholder names
+//! to values, e.g. the columns of a CSV file with a header row:
+//!
+//! ```csv
+//! name,sku
+//! Widget,012345678912
+//! Gadget,098765432109
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+
+use image::GrayImage;
+use thiserror::Error;
+
+use super::{Element, LabelMedia, Renderer, Template, TemplateError};
+use crate::printer::{status, PrintOptions, PrinterError, ThermalPrinter};
+use crate::utils;
+
+#[derive(Error, Debug)]
+pub enum MergeError {
+    #[error("template: {0}")]
+    Template(#[from] TemplateError),
+    #[error("printer: {0}")]
+    Printer(#[from] PrinterError),
+    #[error("no value for placeholder {{{{{0}}}}}")]
+    MissingField(String),
+    #[error("template is for {0} labels but {1} labels are loaded")]
+    WrongMedia(LabelMedia, LabelMedia),
+    #[error("nothing to print")]
+    Empty,
+    #[cfg(feature = "template")]
+    #[error("csv: {0}")]
+    Csv(#[from] csv::Error),
+}
+type Result<T> = std::result::Result<T, MergeError>;
+
+/// Values to fill a template's placeholders with, keyed by placeholder name
+pub type Record = HashMap<String, String>;
+
+/// Reads records from CSV data, using the header row as the placeholder names
+#[cfg(feature = "template")]
+pub fn read_csv(reader: impl std::io::Read) -> Result<Vec<Record>> {
+    let mut reader = csv::Reader::from_reader(reader);
+    let headers = reader.headers()?.clone();
+    reader
+        .records()
+        .map(|record| {
+            Ok(headers
+                .iter()
+                .zip(record?.iter())
+                .map(|(header, value)| (header.to_string(), value.to_string()))
+                .collect())
+        })
+        .collect()
+}
+
+/// Replaces every `{{name}}` in `text` with the record's value for `name`. Surrounding whitespace in the name is
+/// ignored and an unclosed `{{` is left as is.
+pub fn substitute(text: &str, record: &Record) -> Result<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start + 2..].find("}}") {
+            Some(end) => start + 2 + end,
+            None => break,
+        };
+        let name = rest[start + 2..end].trim();
+        let value = record
+            .get(name)
+            .ok_or_else(|| MergeError::MissingField(name.to_string()))?;
+        result.push_str(&rest[..start]);
+        result.push_str(value);
+        rest = &rest[end + 2..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Returns a copy of `template` with its placeholders filled from `record`
+pub fn fill(template: &Template, record: &Record) -> Result<Template> {
+    let mut template = template.clone();
+    for element in &mut template.elements {
+        match element {
+            #[cfg(feature = "text")]
+            Element::Text(text) => text.text = substitute(&text.text, record)?,
+            Element::Image(image) => {
+                image.path = PathBuf::from(substitute(&image.path.to_string_lossy(), record)?)
+            }
+            Element::Barcode(barcode) => barcode.data = substitute(&barcode.data, record)?,
+            Element::Qr(qr) => qr.data = substitute(&qr.data, record)?,
+            Element::Line(_) | Element::Rectangle(_) => {}
+        }
+    }
+    Ok(template)
+}
+
+/// A record that couldn't be rendered, e.g. because its barcode data is invalid
+#[derive(Debug)]
+pub struct RecordError {
+    /// Index of the record, counting from 0
+    pub record: usize,
+    pub error: MergeError,
+}
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "record {}: {}", self.record + 1, self.error)
+    }
+}
+impl std::error::Error for RecordError {}
+
+/// Labels rendered from a template and a list of records
+pub struct Merge {
+    pub media: LabelMedia,
+    /// The rendered labels, along with the index of the record each one was rendered from
+    pub pages: Vec<(usize, GrayImage)>,
+    pub errors: Vec<RecordError>,
+}
+impl Merge {
+    /// Renders one label per record. Records that fail to render are collected in `errors` and left out of `pages`;
+    /// errors that affect every record, like unknown label media, are returned immediately.
+    pub fn render(
+        renderer: &Renderer,
+        template: &Template,
+        records: impl IntoIterator<Item = Record>,
+    ) -> Result<Self> {
+        template.size()?;
+
+        let mut pages = Vec::new();
+        let mut errors = Vec::new();
+        for (index, record) in records.into_iter().enumerate() {
+            let page = fill(template, &record).and_then(|filled| Ok(renderer.render(&filled)?));
+            match page {
+                Ok(page) => pages.push((index, page)),
+                Err(error) => errors.push(RecordError {
+                    record: index,
+                    error,
+                }),
+            }
+        }
+        Ok(Self {
+            media: template.media,
+            pages,
+            errors,
+        })
+    }
+    /// Transforms the rendered labels into raster lines, one set per page
+    pub fn rasterize(&self) -> Vec<Vec<[u8; 90]>> {
+        self.pages
+            .iter()
+            .map(|(_, page)| utils::rasterize_image_to_ql_tiff(page))
+            .collect()
+    }
+    /// Prints the rendered labels as a single job. Fails without printing anything if the loaded media doesn't
+    /// match the template.
+    pub fn print<T: rusb::UsbContext>(
+        &self,
+        printer: &ThermalPrinter<T>,
+        options: &PrintOptions,
+    ) -> Result<status::Response> {
+        if self.pages.is_empty() {
+            return Err(MergeError::Empty);
+        }
+        let loaded = LabelMedia::from(&printer.current_label()?);
+        if loaded != self.media {
+            return Err(MergeError::WrongMedia(self.media, loaded));
+        }
+        Ok(printer.print_pages(&self.rasterize(), options)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::barcode::Symbology;
+    use crate::template::{BarcodeElement, Color, LineElement, QrElement, Units};
+
+    fn record(fields: &[(&str, &str)]) -> Record {
+        fields
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn template() -> Template {
+        let mut template = Template::new(
+            LabelMedia {
+                width: 62,
+                length: Some(29),
+            },
+            Units::Mm,
+        );
+        template.elements.push(Element::Barcode(BarcodeElement {
+            x: 2.0,
+            y: 2.0,
+            width: 40.0,
+            height: 15.0,
+            symbology: Symbology::Ean13,
+            data: "{{sku}}".to_string(),
+            module_width: None,
+            color: Color::Black,
+        }));
+        template.elements.push(Element::Qr(QrElement {
+            x: 44.0,
+            y: 2.0,
+            size: 12.0,
+            data: "https://example.com/{{ sku }}".to_string(),
+            error_correction: Default::default(),
+            color: Color::Black,
+        }));
+        template.elements.push(Element::Line(LineElement {
+            x1: 0.0,
+            y1: 20.0,
+            x2: 58.0,
+            y2: 20.0,
+            thickness: None,
+            color: Color::Black,
+        }));
+        template
+    }
+
+    #[test]
+    fn substitutes_placeholders() {
+        let record = record(&[("name", "Widget"), ("sku", "0123"), ("braces", "{{name}}")]);
+        let substitute = |text: &str| substitute(text, &record).unwrap();
+        assert_eq!(substitute("{{name}}"), "Widget");
+        assert_eq!(substitute("{{ name }}: {{sku}}{{sku}}"), "Widget: 01230123");
+        assert_eq!(substitute("no placeholders"), "no placeholders");
+        assert_eq!(substitute("{name} {{name"), "{name} {{name");
+        assert_eq!(substitute("{{name}} {{"), "Widget {{");
+        // Values are inserted as is, not expanded again
+        assert_eq!(substitute("{{braces}}"), "{{name}}");
+
+        assert!(matches!(
+            super::substitute("{{name}} {{price}}", &record),
+            Err(MergeError::MissingField(name)) if name == "price"
+        ));
+    }
+
+    #[test]
+    fn fills_templates() {
+        let filled = fill(&template(), &record(&[("sku", "012345678912")])).unwrap();
+        match &filled.elements[..] {
+            [Element::Barcode(barcode), Element::Qr(qr), Element::Line(_)] => {
+                assert_eq!(barcode.data, "012345678912");
+                assert_eq!(qr.data, "https://example.com/012345678912");
+            }
+            elements => panic!("unexpected elements: {:?}", elements),
+        }
+        assert!(fill(&template(), &Record::new()).is_err());
+    }
+
+    #[test]
+    fn collects_record_errors() {
+        let records = vec![
+            record(&[("sku", "012345678912")]),
+            record(&[("sku", "not a number")]),
+            record(&[]),
+            record(&[("sku", "098765432109")]),
+        ];
+        let merge = Merge::render(&Renderer::default(), &template(), records).unwrap();
+        let pages: Vec<_> = merge.pages.iter().map(|(index, _)| *index).collect();
+        assert_eq!(pages, [0, 3]);
+        let errors: Vec<_> = merge.errors.iter().map(|error| error.record).collect();
+        assert_eq!(errors, [1, 2]);
+        assert!(matches!(merge.errors[1].error, MergeError::MissingField(_)));
+        assert_eq!(merge.rasterize().len(), 2);
+        assert_eq!(
+            merge.rasterize()[0].len(),
+            merge.pages[0].1.height() as usize
+        );
+
+        let mut unknown = template();
+        unknown.media.width = 1;
+        assert!(Merge::render(&Renderer::default(), &unknown, Vec::new()).is_err());
+    }
+
+    #[cfg(feature = "template")]
+    #[test]
+    fn reads_csv() {
+        let records =
+            read_csv("name,sku\nWidget,012345678912\n\"Gadget, large\",098765432109\n".as_bytes())
+                .unwrap();
+        assert_eq!(
+            records,
+            [
+                record(&[("name", "Widget"), ("sku", "012345678912")]),
+                record(&[("name", "Gadget, large"), ("sku", "098765432109")]),
+            ]
+        );
+        assert!(read_csv("name,sku\nWidget\n".as_bytes()).is_err());
+    }
+}
@@ -1,252 +1,548 @@
 //! Easy-to-use text and image compositing and rasterization for use with Brother QL printers
 
-use crate::printer::constants::Label;
-use image::{DynamicImage, Luma};
-use rusttype::{Font, Point, Scale};
-use std::fs;
-use std::path::PathBuf;
-
-type XY<T> = Point<T>;
-
-fn calc_text_width(glyphs: &[rusttype::PositionedGlyph]) -> u32 {
-    let min_x = glyphs
-        .first()
-        .map(|g| g.pixel_bounding_box().unwrap().min.x)
-        .unwrap();
-    let max_x = glyphs
-        .last()
-        .map(|g| g.pixel_bounding_box().unwrap().max.x)
-        .unwrap();
-    (max_x - min_x) as u32
+use crate::{
+    printer::constants::{Label, WidthLength},
+    template::{
+        Color, Element, LabelMedia, RectangleElement, Renderer, Template, TextElement, Units,
+        VerticalAlignment,
+    },
+    utils,
+};
+use image::{imageops, DynamicImage, GrayImage, Luma};
+use rusttype::{point, Point, PositionedGlyph, Scale};
+use thiserror::Error;
+
+pub mod font;
+pub mod layout;
+#[cfg(feature = "shaping")]
+mod shaping;
+pub use font::{FontChain, FontRegistry, Weight};
+pub use layout::{Alignment, LayoutOptions};
+
+#[derive(Error, Debug)]
+pub enum TextError {
+    #[error("invalid font data")]
+    InvalidFont,
+    #[error("no fonts provided")]
+    NoFonts,
+}
+type Result<T> = std::result::Result<T, TextError>;
+
+/// Default length of continuous tape labels, in dots
+const CONTINUOUS_LENGTH: u32 = 750;
+
+/// Family name of the font passed to `TextRasterizer::new`
+const CUSTOM_FAMILY: &str = "Custom";
+
+/// Width of the second row on 12mm continuous tape, in dots
+const SECOND_ROW_WIDTH: u32 = 170;
+
+/// Extra width that 12mm continuous tape seems to need, in dots
+const NARROW_TAPE_EXTRA_WIDTH: u32 = 25;
+
+/// Blank space at the top of the second row on 12mm continuous tape, in dots. The black background of inverted
+/// labels reaches into it.
+const SECOND_ROW_GAP: u32 = 15;
+
+/// Where `TextRasterizer::render` draws a line of text, in the layout text labels have always had
+struct LinePlacement {
+    /// Largest font size in pixels, before `font_scale`
+    font_size: f32,
+    /// How far across the label the line is centered, as a fraction of its width
+    across: f32,
+    /// Distance that the line is moved from there, along and across the tape, in dots
+    shift: (i32, i32),
+}
+
+/// A line of text on its own
+const SINGLE_LINE: LinePlacement = LinePlacement {
+    font_size: 125.0,
+    across: 0.5,
+    shift: (-5, 0),
+};
+
+/// The primary line of two, raised to make room for the secondary line
+const PRIMARY_LINE: LinePlacement = LinePlacement {
+    font_size: 90.0,
+    across: 0.5,
+    shift: (0, -25),
+};
+
+/// The secondary line of two, raised to just above the bottom edge of the label
+const SECONDARY_LINE: LinePlacement = LinePlacement {
+    font_size: 35.0,
+    across: 1.0,
+    shift: (0, -20),
+};
+
+/// Width of a line of text in pixels, from the left of its first visible glyph to the right of its last
+fn text_width(glyphs: &[PositionedGlyph]) -> u32 {
+    let mut bounding_boxes = glyphs.iter().filter_map(|g| g.pixel_bounding_box());
+    match bounding_boxes.next() {
+        Some(first) => {
+            let last = bounding_boxes.next_back().unwrap_or(first);
+            (last.max.x - first.min.x) as u32
+        }
+        // Only whitespace
+        None => 0,
+    }
 }
 
-struct ResizedText<'a> {
-    rendered_size: XY<u32>,
-    glyphs: Vec<rusttype::PositionedGlyph<'a>>,
+/// A single line of text at the largest whole font size below the maximum that is narrower than a given width
+struct FittedLine {
+    glyphs: Vec<PositionedGlyph<'static>>,
+    width: u32,
+    height: u32,
 }
-impl<'a> ResizedText<'a> {
-    pub fn create<'b>(font: &'a Font, text: &'b str, max_width: u32, max_font_size: f32) -> Self {
-        let mut font_size = max_font_size.ceil(); // Max possible font size
-        let rendered_size;
+impl FittedLine {
+    fn new(fonts: &FontChain, text: &str, max_width: u32, max_font_size: f32) -> Self {
+        let mut font_size = max_font_size.ceil().max(1.0);
         // Scale the font size down until it all fits length-wise
-        let glyphs = loop {
+        loop {
             let scale = Scale::uniform(font_size);
-            let v_metrics = font.v_metrics(scale);
-            let glyphs: Vec<_> = font
-                .layout(
-                    text,
-                    scale,
-                    Point {
-                        x: 0.0,
-                        y: v_metrics.ascent,
-                    },
-                )
-                .collect();
-
-            let width = calc_text_width(&glyphs);
-            if width < max_width {
-                let height = (v_metrics.ascent - v_metrics.descent).ceil() as u32;
-                rendered_size = XY {
-                    x: width,
-                    y: height,
+            let v_metrics = fonts.v_metrics(scale);
+            let glyphs = fonts.layout(text, scale, point(0.0, v_metrics.ascent));
+            let width = text_width(&glyphs);
+            if width < max_width || font_size <= 1.0 {
+                return Self {
+                    glyphs,
+                    width,
+                    height: (v_metrics.ascent - v_metrics.descent).ceil() as u32,
                 };
-                break glyphs;
             }
             font_size -= 1.0;
-        };
+        }
+    }
 
-        Self {
-            rendered_size,
-            glyphs,
+    /// Draws the line where `placement` puts it on an image of a label, with the text running along its length.
+    /// Unlike `draw_glyphs`, the coverage replaces the background instead of blending with it, which is how text
+    /// labels have always been drawn.
+    fn draw(
+        &self,
+        image: &mut GrayImage,
+        (length, width): (u32, u32),
+        placement: &LinePlacement,
+        invert: bool,
+    ) {
+        let center = (length as i32 / 2, (width as f32 * placement.across) as i32);
+        let offset = (
+            center.0 - self.width as i32 / 2 + placement.shift.0,
+            center.1 - self.height as i32 / 2 + placement.shift.1,
+        );
+        for glyph in &self.glyphs {
+            if let Some(bounding_box) = glyph.pixel_bounding_box() {
+                glyph.draw(|x, y, v| {
+                    let x = x as i32 + bounding_box.min.x + offset.0;
+                    let y = y as i32 + bounding_box.min.y + offset.1;
+                    if x >= 0 && y >= 0 && (x as u32) < image.width() && (y as u32) < image.height()
+                    {
+                        let coverage = (255.0 * v) as u8;
+                        let color = if invert { coverage } else { 255 - coverage };
+                        image.put_pixel(x as u32, y as u32, Luma([color]));
+                    }
+                });
+            }
         }
     }
 }
 
 fn draw_glyphs(
-    image: &mut image::GrayImage,
+    image: &mut GrayImage,
     glyphs: &[rusttype::PositionedGlyph],
-    offset: XY<i32>,
+    offset: Point<i32>,
     invert: bool,
 ) {
+    let color = if invert { 255.0 } else { 0.0 };
     for glyph in glyphs {
         if let Some(bounding_box) = glyph.pixel_bounding_box() {
             // Draw the glyph into the image per-pixel by using the draw closure
             glyph.draw(|x, y, v| {
-                let color = if invert {
-                    (255.0 * v) as u8
-                } else {
-                    255 - (255.0 * v) as u8
-                };
-
-                image.put_pixel(
-                    // Offset the position by the glyph bounding box
-                    (x as i32 + bounding_box.min.x + offset.x) as u32,
-                    (y as i32 + bounding_box.min.y + offset.y) as u32,
-                    // Turn the coverage into an alpha value
-                    Luma([color]),
-                )
+                // Offset the position by the glyph bounding box
+                let x = x as i32 + bounding_box.min.x + offset.x;
+                let y = y as i32 + bounding_box.min.y + offset.y;
+                // Clip anything that doesn't fit on the label
+                if x >= 0 && y >= 0 && (x as u32) < image.width() && (y as u32) < image.height() {
+                    // Turn the coverage into an alpha value to blend with what's already there
+                    let pixel = image.get_pixel_mut(x as u32, y as u32);
+                    let background = f32::from(pixel[0]);
+                    pixel[0] = (background + (color - background) * v).round() as u8;
+                }
             });
         }
     }
 }
 
-pub fn image_to_raster_lines(image: &image::GrayImage, width: u32) -> Vec<[u8; 90]> {
-    let width = width as usize;
-    let line_count = image.len() / width;
-
-    // We need to sidescan this generated image for the printer
-    let mut lines = Vec::with_capacity(width);
-    for c in 0..width {
-        let mut line = [0; 90]; // Always 90 for regular sized printers like the QL-700 (with a 0x00 byte to start)
-        let mut line_byte = 0;
-        // Bit index counts backwards
-        // First nibble (bits 7 through 4) in the second byte is blank
-        let mut line_bit_index: i8 = 3;
-        for r in 0..line_count {
-            line_bit_index -= 1;
-            if line_bit_index < 0 {
-                line_byte += 1;
-                line_bit_index += 8;
+/// Sidescans an image from `TextRasterizer::render` into raster lines, one for each column. The first row of the
+/// image lines up with bit 5 of the first byte of each line, counting from the most significant bit, and rows that
+/// don't fit in a line are dropped.
+fn image_to_raster_lines(image: &GrayImage) -> Vec<[u8; 90]> {
+    const FIRST_BIT: u32 = 5;
+    let rows = image.height().min(90 * 8 - FIRST_BIT);
+    (0..image.width())
+        .map(|x| {
+            let mut line = [0; 90];
+            for y in 0..rows {
+                if image.get_pixel(x, y)[0] <= 0xFF / 2 {
+                    let bit = (FIRST_BIT + y) as usize;
+                    line[bit / 8] |= 0x80 >> (bit % 8);
+                }
             }
-            image.get_pixel(0, 0); // TODO: Get rid of this line?
-            let luma_pixel = image.get_pixel(c as u32, r as u32); // + 3 was here in TS code -- not sure if needed
-            let value: u8 = if luma_pixel[0] > 0xFF / 2 { 0 } else { 1 };
-            line[line_byte] |= value << line_bit_index;
-        }
-        lines.push(line);
-    }
-    lines
+            line
+        })
+        .collect()
 }
 
 /// Easily convert text into a raster image that can be printed by a `ThermalPrinter`
 pub struct TextRasterizer {
     label: Label,
-    font_path: PathBuf,
-    second_row_image: Option<PathBuf>,
+    fonts: FontRegistry,
+    family: Option<String>,
+    weight: Weight,
+    second_row_image: Option<GrayImage>,
 }
 impl TextRasterizer {
     /// The text rasterizer needs to know the loaded label media currently in the printer in order to resize and
-    /// shift the text content accordingly so that it will fit.
-    pub fn new(label: Label, font_path: PathBuf) -> Self {
+    /// shift the text content accordingly so that it will fit. `font_data` is the contents of a TrueType or
+    /// OpenType font file. Characters missing from the font are drawn with the embedded default font, if enabled.
+    pub fn new(label: Label, font_data: Vec<u8>) -> Result<Self> {
+        let mut fonts = FontRegistry::new();
+        fonts.add(CUSTOM_FAMILY, Weight::Regular, font_data)?;
+        #[cfg(feature = "embedded-font")]
+        fonts.add_default_family();
+        Self::with_fonts(label, fonts)
+    }
+    /// Uses the fonts in a registry, starting with its first family unless another is chosen with `set_font`
+    pub fn with_fonts(label: Label, fonts: FontRegistry) -> Result<Self> {
+        if fonts.is_empty() {
+            return Err(TextError::NoFonts);
+        }
+        Ok(Self {
+            label,
+            fonts,
+            family: None,
+            weight: Weight::Regular,
+            second_row_image: None,
+        })
+    }
+    /// Uses the embedded DejaVu Sans font
+    #[cfg(feature = "embedded-font")]
+    pub fn with_default_font(label: Label) -> Self {
         Self {
             label,
-            font_path,
+            fonts: FontRegistry::embedded(),
+            family: None,
+            weight: Weight::Regular,
             second_row_image: None,
         }
     }
+    /// Chooses the family and weight to draw text with. Characters that the family doesn't have are drawn with the
+    /// other families in the registry.
+    pub fn set_font(&mut self, family: Option<&str>, weight: Weight) {
+        self.family = family.map(str::to_string);
+        self.weight = weight;
+    }
+    fn fonts(&self) -> FontChain<'_> {
+        self.fonts
+            .chain(self.family.as_deref(), self.weight)
+            .expect("registry is not empty")
+    }
     /// Some types of label media (e.g. 12mm continuous tape) are wider than specified. Use this method to draw
     /// an image onto this second, normally out-of-bounds part of the tape. The bottom portion of the tape
     /// is usually pre-scored from the top part so consider this a way to make "bonus" labels with the same
     /// amount of physical tape.
-    pub fn set_second_row_image(&mut self, path: PathBuf) {
-        self.second_row_image = Some(path);
+    pub fn set_second_row_image(&mut self, image: DynamicImage) {
+        self.second_row_image = Some(image.to_luma8());
     }
-    /// Transforms text into a raster image ready to send to the `ThermalPrinter`. Typically, the text will appear
-    /// as black on a white background. Enable the `invert` flag to print white text on a black background. Note that
-    /// since the label is white, a faint border of white will still surround the label in areas that the printer
-    /// cannot print the black background.
-    pub fn rasterize(
+    /// Renders text onto an image of the label, with the text running along the length of the tape. The image is
+    /// as long as the label and covers the printable width plus the right margin, and on 12mm tape the second row.
+    /// Typically, the text will appear as black on a white background. Enable the `invert` flag to print white text
+    /// on a black background. Note that since the label is white, a faint border of white will still surround the
+    /// label in areas that the printer cannot print the black background.
+    pub fn render(
         &self,
         text: &str,
         secondary_text: Option<&str>,
         font_scale: f32,
         invert: bool,
-    ) -> Vec<[u8; 90]> {
-        let font_data = fs::read(&self.font_path).expect("Invalid font path");
-        let font: Font<'static> = Font::from_bytes(font_data).unwrap();
-
-        let mut length = 750;
-        let mut width;
-        let mut secondary_width = 0;
-
-        if self.label.tape_size.1 == 0 {
+    ) -> GrayImage {
+        let top_width = self.label.dots_printable.0 + u32::from(self.label.right_margin);
+        let (width, length, secondary_width) = match self.label.tape_size {
+            // 12mm labels have a second label below the primary that can actually be used
+            WidthLength(12, 0) => (
+                top_width + NARROW_TAPE_EXTRA_WIDTH,
+                CONTINUOUS_LENGTH,
+                match self.second_row_image {
+                    Some(_) => SECOND_ROW_WIDTH,
+                    None => 0,
+                },
+            ),
             // Continuous tape
-            width = self.label.dots_printable.0 + self.label.right_margin as u32;
-
-            if self.label.tape_size.0 == 12 {
-                // 12mm label seems to need this for some reason
-                width += 25;
-                // 12mm labels have a second label below the primary that can actually be used
-                if self.second_row_image.is_some() {
-                    secondary_width = 170;
-                }
-            }
-        } else {
+            WidthLength(_, 0) => (top_width, CONTINUOUS_LENGTH, 0),
             // Die cut labels
-            width = self.label.dots_printable.0 + self.label.right_margin as u32;
-            length = self.label.dots_printable.1;
-        }
+            _ => (top_width, self.label.dots_printable.1, 0),
+        };
 
-        let mut image = DynamicImage::new_luma8(length, width + secondary_width).to_luma8();
-        // Set image background
-        for (_x, y, pixel) in image.enumerate_pixels_mut() {
-            if invert {
-                let top_label_size =
-                    self.label.dots_printable.0 + self.label.right_margin as u32 + 15;
-                if y > top_label_size {
-                    *pixel = Luma([255]); // Set to white
-                } else {
-                    *pixel = Luma([0]); // Set to black
-                }
+        // Set image background, leaving the second row white
+        let mut image = GrayImage::from_fn(length, width + secondary_width, |_x, y| {
+            if invert && y <= top_width + SECOND_ROW_GAP {
+                Luma([0])
             } else {
-                *pixel = Luma([255]); // Set to white
+                Luma([255])
             }
-        }
+        });
 
+        let fonts = self.fonts();
+        let mut draw_line = |text: &str, placement: &LinePlacement| {
+            let line = FittedLine::new(&fonts, text, length, placement.font_size * font_scale);
+            line.draw(&mut image, (length, width), placement, invert);
+        };
         match secondary_text {
             Some(secondary_text) => {
-                let primary = ResizedText::create(&font, text, length, 90.0 * font_scale);
-                let secondary =
-                    ResizedText::create(&font, secondary_text, length, 35.0 * font_scale);
-
-                let primary_offset = XY {
-                    x: (length as i32 / 2) - (primary.rendered_size.x as i32 / 2),
-                    y: (width as i32 / 2) - (primary.rendered_size.y as i32 / 2) - 25,
-                };
-                let secondary_offset = XY {
-                    x: (length as i32 / 2) - (secondary.rendered_size.x as i32 / 2),
-                    y: (width as i32 / 1) - (secondary.rendered_size.y as i32 / 2) - 20,
-                };
-                draw_glyphs(&mut image, &primary.glyphs, primary_offset, invert);
-                draw_glyphs(&mut image, &secondary.glyphs, secondary_offset, invert);
-            }
-            None => {
-                let primary = ResizedText::create(&font, text, length, 125.0 * font_scale);
-
-                let offset = XY {
-                    x: (length as i32 / 2) - (primary.rendered_size.x as i32 / 2) - 5,
-                    y: (width as i32 / 2) - (primary.rendered_size.y as i32 / 2),
-                };
-
-                draw_glyphs(&mut image, &primary.glyphs, offset, invert);
+                draw_line(text, &PRIMARY_LINE);
+                draw_line(secondary_text, &SECONDARY_LINE);
             }
+            None => draw_line(text, &SINGLE_LINE),
         }
 
-        if let Some(image_path) = &self.second_row_image {
-            let overlay = image::open(image_path).unwrap().to_luma8();
-
-            let top_margin = 15;
+        if let (Some(overlay), true) = (&self.second_row_image, secondary_width > 0) {
             let ratio = overlay.width() as f32 / overlay.height() as f32;
 
             let mut new_width: u32 = length;
             let mut new_height: u32 = (new_width as f32 / ratio) as u32;
-            if new_height > secondary_width - top_margin {
-                new_height = secondary_width - top_margin;
+            if new_height > secondary_width - SECOND_ROW_GAP {
+                new_height = secondary_width - SECOND_ROW_GAP;
                 new_width = (new_height as f32 * ratio) as u32;
             }
-            let resized = image::imageops::resize(
-                &overlay,
+            let resized = imageops::resize(
+                overlay,
                 new_width,
                 new_height,
-                image::imageops::FilterType::Triangle,
+                imageops::FilterType::Triangle,
+            );
+            imageops::overlay(
+                &mut image,
+                &resized,
+                i64::from((length - new_width) / 2),
+                i64::from(width),
             );
-            image::imageops::overlay(&mut image, &resized, (length - new_width) / 2, width);
         }
 
-        // Save the image to a png file if debug mode is enabled
-        if cfg!(debug_assertions) {
-            image.save("render.png").unwrap();
+        image
+    }
+    /// Transforms text into raster lines ready to send with `ThermalPrinter::print_lines`. See `render` for the
+    /// meaning of the arguments.
+    pub fn rasterize(
+        &self,
+        text: &str,
+        secondary_text: Option<&str>,
+        font_scale: f32,
+        invert: bool,
+    ) -> Vec<[u8; 90]> {
+        image_to_raster_lines(&self.render(text, secondary_text, font_scale, invert))
+    }
+    /// Wraps text across the width of the label, with lines running across the tape like a page of text. Die-cut
+    /// labels shrink the text as needed to fit it onto the label, while continuous tape is cut to the length of the
+    /// text.
+    pub fn render_paragraph(&self, text: &str, options: &LayoutOptions, invert: bool) -> GrayImage {
+        let template = self.paragraph_template(text, options, invert);
+        Renderer::with_fonts(self.fonts.clone())
+            .render(&template)
+            .expect("paragraph templates fit their label")
+    }
+    /// A template of the whole label with a single text box, see `render_paragraph`
+    pub fn paragraph_template(
+        &self,
+        text: &str,
+        options: &LayoutOptions,
+        invert: bool,
+    ) -> Template {
+        let width = self.label.dots_printable.0;
+        let length = match self.label.tape_size.1 {
+            0 => layout::layout(&self.fonts(), text, width, None, options).height,
+            _ => self.label.dots_printable.1,
+        }
+        .max(1);
+
+        let mut template = Template::new(LabelMedia::from(&self.label), Units::Dots);
+        template.length = Some(length as f32);
+        if invert {
+            template.elements.push(Element::Rectangle(RectangleElement {
+                x: 0.0,
+                y: 0.0,
+                width: width as f32,
+                height: length as f32,
+                outline: None,
+                color: Color::Black,
+            }));
+        }
+        template.elements.push(Element::Text(TextElement {
+            font_size: Some(options.font_size),
+            min_font_size: Some(options.min_font_size),
+            line_spacing: options.line_spacing,
+            align: options.alignment,
+            // Centered on die-cut labels
+            vertical_align: VerticalAlignment::Center,
+            max_lines: options.max_lines,
+            wrap: options.wrap,
+            family: self.family.clone(),
+            bold: self.weight == Weight::Bold,
+            color: if invert { Color::White } else { Color::Black },
+            ..TextElement::new(0.0, 0.0, width as f32, length as f32, text)
+        }));
+        template
+    }
+    /// Transforms wrapped text into raster lines ready to send with `ThermalPrinter::print_lines`. See
+    /// `render_paragraph`.
+    pub fn rasterize_paragraph(
+        &self,
+        text: &str,
+        options: &LayoutOptions,
+        invert: bool,
+    ) -> Vec<[u8; 90]> {
+        utils::rasterize_image_to_ql_tiff(&self.render_paragraph(text, options, invert))
+    }
+}
+
+#[cfg(all(test, feature = "embedded-font"))]
+mod tests {
+    use super::*;
+    use crate::printer::constants;
+    use rusttype::Font;
+
+    /// `TextRasterizer::rasterize` as it was before fonts were registered, for a single line of text
+    fn original_rasterize(label: &Label, font: &Font, text: &str, invert: bool) -> Vec<[u8; 90]> {
+        let mut length = 750;
+        let mut width = label.dots_printable.0 + label.right_margin as u32;
+        if label.tape_size.1 == 0 {
+            if label.tape_size.0 == 12 {
+                width += NARROW_TAPE_EXTRA_WIDTH;
+            }
+        } else {
+            length = label.dots_printable.1;
+        }
+
+        let mut image = GrayImage::new(length, width);
+        for (_x, y, pixel) in image.enumerate_pixels_mut() {
+            let top_label_size =
+                label.dots_printable.0 + label.right_margin as u32 + SECOND_ROW_GAP;
+            *pixel = Luma([if invert && y <= top_label_size {
+                0
+            } else {
+                255
+            }]);
+        }
+
+        let mut font_size = 125.0_f32;
+        let (glyphs, rendered_width, rendered_height) = loop {
+            let scale = Scale::uniform(font_size);
+            let v_metrics = font.v_metrics(scale);
+            let glyphs: Vec<_> = font
+                .layout(text, scale, point(0.0, v_metrics.ascent))
+                .collect();
+            let min_x = glyphs.first().unwrap().pixel_bounding_box().unwrap().min.x;
+            let max_x = glyphs.last().unwrap().pixel_bounding_box().unwrap().max.x;
+            let width = (max_x - min_x) as u32;
+            if width < length {
+                let height = (v_metrics.ascent - v_metrics.descent).ceil() as u32;
+                break (glyphs, width, height);
+            }
+            font_size -= 1.0;
+        };
+        let offset_x = (length as i32 / 2) - (rendered_width as i32 / 2) - 5;
+        let offset_y = (width as i32 / 2) - (rendered_height as i32 / 2);
+        for glyph in &glyphs {
+            if let Some(bounding_box) = glyph.pixel_bounding_box() {
+                glyph.draw(|x, y, v| {
+                    let color = if invert {
+                        (255.0 * v) as u8
+                    } else {
+                        255 - (255.0 * v) as u8
+                    };
+                    let x = (x as i32 + bounding_box.min.x + offset_x) as u32;
+                    let y = (y as i32 + bounding_box.min.y + offset_y) as u32;
+                    // Clipped, where the original panicked
+                    if x < image.width() && y < image.height() {
+                        image.put_pixel(x, y, Luma([color]))
+                    }
+                });
+            }
+        }
+
+        let mut lines = Vec::with_capacity(length as usize);
+        for c in 0..length {
+            let mut line = [0; 90];
+            let mut line_byte = 0;
+            let mut line_bit_index: i8 = 3;
+            for r in 0..width {
+                line_bit_index -= 1;
+                if line_bit_index < 0 {
+                    line_byte += 1;
+                    line_bit_index += 8;
+                }
+                let value: u8 = if image.get_pixel(c, r)[0] > 0xFF / 2 {
+                    0
+                } else {
+                    1
+                };
+                line[line_byte] |= value << line_bit_index;
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    #[test]
+    fn renders_paragraphs_from_templates() {
+        let text = "A paragraph long enough to wrap across several lines of the label";
+        let options = LayoutOptions::default();
+        for (width, length) in [(62, None), (29, Some(90))] {
+            let label = constants::label_data(width, length).unwrap();
+            let rasterizer = TextRasterizer::with_default_font(label);
+            for invert in [false, true] {
+                // The layout drawn straight onto the label, centered on die-cut labels
+                let max_length = length.map(|_| label.dots_printable.1);
+                let layout = layout::layout(
+                    &rasterizer.fonts(),
+                    text,
+                    label.dots_printable.0,
+                    max_length,
+                    &options,
+                );
+                let length = max_length.unwrap_or(layout.height);
+                let background = if invert { Luma([0]) } else { Luma([255]) };
+                let mut expected =
+                    GrayImage::from_pixel(label.dots_printable.0, length, background);
+                let top = (length - layout.height) as f32 / 2.0;
+                layout.draw(&mut expected, (0, top.round() as i32), invert);
+
+                let image = rasterizer.render_paragraph(text, &options, invert);
+                assert!(
+                    image == expected,
+                    "{}x{:?}mm, invert: {}",
+                    width,
+                    length,
+                    invert
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rasterizes_like_the_original() {
+        let font = Font::try_from_bytes(dejavu::sans::regular()).unwrap();
+        for (width, length) in [(62, None), (12, None), (29, Some(90))] {
+            let label = constants::label_data(width, length).unwrap();
+            let rasterizer = TextRasterizer::with_default_font(label);
+            for invert in [false, true] {
+                assert!(
+                    rasterizer.rasterize("Hello, World 123", None, 1.0, invert)
+                        == original_rasterize(&label, &font, "Hello, World 123", invert),
+                    "{}x{:?}mm, invert: {}",
+                    width,
+                    length,
+                    invert
+                );
+            }
         }
-        image_to_raster_lines(&image, length)
     }
 }
@@ -0,0 +1,351 @@
+//! Loading fonts once and choosing between them for each character
+//!
+//! A `FontRegistry` holds families of regular and bold faces. The families form a fallback chain in the order they
+//! were added, so characters missing from the preferred family (e.g. CJK, emoji, or accented letters) are drawn
+//! with the first family that has them.
+
+use std::ptr;
+#[cfg(feature = "shaping")]
+use std::sync::Arc;
+
+use rusttype::{point, Font, GlyphId, Point, PositionedGlyph, Scale, VMetrics};
+
+#[cfg(feature = "shaping")]
+use super::shaping::ShapingFace;
+use super::{Result, TextError};
+
+/// Name of the family added by `FontRegistry::add_default_family`
+#[cfg(feature = "embedded-font")]
+pub const DEFAULT_FAMILY: &str = "DejaVu Sans";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Weight {
+    #[default]
+    Regular,
+    Bold,
+}
+
+/// A font in a registry, parsed for shaping once when it is added
+#[derive(Clone)]
+struct Face {
+    font: Font<'static>,
+    #[cfg(feature = "shaping")]
+    shaping: Arc<ShapingFace>,
+}
+impl Face {
+    fn new(font: Font<'static>) -> Self {
+        Self {
+            #[cfg(feature = "shaping")]
+            shaping: Arc::new(ShapingFace::parse(font.clone())),
+            font,
+        }
+    }
+    fn chain_font(&self) -> ChainFont<'_> {
+        ChainFont {
+            font: &self.font,
+            #[cfg(feature = "shaping")]
+            shaping: Some(&self.shaping),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Family {
+    name: String,
+    regular: Option<Face>,
+    bold: Option<Face>,
+}
+impl Family {
+    /// The face of the requested weight, or the other face if the family doesn't have it
+    fn face(&self, weight: Weight) -> Option<&Face> {
+        match weight {
+            Weight::Regular => self.regular.as_ref().or(self.bold.as_ref()),
+            Weight::Bold => self.bold.as_ref().or(self.regular.as_ref()),
+        }
+    }
+}
+
+/// Parsed fonts, grouped into families that make up a fallback chain. Cloning a registry is cheap and doesn't
+/// parse the fonts again.
+#[derive(Clone, Default)]
+pub struct FontRegistry {
+    families: Vec<Family>,
+}
+impl FontRegistry {
+    /// An empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// A registry containing only the embedded default font
+    #[cfg(feature = "embedded-font")]
+    pub fn embedded() -> Self {
+        let mut registry = Self::new();
+        registry.add_default_family();
+        registry
+    }
+    /// Adds the embedded DejaVu Sans family to the end of the fallback chain
+    #[cfg(feature = "embedded-font")]
+    pub fn add_default_family(&mut self) {
+        self.add_static(DEFAULT_FAMILY, Weight::Regular, dejavu::sans::regular())
+            .expect("embedded font is valid");
+        self.add_static(DEFAULT_FAMILY, Weight::Bold, dejavu::sans::bold())
+            .expect("embedded font is valid");
+    }
+    /// Adds a face to a family from the contents of a TrueType or OpenType font file. New families are added to the
+    /// end of the fallback chain. Adding a face that the family already has replaces it.
+    pub fn add(&mut self, family: &str, weight: Weight, data: Vec<u8>) -> Result<()> {
+        let font = Font::try_from_vec(data).ok_or(TextError::InvalidFont)?;
+        self.insert(family, weight, font);
+        Ok(())
+    }
+    /// Same as `add`, for font data that is compiled into the program
+    pub fn add_static(&mut self, family: &str, weight: Weight, data: &'static [u8]) -> Result<()> {
+        let font = Font::try_from_bytes(data).ok_or(TextError::InvalidFont)?;
+        self.insert(family, weight, font);
+        Ok(())
+    }
+    fn insert(&mut self, family: &str, weight: Weight, font: Font<'static>) {
+        let index = match self.families.iter().position(|f| f.name == family) {
+            Some(index) => index,
+            None => {
+                self.families.push(Family {
+                    name: family.to_string(),
+                    regular: None,
+                    bold: None,
+                });
+                self.families.len() - 1
+            }
+        };
+        let face = match weight {
+            Weight::Regular => &mut self.families[index].regular,
+            Weight::Bold => &mut self.families[index].bold,
+        };
+        *face = Some(Face::new(font));
+    }
+    pub fn is_empty(&self) -> bool {
+        self.families.is_empty()
+    }
+    /// Family names in fallback order
+    pub fn families(&self) -> impl Iterator<Item = &str> {
+        self.families.iter().map(|f| f.name.as_str())
+    }
+    /// The face of a family with the requested weight, or its other face if it doesn't have one
+    pub fn get(&self, family: &str, weight: Weight) -> Option<&Font<'static>> {
+        self.families
+            .iter()
+            .find(|f| f.name == family)
+            .and_then(|f| f.face(weight))
+            .map(|face| &face.font)
+    }
+    /// The fonts to draw text with, starting with `family` (or else the first family) and falling back through the
+    /// rest of the families in order. Returns `None` if the registry is empty.
+    pub fn chain(&self, family: Option<&str>, weight: Weight) -> Option<FontChain<'_>> {
+        let preferred = family.and_then(|family| self.families.iter().find(|f| f.name == family));
+        let fonts: Vec<_> = preferred
+            .into_iter()
+            .chain(
+                self.families
+                    .iter()
+                    .filter(|f| !preferred.is_some_and(|p| ptr::eq(*f, p))),
+            )
+            .filter_map(|f| f.face(weight))
+            .map(Face::chain_font)
+            .collect();
+        if fonts.is_empty() {
+            return None;
+        }
+        Some(FontChain { fonts })
+    }
+}
+
+/// A font in a chain, along with the font parsed for shaping if it comes from a registry
+#[derive(Clone, Copy)]
+pub(super) struct ChainFont<'a> {
+    pub(super) font: &'a Font<'static>,
+    #[cfg(feature = "shaping")]
+    pub(super) shaping: Option<&'a ShapingFace>,
+}
+impl<'a> From<&'a Font<'static>> for ChainFont<'a> {
+    fn from(font: &'a Font<'static>) -> Self {
+        Self {
+            font,
+            #[cfg(feature = "shaping")]
+            shaping: None,
+        }
+    }
+}
+
+/// An ordered list of fonts, where each character is drawn with the first font that has a glyph for it. Fonts
+/// from a `FontRegistry` are only parsed for shaping once, while fonts added with `new` and `with_fallback` are
+/// parsed again each time text is laid out.
+#[derive(Clone)]
+pub struct FontChain<'a> {
+    fonts: Vec<ChainFont<'a>>,
+}
+impl<'a> FontChain<'a> {
+    /// A chain starting with `primary`, which is also used for characters that no font has
+    pub fn new(primary: &'a Font<'static>) -> Self {
+        Self {
+            fonts: vec![primary.into()],
+        }
+    }
+    /// Adds a font to the end of the chain
+    pub fn with_fallback(mut self, font: &'a Font<'static>) -> Self {
+        self.fonts.push(font.into());
+        self
+    }
+    pub fn primary(&self) -> &'a Font<'static> {
+        self.fonts[0].font
+    }
+    /// The first font that has a glyph for `c`
+    pub fn font_for(&self, c: char) -> &'a Font<'static> {
+        self.chain_font_for(c).font
+    }
+    pub(super) fn chain_font_for(&self, c: char) -> ChainFont<'a> {
+        self.fonts
+            .iter()
+            .copied()
+            .find(|font| font.font.glyph(c).id() != GlyphId(0))
+            .unwrap_or(self.fonts[0])
+    }
+    /// Vertical metrics of the primary font, which are used for line heights
+    pub fn v_metrics(&self, scale: Scale) -> VMetrics {
+        self.primary().v_metrics(scale)
+    }
+    /// Lays out a single line of text with its baseline starting at `start`
+    pub fn layout(
+        &self,
+        text: &str,
+        scale: Scale,
+        start: Point<f32>,
+    ) -> Vec<PositionedGlyph<'static>> {
+        self.layout_with_end(text, scale, start).0
+    }
+    /// Width of a single line of text in pixels
+    pub fn measure(&self, text: &str, scale: Scale) -> f32 {
+        self.layout_with_end(text, scale, point(0.0, 0.0)).1
+    }
+    #[cfg(feature = "shaping")]
+    fn layout_with_end(
+        &self,
+        text: &str,
+        scale: Scale,
+        start: Point<f32>,
+    ) -> (Vec<PositionedGlyph<'static>>, f32) {
+        super::shaping::layout(self, text, scale, start)
+    }
+    /// Positions characters one after another from left to right. Kerning is applied between consecutive
+    /// characters drawn with the same font.
+    #[cfg(not(feature = "shaping"))]
+    fn layout_with_end(
+        &self,
+        text: &str,
+        scale: Scale,
+        start: Point<f32>,
+    ) -> (Vec<PositionedGlyph<'static>>, f32) {
+        let mut glyphs = Vec::with_capacity(text.len());
+        let mut x = start.x;
+        let mut previous: Option<(&Font<'static>, GlyphId)> = None;
+        for c in text.chars().filter(|c| !c.is_control()) {
+            let font = self.font_for(c);
+            let glyph = font.glyph(c).scaled(scale);
+            if let Some((previous_font, previous_id)) = previous {
+                if ptr::eq(previous_font, font) {
+                    x += font.pair_kerning(scale, previous_id, glyph.id());
+                }
+            }
+            previous = Some((font, glyph.id()));
+            let advance = glyph.h_metrics().advance_width;
+            glyphs.push(glyph.positioned(point(x, start.y)));
+            x += advance;
+        }
+        (glyphs, x)
+    }
+}
+impl<'a> From<&'a Font<'static>> for FontChain<'a> {
+    fn from(font: &'a Font<'static>) -> Self {
+        Self::new(font)
+    }
+}
+
+#[cfg(all(test, feature = "embedded-font"))]
+mod tests {
+    use super::*;
+
+    /// DejaVu Sans ExtraLight, which is missing e.g. '★'
+    fn light() -> &'static [u8] {
+        dejavu::sans::extra_light()
+    }
+
+    fn registry() -> FontRegistry {
+        let mut registry = FontRegistry::new();
+        registry
+            .add_static("Light", Weight::Regular, light())
+            .unwrap();
+        registry.add_default_family();
+        registry
+    }
+
+    #[test]
+    fn registers_families() {
+        let mut registry = registry();
+        assert_eq!(
+            registry.families().collect::<Vec<_>>(),
+            ["Light", DEFAULT_FAMILY]
+        );
+
+        // Replacing a face keeps the family in place
+        registry
+            .add("Light", Weight::Regular, light().to_vec())
+            .unwrap();
+        assert_eq!(registry.families().count(), 2);
+
+        // Families without a face of the requested weight use their other face
+        let regular = registry.get("Light", Weight::Regular).unwrap();
+        assert!(ptr::eq(
+            registry.get("Light", Weight::Bold).unwrap(),
+            regular
+        ));
+        let bold = registry.get(DEFAULT_FAMILY, Weight::Bold).unwrap();
+        assert!(!ptr::eq(
+            bold,
+            registry.get(DEFAULT_FAMILY, Weight::Regular).unwrap()
+        ));
+        assert!(registry.get("Missing", Weight::Regular).is_none());
+
+        assert!(matches!(
+            registry.add("Broken", Weight::Regular, b"not a font".to_vec()),
+            Err(TextError::InvalidFont)
+        ));
+        assert_eq!(registry.families().count(), 2);
+        assert!(FontRegistry::new().chain(None, Weight::Regular).is_none());
+    }
+
+    #[test]
+    fn falls_back_per_character() {
+        let registry = registry();
+        let light = registry.get("Light", Weight::Regular).unwrap();
+        let sans = registry.get(DEFAULT_FAMILY, Weight::Regular).unwrap();
+
+        let chain = registry.chain(None, Weight::Regular).unwrap();
+        assert!(ptr::eq(chain.primary(), light));
+        assert!(ptr::eq(chain.font_for('a'), light));
+        assert!(ptr::eq(chain.font_for('★'), sans));
+        // Characters that no font has are drawn with the primary font
+        assert!(ptr::eq(chain.font_for('\u{10FFFD}'), light));
+
+        let chain = registry
+            .chain(Some(DEFAULT_FAMILY), Weight::Regular)
+            .unwrap();
+        assert!(ptr::eq(chain.primary(), sans));
+        assert!(ptr::eq(chain.font_for('a'), sans));
+        let chain = registry.chain(Some("Missing"), Weight::Regular).unwrap();
+        assert!(ptr::eq(chain.primary(), light));
+
+        let chain = FontChain::new(light).with_fallback(sans);
+        assert!(ptr::eq(chain.font_for('★'), sans));
+        let scale = Scale::uniform(40.0);
+        assert!(chain.measure("a★", scale) > chain.measure("a", scale));
+        assert_eq!(chain.layout("a★", scale, point(0.0, 0.0)).len(), 2);
+    }
+}
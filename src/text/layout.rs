@@ -0,0 +1,376 @@
+//! Wrapping and fitting of multi-line text into a box on the label
+
+use rusttype::{point, PositionedGlyph, Scale};
+#[cfg(feature = "template")]
+use serde::{Deserialize, Serialize};
+
+use super::font::FontChain;
+
+/// Appended to the last line when text is cut off by `LayoutOptions::max_lines`
+const ELLIPSIS: char = '…';
+
+/// Horizontal alignment of each line of text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "template", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "template", serde(rename_all = "snake_case"))]
+pub enum Alignment {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// How text is laid out by `layout`
+#[derive(Debug, Clone, Copy)]
+pub struct LayoutOptions {
+    /// Largest font size to use, in pixels
+    pub font_size: f32,
+    /// Smallest font size that `layout` shrinks text to while trying to make it fit, in pixels
+    pub min_font_size: f32,
+    /// Multiple of the font's natural line height between baselines
+    pub line_spacing: f32,
+    pub alignment: Alignment,
+    /// Cut off text after this many lines, ending the last one with an ellipsis
+    pub max_lines: Option<usize>,
+    /// Wrap paragraphs at whitespace. Otherwise each paragraph stays on one line and the font shrinks until it fits.
+    pub wrap: bool,
+}
+impl Default for LayoutOptions {
+    fn default() -> Self {
+        Self {
+            font_size: 90.0,
+            min_font_size: 20.0,
+            line_spacing: 1.0,
+            alignment: Alignment::Left,
+            max_lines: None,
+            wrap: true,
+        }
+    }
+}
+
+/// A line of text after wrapping
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub text: String,
+    /// Width in pixels
+    pub width: u32,
+}
+
+/// Text that has been wrapped, sized, and positioned by `layout`
+pub struct TextLayout {
+    pub lines: Vec<Line>,
+    /// Font size that was chosen, in pixels
+    pub font_size: f32,
+    /// Width of the box the text was laid out in, in pixels
+    pub width: u32,
+    /// Height of the laid out text, in pixels
+    pub height: u32,
+    /// Whether the text still overflows its box at the minimum font size
+    pub overflows: bool,
+    glyphs: Vec<PositionedGlyph<'static>>,
+}
+impl TextLayout {
+    /// Glyphs positioned relative to the top left corner of the box
+    pub fn glyphs(&self) -> &[PositionedGlyph<'static>] {
+        &self.glyphs
+    }
+    /// Draws the text onto an image with the top left corner of the box at `offset`
+    pub fn draw(&self, image: &mut image::GrayImage, offset: (i32, i32), invert: bool) {
+        super::draw_glyphs(image, &self.glyphs, point(offset.0, offset.1), invert);
+    }
+}
+
+struct Wrapped {
+    lines: Vec<Line>,
+    /// A word was too long for a line by itself and had to be split
+    split_words: bool,
+}
+
+/// Greedily wraps each paragraph of `text` to `max_width`, splitting words that don't fit on a line by themselves
+fn wrap(fonts: &FontChain, scale: Scale, text: &str, max_width: u32) -> Wrapped {
+    let max_width = max_width as f32;
+    let fits = |s: &str| fonts.measure(s, scale) <= max_width;
+    let mut lines = Vec::new();
+    let mut split_words = false;
+
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() {
+                word.to_string()
+            } else {
+                format!("{line} {word}")
+            };
+            if fits(&candidate) {
+                line = candidate;
+                continue;
+            }
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            if fits(word) {
+                line = word.to_string();
+                continue;
+            }
+            // Split the word wherever it overflows, keeping at least one character per line
+            split_words = true;
+            for c in word.chars() {
+                line.push(c);
+                if !fits(&line) && line.chars().count() > 1 {
+                    line.pop();
+                    lines.push(std::mem::replace(&mut line, c.to_string()));
+                }
+            }
+        }
+        lines.push(line);
+    }
+
+    Wrapped {
+        lines: lines
+            .into_iter()
+            .map(|text| Line {
+                width: fonts.measure(&text, scale).ceil() as u32,
+                text,
+            })
+            .collect(),
+        split_words,
+    }
+}
+
+/// Cuts `lines` off after `max_lines`, ending the last line with an ellipsis that still fits in `max_width`
+fn truncate(
+    fonts: &FontChain,
+    scale: Scale,
+    lines: &mut Vec<Line>,
+    max_lines: usize,
+    max_width: u32,
+) {
+    if lines.len() <= max_lines {
+        return;
+    }
+    lines.truncate(max_lines);
+    if let Some(last) = lines.last_mut() {
+        let mut text = last.text.trim_end().to_string();
+        loop {
+            let candidate = format!("{text}{ELLIPSIS}");
+            let width = fonts.measure(&candidate, scale);
+            if width <= max_width as f32 || text.is_empty() {
+                *last = Line {
+                    width: width.ceil() as u32,
+                    text: candidate,
+                };
+                break;
+            }
+            text.pop();
+            text.truncate(text.trim_end().len());
+        }
+    }
+}
+
+/// Wraps text to `max_width` pixels and positions it according to `options`. Lines break at newlines in `text` and,
+/// if `options.wrap` is set, at whitespace.
+///
+/// The font size starts at `options.font_size` and shrinks down to `options.min_font_size` until all lines fit
+/// within `max_width` without splitting words and, if `max_height` is given, until all lines fit within it. Text
+/// that still doesn't fit is marked with `TextLayout::overflows`.
+pub fn layout(
+    fonts: &FontChain,
+    text: &str,
+    max_width: u32,
+    max_height: Option<u32>,
+    options: &LayoutOptions,
+) -> TextLayout {
+    let min_font_size = options.min_font_size.max(1.0);
+    let mut font_size = options.font_size.max(min_font_size);
+
+    loop {
+        let scale = Scale::uniform(font_size);
+        let v_metrics = fonts.v_metrics(scale);
+        let text_height = v_metrics.ascent - v_metrics.descent;
+        let line_height = (text_height + v_metrics.line_gap) * options.line_spacing;
+
+        let Wrapped {
+            mut lines,
+            split_words,
+        } = if options.wrap {
+            wrap(fonts, scale, text, max_width)
+        } else {
+            Wrapped {
+                lines: text
+                    .lines()
+                    .map(|line| Line {
+                        width: fonts.measure(line, scale).ceil() as u32,
+                        text: line.to_string(),
+                    })
+                    .collect(),
+                split_words: false,
+            }
+        };
+        if let Some(max_lines) = options.max_lines {
+            truncate(fonts, scale, &mut lines, max_lines.max(1), max_width);
+        }
+        let height = match lines.len() {
+            0 => 0.0,
+            n => line_height * (n - 1) as f32 + text_height,
+        }
+        .ceil() as u32;
+
+        let too_tall = max_height.is_some_and(|max_height| height > max_height);
+        let too_wide = lines.iter().any(|l| l.width > max_width);
+        if (too_tall || too_wide || split_words) && font_size > min_font_size {
+            font_size = (font_size - 1.0).max(min_font_size);
+            continue;
+        }
+
+        let glyphs = lines
+            .iter()
+            .enumerate()
+            .flat_map(|(i, line)| {
+                let x = match options.alignment {
+                    Alignment::Left => 0,
+                    Alignment::Center => max_width.saturating_sub(line.width) / 2,
+                    Alignment::Right => max_width.saturating_sub(line.width),
+                };
+                let baseline = v_metrics.ascent + line_height * i as f32;
+                fonts.layout(&line.text, scale, point(x as f32, baseline))
+            })
+            .collect();
+
+        return TextLayout {
+            lines,
+            font_size,
+            width: max_width,
+            height,
+            overflows: too_tall || too_wide,
+            glyphs,
+        };
+    }
+}
+
+#[cfg(all(test, feature = "embedded-font"))]
+mod tests {
+    use super::*;
+    use crate::text::{FontRegistry, Weight};
+
+    fn texts(layout: &TextLayout) -> Vec<&str> {
+        layout.lines.iter().map(|line| line.text.as_str()).collect()
+    }
+
+    /// Left edge of the first glyph with an outline
+    fn left(layout: &TextLayout) -> i32 {
+        layout
+            .glyphs()
+            .iter()
+            .filter_map(|glyph| glyph.pixel_bounding_box())
+            .map(|bounds| bounds.min.x)
+            .min()
+            .unwrap()
+    }
+
+    #[test]
+    fn wraps_at_whitespace() {
+        let registry = FontRegistry::embedded();
+        let fonts = registry.chain(None, Weight::Regular).unwrap();
+        let options = LayoutOptions {
+            font_size: 40.0,
+            min_font_size: 40.0,
+            ..LayoutOptions::default()
+        };
+        let text = "the quick brown fox jumps over the lazy dog\nagain";
+        let laid_out = layout(&fonts, text, 300, None, &options);
+        assert!(laid_out.lines.len() > 2);
+        assert!(laid_out.lines.iter().all(|line| line.width <= 300));
+        assert_eq!(texts(&laid_out).join(" "), text.replace('\n', " "));
+        assert_eq!(texts(&laid_out).last(), Some(&"again"));
+        assert!(!laid_out.overflows);
+
+        // Without wrapping, each paragraph stays on one line even if it doesn't fit
+        let laid_out = layout(
+            &fonts,
+            text,
+            300,
+            None,
+            &LayoutOptions {
+                wrap: false,
+                ..options
+            },
+        );
+        assert_eq!(texts(&laid_out), text.lines().collect::<Vec<_>>());
+        assert!(laid_out.overflows);
+    }
+
+    #[test]
+    fn shrinks_to_fit() {
+        let registry = FontRegistry::embedded();
+        let fonts = registry.chain(None, Weight::Regular).unwrap();
+        let options = LayoutOptions {
+            font_size: 100.0,
+            min_font_size: 10.0,
+            ..LayoutOptions::default()
+        };
+        let laid_out = layout(&fonts, "one two three four", 400, Some(100), &options);
+        assert!(laid_out.font_size < 100.0);
+        assert!(laid_out.height <= 100);
+        assert!(!laid_out.overflows);
+
+        // Words are only split once the font can't get any smaller
+        let laid_out = layout(&fonts, "unbreakable", 100, None, &options);
+        assert_eq!(laid_out.lines.len(), 1);
+        let options = LayoutOptions {
+            min_font_size: 60.0,
+            ..options
+        };
+        let laid_out = layout(&fonts, "unbreakable", 100, Some(100), &options);
+        assert_eq!(laid_out.font_size, 60.0);
+        assert!(laid_out.lines.len() > 1);
+        assert_eq!(texts(&laid_out).concat(), "unbreakable");
+        assert!(laid_out.overflows);
+    }
+
+    #[test]
+    fn truncates_with_ellipsis() {
+        let registry = FontRegistry::embedded();
+        let fonts = registry.chain(None, Weight::Regular).unwrap();
+        let options = LayoutOptions {
+            font_size: 40.0,
+            min_font_size: 40.0,
+            max_lines: Some(2),
+            ..LayoutOptions::default()
+        };
+        let laid_out = layout(
+            &fonts,
+            "one two three four five six seven",
+            200,
+            None,
+            &options,
+        );
+        assert_eq!(laid_out.lines.len(), 2);
+        let last = &laid_out.lines[1];
+        assert!(last.text.ends_with(ELLIPSIS));
+        assert!(last.width <= 200);
+        assert!(!laid_out.overflows);
+    }
+
+    #[test]
+    fn aligns_lines() {
+        let registry = FontRegistry::embedded();
+        let fonts = registry.chain(None, Weight::Regular).unwrap();
+        let aligned = |alignment| {
+            let options = LayoutOptions {
+                font_size: 40.0,
+                alignment,
+                ..LayoutOptions::default()
+            };
+            layout(&fonts, "Hi", 400, None, &options)
+        };
+        let (left_aligned, center, right) = (
+            aligned(Alignment::Left),
+            aligned(Alignment::Center),
+            aligned(Alignment::Right),
+        );
+        let width = left_aligned.lines[0].width as i32;
+        assert!(left(&left_aligned) < 10);
+        assert!((left(&center) - left(&left_aligned) - (400 - width) / 2).abs() <= 1);
+        assert!((left(&right) - left(&left_aligned) - (400 - width)).abs() <= 1);
+    }
+}
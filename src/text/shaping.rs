@@ -0,0 +1,178 @@
+//! Shaping of complex scripts and right-to-left text with rustybuzz and unicode-bidi
+
+use std::ops::Range;
+use std::ptr;
+
+use rusttype::{point, Font, GlyphId, Point, PositionedGlyph, Scale};
+use rustybuzz::{Direction, UnicodeBuffer};
+use unicode_bidi::BidiInfo;
+
+use super::font::{ChainFont, FontChain};
+
+/// A font parsed for shaping, kept along with the font it borrows from
+#[ouroboros::self_referencing]
+pub(super) struct ShapingFace {
+    font: Font<'static>,
+    #[borrows(font)]
+    #[covariant]
+    face: Option<rustybuzz::Face<'this>>,
+}
+impl ShapingFace {
+    pub(super) fn parse(font: Font<'static>) -> Self {
+        ShapingFaceBuilder {
+            font,
+            face_builder: |font| parse_face(font),
+        }
+        .build()
+    }
+}
+
+/// Parses a font for shaping. rusttype already parsed it, so this shouldn't fail.
+fn parse_face<'a>(font: &'a Font<'static>) -> Option<rustybuzz::Face<'a>> {
+    match font {
+        Font::Ref(face) => rustybuzz::Face::from_face((**face).clone()),
+        Font::Owned(face) => rustybuzz::Face::from_slice(face.as_slice(), 0),
+    }
+}
+
+/// Lays out a single line of text, reordering it for display according to the Unicode bidirectional algorithm and
+/// shaping each run with the font chosen for it. Returns the glyphs and the x coordinate after the last one.
+pub(super) fn layout(
+    fonts: &FontChain,
+    text: &str,
+    scale: Scale,
+    start: Point<f32>,
+) -> (Vec<PositionedGlyph<'static>>, f32) {
+    let bidi = BidiInfo::new(text, None);
+    let mut glyphs = Vec::with_capacity(text.len());
+    let mut x = start.x;
+
+    for paragraph in &bidi.paragraphs {
+        let (levels, runs) = bidi.visual_runs(paragraph, paragraph.range.clone());
+        for run in runs {
+            let rtl = levels[run.start].is_rtl();
+            let mut segments = font_segments(fonts, text, run);
+            // Segments are in logical order, which is backwards for right-to-left runs
+            if rtl {
+                segments.reverse();
+            }
+            for (range, font) in segments {
+                x = shape(
+                    font,
+                    &text[range],
+                    rtl,
+                    scale,
+                    point(x, start.y),
+                    &mut glyphs,
+                );
+            }
+        }
+    }
+    (glyphs, x)
+}
+
+/// Splits a run of text into ranges that are each drawn with a single font, preferring to keep the current font for
+/// characters it has so that spaces and punctuation don't break up a run
+fn font_segments<'a>(
+    fonts: &FontChain<'a>,
+    text: &str,
+    run: Range<usize>,
+) -> Vec<(Range<usize>, ChainFont<'a>)> {
+    let mut segments: Vec<(Range<usize>, ChainFont<'a>)> = Vec::new();
+    for (i, c) in text[run.clone()].char_indices() {
+        let i = run.start + i;
+        let end = i + c.len_utf8();
+        match segments.last_mut() {
+            Some((range, font)) if font.font.glyph(c).id() != GlyphId(0) => range.end = end,
+            _ => {
+                let font = fonts.chain_font_for(c);
+                match segments.last_mut() {
+                    Some((range, last)) if ptr::eq(last.font, font.font) => range.end = end,
+                    _ => segments.push((i..end, font)),
+                }
+            }
+        }
+    }
+    segments
+}
+
+/// Shapes text set in a single font and direction, appending its glyphs starting at `start`. Returns the x
+/// coordinate after the last glyph.
+fn shape(
+    font: ChainFont,
+    text: &str,
+    rtl: bool,
+    scale: Scale,
+    start: Point<f32>,
+    glyphs: &mut Vec<PositionedGlyph<'static>>,
+) -> f32 {
+    let parsed;
+    let face = match font.shaping {
+        Some(shaping) => shaping.borrow_face().as_ref(),
+        None => {
+            parsed = parse_face(font.font);
+            parsed.as_ref()
+        }
+    };
+    let Some(face) = face else {
+        return start.x;
+    };
+    let font = font.font;
+
+    let mut buffer = UnicodeBuffer::new();
+    buffer.push_str(text);
+    buffer.set_direction(if rtl {
+        Direction::RightToLeft
+    } else {
+        Direction::LeftToRight
+    });
+    let output = rustybuzz::shape(face, &[], buffer);
+
+    // Font units to pixels, in the same way that rusttype scales glyphs
+    let scale_x = font.scale_for_pixel_height(scale.x);
+    let scale_y = font.scale_for_pixel_height(scale.y);
+    let mut x = start.x;
+    for (info, position) in output.glyph_infos().iter().zip(output.glyph_positions()) {
+        let glyph = font
+            .glyph(GlyphId(info.glyph_id as u16))
+            .scaled(scale)
+            .positioned(point(
+                x + position.x_offset as f32 * scale_x,
+                start.y - position.y_offset as f32 * scale_y,
+            ));
+        glyphs.push(glyph);
+        x += position.x_advance as f32 * scale_x;
+    }
+    x
+}
+
+#[cfg(all(test, feature = "embedded-font"))]
+mod tests {
+    use super::*;
+    use crate::text::{FontRegistry, Weight};
+
+    #[test]
+    fn registry_fonts_shape_like_other_fonts() {
+        let registry = FontRegistry::embedded();
+        let cached = registry.chain(None, Weight::Regular).unwrap();
+        let font = Font::try_from_bytes(dejavu::sans::regular()).unwrap();
+        let uncached = FontChain::new(&font);
+
+        let scale = Scale::uniform(40.0);
+        for text in ["Office AVA", "שלום world", "مرحبا"] {
+            let (cached_glyphs, cached_end) = layout(&cached, text, scale, point(0.0, 30.0));
+            let (glyphs, end) = layout(&uncached, text, scale, point(0.0, 30.0));
+            assert_eq!(cached_end, end, "{}", text);
+            let ids = |glyphs: &[PositionedGlyph]| -> Vec<_> {
+                glyphs.iter().map(|g| (g.id(), g.position())).collect()
+            };
+            assert_eq!(ids(&cached_glyphs), ids(&glyphs), "{}", text);
+        }
+    }
+
+    #[test]
+    fn registries_can_be_shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<FontRegistry>();
+    }
+}
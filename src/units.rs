@@ -0,0 +1,156 @@
+//! Physical lengths and their size in printer dots
+//!
+//! All supported printers print 300 dots per inch, across and along the tape.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(feature = "template")]
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum UnitsError {
+    #[error("invalid length {0:?}, expected a number followed by mm, in, pt, or dots")]
+    InvalidLength(String),
+}
+
+/// Dots per inch across and along the tape
+pub const DOTS_PER_INCH: f32 = 300.0;
+
+const MM_PER_INCH: f32 = 25.4;
+const PT_PER_INCH: f32 = 72.0;
+
+/// Units of physical lengths
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "template", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "template", serde(rename_all = "snake_case"))]
+pub enum Units {
+    Dots,
+    Mm,
+    Inch,
+    /// Typographic points, 1/72 inch
+    Pt,
+}
+impl Units {
+    /// Converts a length in these units to dots at 300 dots per inch
+    pub fn to_dots(self, value: f32) -> f32 {
+        match self {
+            Units::Dots => value,
+            Units::Mm => value * DOTS_PER_INCH / MM_PER_INCH,
+            Units::Inch => value * DOTS_PER_INCH,
+            Units::Pt => value * DOTS_PER_INCH / PT_PER_INCH,
+        }
+    }
+    fn suffix(self) -> &'static str {
+        match self {
+            Units::Dots => "dots",
+            Units::Mm => "mm",
+            Units::Inch => "in",
+            Units::Pt => "pt",
+        }
+    }
+}
+
+/// A length in any units, e.g. `Length::mm(12.0)`. Parses from strings like "12mm", "0.5in", "36pt", or "150"
+/// (dots).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Length {
+    pub value: f32,
+    pub units: Units,
+}
+impl Length {
+    pub fn new(value: f32, units: Units) -> Self {
+        Self { value, units }
+    }
+    pub fn dots(value: u32) -> Self {
+        Self::new(value as f32, Units::Dots)
+    }
+    pub fn mm(value: f32) -> Self {
+        Self::new(value, Units::Mm)
+    }
+    pub fn inch(value: f32) -> Self {
+        Self::new(value, Units::Inch)
+    }
+    pub fn pt(value: f32) -> Self {
+        Self::new(value, Units::Pt)
+    }
+    /// Whole number of dots at 300 dots per inch
+    pub fn to_dots(self) -> u32 {
+        self.units.to_dots(self.value).round().max(0.0) as u32
+    }
+}
+impl Default for Length {
+    fn default() -> Self {
+        Self::dots(0)
+    }
+}
+impl fmt::Display for Length {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.value, self.units.suffix())
+    }
+}
+impl FromStr for Length {
+    type Err = UnitsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+        let (value, suffix) = s.split_at(split);
+        let units = match suffix {
+            "" | "dot" | "dots" => Units::Dots,
+            "mm" => Units::Mm,
+            "in" | "inch" => Units::Inch,
+            "pt" => Units::Pt,
+            _ => return Err(UnitsError::InvalidLength(s.to_string())),
+        };
+        match value.trim().parse::<f32>() {
+            Ok(value) if value.is_finite() && value >= 0.0 => Ok(Self::new(value, units)),
+            _ => Err(UnitsError::InvalidLength(s.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_lengths() {
+        let parse = |s: &str| s.parse::<Length>().unwrap();
+        assert_eq!(parse("12mm"), Length::mm(12.0));
+        assert_eq!(parse(" 0.5 in "), Length::inch(0.5));
+        assert_eq!(parse("1inch"), Length::inch(1.0));
+        assert_eq!(parse("36pt"), Length::pt(36.0));
+        assert_eq!(parse("150"), Length::dots(150));
+        assert_eq!(parse("150dots"), Length::dots(150));
+        assert_eq!(parse("1 dot"), Length::dots(1));
+
+        for invalid in ["", "mm", "12cm", "-1mm", "inf", "NaN", "1e40in", "12 m m"] {
+            assert!(invalid.parse::<Length>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn converts_to_dots() {
+        assert_eq!(Length::inch(1.0).to_dots(), 300);
+        assert_eq!(Length::mm(25.4).to_dots(), 300);
+        assert_eq!(Length::pt(72.0).to_dots(), 300);
+        assert_eq!(Length::mm(62.0).to_dots(), 732);
+        assert_eq!(Length::dots(696).to_dots(), 696);
+        assert_eq!(Length::new(-5.0, Units::Mm).to_dots(), 0);
+    }
+
+    #[test]
+    fn displays_as_parsed() {
+        for length in [
+            Length::mm(12.5),
+            Length::inch(2.0),
+            Length::pt(9.0),
+            Length::dots(35),
+        ] {
+            assert_eq!(length.to_string().parse::<Length>().unwrap(), length);
+        }
+        assert_eq!(Length::mm(12.5).to_string(), "12.5mm");
+    }
+}
@@ -1,126 +1,359 @@
 use image::{
-    buffer::ConvertBuffer,
-    imageops::{self, dither, resize, ColorMap},
-    DynamicImage, GrayImage, ImageBuffer, Luma,
+    imageops::{self, resize},
+    DynamicImage, GrayImage, Luma,
 };
 
-use crate::printer::Orientation;
-
-pub(crate) fn rasterize_image_to_ql_tiff(image: GrayImage) -> Vec<[u8; 90]> {
-    let width = image.width() as usize;
-    let height = image.height() as usize;
-
-    let mut lines = Vec::with_capacity(width);
-    for row in 0..height {
-        let mut line = [0; 90]; // Always 90 for regular sized printers like the QL-700 (with a 0x00 byte to start)
-                                // let mut line_byte = 7;
-                                // Bit index counts backwards
-                                // First nibble (bits 7 through 4) in the second byte is blank
-                                // let mut line_bit_index: i8 = 0;
-        for col in 0_usize..720 {
-            let line_byte = ((719 / 8) - (col as isize / 8)) as usize;
-            let line_bit_index = col % 8;
-            if col >= width {
-                break;
-            }
-            let luma_pixel = image.get_pixel(col as u32, row as u32); // + 3 was here in TS code -- not sure if needed
-            let value: u8 = if luma_pixel[0] > 0xFF / 2 { 0 } else { 1 };
-            line[line_byte] |= value << line_bit_index;
+use crate::printer::{constants::Label, Orientation, PrinterError};
+use crate::units::Length;
+
+pub mod dither;
+pub mod preprocess;
+pub mod raster;
+pub mod stream;
+pub use dither::Dither;
+pub use preprocess::Preprocess;
+pub use stream::LineStream;
+
+/// How an image is scaled to the printable area of a label
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Fit {
+    /// Scale the image to the printable width, with its length following from the aspect ratio
+    #[default]
+    Width,
+    /// Scale the image to fit inside the printable area, leaving blank space around it
+    Contain,
+    /// Scale the image to fill the printable area, cropping whatever doesn't fit
+    Cover,
+    /// Scale the image to the printable area, ignoring its aspect ratio
+    Stretch,
+    /// Keep the image at its original size, one pixel per dot
+    None,
+}
+
+/// Position of an image on the label when it is smaller (or larger, when cropping) than the printable area
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Align {
+    Start,
+    #[default]
+    Center,
+    End,
+}
+impl Align {
+    /// Offset of something `size` long in a space `available` long
+    fn offset(self, available: u32, size: u32) -> i64 {
+        let free = i64::from(available) - i64::from(size);
+        match self {
+            Align::Start => 0,
+            Align::Center => free / 2,
+            Align::End => free,
         }
-        lines.push(line);
     }
-    lines
 }
 
-pub(crate) fn dither_luma8_image(image: &mut GrayImage) {
-    struct BlackAndWhite {}
-    impl ColorMap for BlackAndWhite {
-        type Color = Luma<u8>;
+/// Flipping an image, e.g. for iron-on transfers or labels read through a window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mirror {
+    #[default]
+    None,
+    /// Flip left to right
+    Horizontal,
+    /// Flip top to bottom
+    Vertical,
+}
 
-        fn index_of(&self, color: &Self::Color) -> usize {
-            if color.0[0] < (u8::MAX) / 2 {
-                0
-            } else {
-                1
-            }
-        }
+/// What to do with an image that doesn't fit on the label with `Fit::Width` or `Fit::None`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Scale the image down until it fits
+    #[default]
+    Shrink,
+    /// Cut off the parts of the image outside the label, keeping the part chosen by the alignment
+    Crop,
+    /// Fail with `PrinterError::ContentTooLong` or `PrinterError::ContentTooWide`
+    Error,
+}
 
-        fn map_color(&self, color: &mut Self::Color) {
-            if color.0[0] < (u8::MAX) / 2 {
-                color.0[0] = u8::MIN;
-            } else {
-                color.0[0] = u8::MAX;
-            }
+/// How an image is scaled and positioned on a label
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Placement {
+    pub fit: Fit,
+    /// Alignment across the tape
+    pub align: Align,
+    /// Alignment along the tape
+    pub vertical_align: Align,
+    /// Length of labels on continuous tape. `None` makes the label as long as the image. Die-cut labels always use
+    /// their printable length.
+    pub length: Option<Length>,
+    pub overflow: Overflow,
+}
+impl Placement {
+    /// Length of labels in dots: the printable length of die-cut labels, or `length` on continuous tape
+    pub fn length_on(&self, label: &Label) -> Option<u32> {
+        match label.tape_size.1 {
+            0 => self.length.map(Length::to_dots),
+            _ => Some(label.dots_printable.1),
         }
+    }
+}
 
-        fn lookup(&self, index: usize) -> Option<Self::Color> {
-            match index {
-                0 => Some(Luma::<u8>::from([u8::MIN])),
-                1 => Some(Luma::<u8>::from([u8::MAX])),
-                _ => None,
-            }
-        }
+/// How an image is turned into raster lines
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ImageOptions {
+    pub orientation: Orientation,
+    /// Applied before the image is rotated
+    pub mirror: Mirror,
+    pub dither: Dither,
+    pub placement: Placement,
+    /// Adjustments made before the image is placed and dithered
+    pub preprocess: Preprocess,
+}
 
-        fn has_lookup(&self) -> bool {
-            true
-        }
+/// Resize, rotate, convert to grayscale, optionally dither, and rasterize an image so that it fills the
+/// printable width of `label`. On die-cut labels, images that are too long are shrunk to fit and shorter images
+/// are centered on the label. Empty images have no lines. The result can be sent with
+/// `ThermalPrinter::print_lines` or serialized with `job::serialize_job`.
+pub fn rasterize_image(
+    image: DynamicImage,
+    label: &Label,
+    orientation: Orientation,
+    dither: bool,
+) -> Vec<[u8; 90]> {
+    let options = ImageOptions {
+        orientation,
+        dither: dither.into(),
+        ..ImageOptions::default()
+    };
+    match rasterize_image_with_options(image, label, &options) {
+        Ok(lines) => lines,
+        Err(PrinterError::EmptyImage) => Vec::new(),
+        Err(e) => panic!("shrinking always fits: {}", e),
     }
+}
+
+/// Like `rasterize_image`, choosing how the image is adjusted, scaled, positioned, and dithered
+pub fn rasterize_image_with_options(
+    image: DynamicImage,
+    label: &Label,
+    options: &ImageOptions,
+) -> Result<Vec<[u8; 90]>, PrinterError> {
+    // Grayscale and adjust, before the blank space around the image is added
+    let image = options.preprocess.apply(image);
+
+    // Mirror and rotate
+    let image = orient(image, label, options);
+
+    // Resize
+    let placement = &options.placement;
+    let length = placement.length_on(label);
+    let mut image = place_image(&image, label.dots_printable.0, length, placement)?;
+
+    // Dither
+    options
+        .dither
+        .apply_with_threshold(&mut image, options.preprocess.threshold);
 
-    let color_map = BlackAndWhite {};
-    dither(image, &color_map)
-}
-
-pub(crate) fn convert_image_to_luma_u8(image: DynamicImage) -> GrayImage {
-    match image {
-        DynamicImage::ImageLuma8(i) => i,
-        DynamicImage::ImageLumaA8(i) => i.convert(),
-        DynamicImage::ImageLuma16(i) => i.convert(),
-        DynamicImage::ImageLumaA16(i) => i.convert(),
-        DynamicImage::ImageRgb8(i) => i.convert(),
-        DynamicImage::ImageRgba8(i) => i.convert(),
-        DynamicImage::ImageRgb16(i) => i.convert(),
-        DynamicImage::ImageRgba16(i) => i.convert(),
-        DynamicImage::ImageRgb32F(i) => i.convert(),
-        DynamicImage::ImageRgba32F(i) => i.convert(),
-        _ => unimplemented!(),
+    // Rasterize
+    Ok(rasterize_image_to_ql_tiff(&image))
+}
+
+/// Applies the mirroring and orientation in `options` to an image
+pub(crate) fn orient(image: GrayImage, label: &Label, options: &ImageOptions) -> GrayImage {
+    let image = match options.mirror {
+        Mirror::None => image,
+        Mirror::Horizontal => imageops::flip_horizontal(&image),
+        Mirror::Vertical => imageops::flip_vertical(&image),
+    };
+    let orientation = match options.orientation {
+        Orientation::Auto => auto_orientation(image.dimensions(), label, &options.placement),
+        orientation => orientation,
+    };
+    match orientation {
+        Orientation::Normal | Orientation::Auto => image,
+        Orientation::Rotated => imageops::rotate90(&image),
+        Orientation::Rotated180 => imageops::rotate180(&image),
+        Orientation::Rotated270 => imageops::rotate270(&image),
     }
 }
 
-pub(crate) fn resize_and_rotate_image<I>(image: I, orientation: Orientation, final_width: u32) -> I
-where
-    I: image::GenericImageView,
-    I::Pixel: 'static,
-    <I::Pixel as image::Pixel>::Subpixel: 'static,
-    I: From<
-        ImageBuffer<
-            <I as image::GenericImageView>::Pixel,
-            Vec<<<I as image::GenericImageView>::Pixel as image::Pixel>::Subpixel>,
-        >,
-    >,
-{
-    let owidth = image.width();
-    let oheight = image.height();
-
-    let nwidth = final_width;
-    let nheight = match orientation {
-        Orientation::Normal => {
-            (f64::from(nwidth) / (f64::from(owidth)) * f64::from(oheight)).floor() as u32
+/// Whether an image of `size` prints larger on `label` as is or turned by 90 degrees. Never returns
+/// `Orientation::Auto`.
+pub fn auto_orientation(size: (u32, u32), label: &Label, placement: &Placement) -> Orientation {
+    let length = placement.length_on(label);
+    let area = |size: (u32, u32)| {
+        placed_size(size, label.dots_printable.0, length, placement)
+            .map_or(0, |(size, _)| u64::from(size.0) * u64::from(size.1))
+    };
+    if area((size.1, size.0)) > area(size) {
+        Orientation::Rotated
+    } else {
+        Orientation::Normal
+    }
+}
+
+/// Scales and positions an image on a white background `width` dots wide and `length` dots long, or as long as the
+/// scaled image if `length` is `None`
+pub fn place_image(
+    image: &GrayImage,
+    width: u32,
+    length: Option<u32>,
+    placement: &Placement,
+) -> Result<GrayImage, PrinterError> {
+    let (size, length) = placed_size(image.dimensions(), width, length, placement)?;
+    let image = if size == image.dimensions() {
+        image.clone()
+    } else {
+        resize(image, size.0, size.1, imageops::FilterType::Lanczos3)
+    };
+    let mut placed = GrayImage::from_pixel(width, length, Luma([255]));
+    imageops::overlay(
+        &mut placed,
+        &image,
+        placement.align.offset(width, size.0),
+        placement.vertical_align.offset(length, size.1),
+    );
+    Ok(placed)
+}
+
+/// Size that `place_image` scales an image of the given size to, along with the length of the label. Fails with
+/// `PrinterError::EmptyImage` if the image has no pixels.
+pub fn placed_size(
+    (image_width, image_height): (u32, u32),
+    width: u32,
+    length: Option<u32>,
+    placement: &Placement,
+) -> Result<((u32, u32), u32), PrinterError> {
+    if image_width == 0 || image_height == 0 {
+        return Err(PrinterError::EmptyImage);
+    }
+    let scale_to = |scale: f64| {
+        (
+            ((f64::from(image_width) * scale).round() as u32).max(1),
+            ((f64::from(image_height) * scale).round() as u32).max(1),
+        )
+    };
+    let width_scale = f64::from(width) / f64::from(image_width);
+    let contain_scale = |length: u32| width_scale.min(f64::from(length) / f64::from(image_height));
+
+    let (size, length) = match (placement.fit, length) {
+        (Fit::Contain, Some(length)) => (scale_to(contain_scale(length)), length),
+        (Fit::Cover, Some(length)) => {
+            let scale = width_scale.max(f64::from(length) / f64::from(image_height));
+            (scale_to(scale), length)
+        }
+        (Fit::Stretch, Some(length)) => ((width, length), length),
+        (Fit::None, length) => {
+            let length = length.unwrap_or(image_height);
+            let size = (image_width, image_height);
+            (overflow(size, width, length, placement)?, length)
         }
-        Orientation::Rotated => {
-            (f64::from(nwidth) / (f64::from(oheight)) * f64::from(owidth)).floor() as u32
+        // Everything else scales to the width
+        (_, length) => {
+            let height = (f64::from(image_height) * width_scale).floor().max(1.0) as u32;
+            let length = length.unwrap_or(height);
+            let size = (width, height);
+            (overflow(size, width, length, placement)?, length)
         }
     };
+    Ok((size, length))
+}
 
-    let image = match orientation {
-        Orientation::Normal => image,
-        Orientation::Rotated => imageops::rotate90(&image).into(),
-    };
+/// Applies `placement.overflow` to an image of `size` that may not fit in `width` by `length`
+fn overflow(
+    size: (u32, u32),
+    width: u32,
+    length: u32,
+    placement: &Placement,
+) -> Result<(u32, u32), PrinterError> {
+    if size.0 <= width && size.1 <= length {
+        return Ok(size);
+    }
+    match placement.overflow {
+        Overflow::Shrink => {
+            let shrink =
+                (f64::from(width) / f64::from(size.0)).min(f64::from(length) / f64::from(size.1));
+            Ok((
+                ((f64::from(size.0) * shrink).floor() as u32).max(1),
+                ((f64::from(size.1) * shrink).floor() as u32).max(1),
+            ))
+        }
+        Overflow::Crop => Ok(size),
+        Overflow::Error if size.0 > width => Err(PrinterError::ContentTooWide(size.0, width)),
+        Overflow::Error => Err(PrinterError::ContentTooLong(size.1 as usize, length)),
+    }
+}
 
-    resize(
-        &image,
-        nwidth,
-        nheight,
-        image::imageops::FilterType::Lanczos3,
-    )
-    .into()
+pub(crate) fn rasterize_image_to_ql_tiff(image: &GrayImage) -> Vec<[u8; 90]> {
+    let mut lines = Vec::with_capacity(image.height() as usize);
+    raster::rasterize_into(image, &mut lines);
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::printer::constants;
+
+    #[test]
+    fn places_images() {
+        let placement = |fit: Fit, overflow: Overflow| Placement {
+            fit,
+            overflow,
+            ..Placement::default()
+        };
+        let width = placement(Fit::Width, Overflow::Shrink);
+        assert_eq!(
+            placed_size((100, 50), 200, None, &width).unwrap(),
+            ((200, 100), 100)
+        );
+        assert_eq!(
+            placed_size((100, 50), 200, Some(50), &width).unwrap(),
+            ((100, 50), 50)
+        );
+
+        let contain = placement(Fit::Contain, Overflow::Shrink);
+        assert_eq!(
+            placed_size((100, 50), 200, Some(50), &contain).unwrap(),
+            ((100, 50), 50)
+        );
+        let cover = placement(Fit::Cover, Overflow::Shrink);
+        assert_eq!(
+            placed_size((100, 50), 200, Some(50), &cover).unwrap(),
+            ((200, 100), 50)
+        );
+        let stretch = placement(Fit::Stretch, Overflow::Shrink);
+        assert_eq!(
+            placed_size((100, 50), 200, Some(50), &stretch).unwrap(),
+            ((200, 50), 50)
+        );
+
+        let none = placement(Fit::None, Overflow::Crop);
+        assert_eq!(
+            placed_size((300, 50), 200, None, &none).unwrap(),
+            ((300, 50), 50)
+        );
+        let error = placement(Fit::None, Overflow::Error);
+        assert!(matches!(
+            placed_size((300, 50), 200, None, &error),
+            Err(PrinterError::ContentTooWide(300, 200))
+        ));
+    }
+
+    #[test]
+    fn rejects_empty_images() {
+        let placement = Placement::default();
+        for size in [(0, 0), (0, 10), (10, 0)] {
+            assert!(matches!(
+                placed_size(size, 200, Some(100), &placement),
+                Err(PrinterError::EmptyImage)
+            ));
+        }
+
+        let label = constants::label_data(62, None).unwrap();
+        let empty = DynamicImage::new_luma8(0, 20);
+        assert!(matches!(
+            rasterize_image_with_options(empty.clone(), &label, &ImageOptions::default()),
+            Err(PrinterError::EmptyImage)
+        ));
+        assert!(rasterize_image(empty, &label, Orientation::Normal, false).is_empty());
+    }
 }
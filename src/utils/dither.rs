@@ -0,0 +1,323 @@
+//! Conversion of grayscale images to pure black and white
+
+use image::GrayImage;
+#[cfg(feature = "template")]
+use serde::{Deserialize, Serialize};
+
+/// How gray levels are turned into black and white dots. Photos look best with error diffusion (Atkinson keeps
+/// the most contrast), while barcodes and text should always use `Threshold`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "template", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "template", serde(rename_all = "snake_case"))]
+pub enum Dither {
+    /// Every pixel lighter than 50% gray is white
+    #[default]
+    Threshold,
+    FloydSteinberg,
+    Atkinson,
+    JarvisJudiceNinke,
+    Stucki,
+    Sierra,
+    /// Ordered dithering with a 4x4 Bayer matrix
+    Bayer4,
+    /// Ordered dithering with an 8x8 Bayer matrix
+    Bayer8,
+}
+impl From<bool> for Dither {
+    /// `true` dithers with Floyd–Steinberg, `false` thresholds
+    fn from(dither: bool) -> Self {
+        if dither {
+            Dither::FloydSteinberg
+        } else {
+            Dither::Threshold
+        }
+    }
+}
+impl Dither {
+    /// Converts every pixel of the image to black (0) or white (255)
+    pub fn apply(self, image: &mut GrayImage) {
+        self.apply_with_threshold(image, 128);
+    }
+    /// Like `apply`, with pixels from gray level `threshold` up becoming white. Lower thresholds make the image
+    /// lighter.
+    pub fn apply_with_threshold(self, image: &mut GrayImage, threshold: u8) {
+        let width = image.width() as usize;
+        if width == 0 {
+            return;
+        }
+        let mut rows = RowDither::new(self, threshold, width);
+        for row in image.chunks_exact_mut(width) {
+            rows.apply(row);
+        }
+    }
+}
+
+/// Dithers an image one row at a time, from top to bottom, so that the whole image never has to be in memory.
+/// Only the error still to be spread to the next rows is kept.
+pub struct RowDither {
+    dither: Dither,
+    threshold: i32,
+    /// Index of the next row
+    y: usize,
+    /// Error spread to the next row and the two after it
+    errors: [Vec<i32>; 3],
+}
+impl RowDither {
+    pub fn new(dither: Dither, threshold: u8, width: usize) -> Self {
+        Self {
+            dither,
+            threshold: i32::from(threshold),
+            y: 0,
+            errors: [vec![0; width], vec![0; width], vec![0; width]],
+        }
+    }
+    /// Converts every pixel of the next row to black (0) or white (255)
+    pub fn apply(&mut self, row: &mut [u8]) {
+        let threshold = self.threshold;
+        match self.dither {
+            Dither::Threshold => {
+                for pixel in row.iter_mut() {
+                    *pixel = quantize(i32::from(*pixel), threshold);
+                }
+            }
+            Dither::FloydSteinberg => self.diffuse(row, &FLOYD_STEINBERG, 16),
+            Dither::Atkinson => self.diffuse(row, &ATKINSON, 8),
+            Dither::JarvisJudiceNinke => self.diffuse(row, &JARVIS_JUDICE_NINKE, 48),
+            Dither::Stucki => self.diffuse(row, &STUCKI, 42),
+            Dither::Sierra => self.diffuse(row, &SIERRA, 32),
+            Dither::Bayer4 => ordered(row, self.y, &BAYER_4, threshold),
+            Dither::Bayer8 => ordered(row, self.y, &BAYER_8, threshold),
+        }
+        self.y += 1;
+    }
+    fn diffuse(&mut self, row: &mut [u8], kernel: &Kernel, divisor: i32) {
+        let width = row.len().min(self.errors[0].len());
+        // Errors pushed past 0 or 255 are kept so that they can carry over to the next pixels
+        for (x, pixel) in row[..width].iter_mut().enumerate() {
+            let old = i32::from(*pixel) + self.errors[0][x];
+            let new = quantize(old, self.threshold);
+            let error = old - i32::from(new);
+            *pixel = new;
+
+            for &(dx, dy, weight) in kernel {
+                let nx = x as i32 + dx;
+                if nx >= 0 && (nx as usize) < width {
+                    self.errors[dy as usize][nx as usize] += error * weight / divisor;
+                }
+            }
+        }
+        self.errors.rotate_left(1);
+        self.errors[2].iter_mut().for_each(|error| *error = 0);
+    }
+}
+
+fn quantize(value: i32, threshold: i32) -> u8 {
+    if value >= threshold {
+        u8::MAX
+    } else {
+        0
+    }
+}
+
+/// Error diffusion weights as (x offset, y offset, weight), divided by the divisor passed to `RowDither::diffuse`
+type Kernel = [(i32, i32, i32)];
+
+const FLOYD_STEINBERG: [(i32, i32, i32); 4] = [(1, 0, 7), (-1, 1, 3), (0, 1, 5), (1, 1, 1)];
+
+/// Only spreads 6/8 of the error, which keeps highlights and shadows clean
+const ATKINSON: [(i32, i32, i32); 6] = [
+    (1, 0, 1),
+    (2, 0, 1),
+    (-1, 1, 1),
+    (0, 1, 1),
+    (1, 1, 1),
+    (0, 2, 1),
+];
+
+const JARVIS_JUDICE_NINKE: [(i32, i32, i32); 12] = [
+    (1, 0, 7),
+    (2, 0, 5),
+    (-2, 1, 3),
+    (-1, 1, 5),
+    (0, 1, 7),
+    (1, 1, 5),
+    (2, 1, 3),
+    (-2, 2, 1),
+    (-1, 2, 3),
+    (0, 2, 5),
+    (1, 2, 3),
+    (2, 2, 1),
+];
+
+const STUCKI: [(i32, i32, i32); 12] = [
+    (1, 0, 8),
+    (2, 0, 4),
+    (-2, 1, 2),
+    (-1, 1, 4),
+    (0, 1, 8),
+    (1, 1, 4),
+    (2, 1, 2),
+    (-2, 2, 1),
+    (-1, 2, 2),
+    (0, 2, 4),
+    (1, 2, 2),
+    (2, 2, 1),
+];
+
+const SIERRA: [(i32, i32, i32); 10] = [
+    (1, 0, 5),
+    (2, 0, 3),
+    (-2, 1, 2),
+    (-1, 1, 4),
+    (0, 1, 5),
+    (1, 1, 4),
+    (2, 1, 2),
+    (-1, 2, 2),
+    (0, 2, 3),
+    (1, 2, 2),
+];
+
+const BAYER_4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+const BAYER_8: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+fn ordered<const N: usize>(row: &mut [u8], y: usize, matrix: &[[u8; N]; N], threshold: i32) {
+    let levels = (N * N) as f32;
+    // Shift the pattern so that a flat 50% gray moves with the threshold
+    let bias = (threshold - 128) as f32;
+    for (x, pixel) in row.iter_mut().enumerate() {
+        let rank = f32::from(matrix[y % N][x % N]);
+        let threshold = (rank + 0.5) / levels * 255.0 + bias;
+        *pixel = if f32::from(*pixel) > threshold {
+            u8::MAX
+        } else {
+            0
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Dither; 8] = [
+        Dither::Threshold,
+        Dither::FloydSteinberg,
+        Dither::Atkinson,
+        Dither::JarvisJudiceNinke,
+        Dither::Stucki,
+        Dither::Sierra,
+        Dither::Bayer4,
+        Dither::Bayer8,
+    ];
+
+    fn gray(level: u8) -> GrayImage {
+        GrayImage::from_pixel(64, 64, image::Luma([level]))
+    }
+
+    fn white_fraction(image: &GrayImage) -> f32 {
+        let white = image.pixels().filter(|pixel| pixel[0] == u8::MAX).count();
+        white as f32 / (image.width() * image.height()) as f32
+    }
+
+    #[test]
+    fn kernels_spread_whole_error() {
+        let total = |kernel: &Kernel| kernel.iter().map(|&(_, _, weight)| weight).sum::<i32>();
+        assert_eq!(total(&FLOYD_STEINBERG), 16);
+        assert_eq!(total(&ATKINSON), 6);
+        assert_eq!(total(&JARVIS_JUDICE_NINKE), 48);
+        assert_eq!(total(&STUCKI), 42);
+        assert_eq!(total(&SIERRA), 32);
+
+        let kernels: [&Kernel; 5] = [
+            &FLOYD_STEINBERG,
+            &ATKINSON,
+            &JARVIS_JUDICE_NINKE,
+            &STUCKI,
+            &SIERRA,
+        ];
+        for kernel in kernels {
+            // Error only goes to pixels that haven't been visited yet
+            for &(dx, dy, _) in kernel {
+                assert!((1..=2).contains(&dy) || (dy == 0 && dx > 0));
+            }
+        }
+
+        let bayer_4: Vec<u8> = BAYER_4.iter().flatten().copied().collect();
+        let bayer_8: Vec<u8> = BAYER_8.iter().flatten().copied().collect();
+        for mut ranks in [bayer_4, bayer_8] {
+            // Each rank appears once
+            ranks.sort_unstable();
+            assert!(ranks.iter().copied().eq(0..ranks.len() as u8));
+        }
+    }
+
+    #[test]
+    fn keeps_black_and_white() {
+        for dither in ALL {
+            for level in [0, u8::MAX] {
+                let mut image = gray(level);
+                dither.apply(&mut image);
+                assert_eq!(image, gray(level), "{:?}", dither);
+            }
+        }
+    }
+
+    #[test]
+    fn preserves_gray_levels() {
+        for dither in ALL {
+            for level in [32, 64, 128, 192, 224] {
+                let mut image = gray(level);
+                dither.apply(&mut image);
+                assert!(image
+                    .pixels()
+                    .all(|pixel| pixel[0] == 0 || pixel[0] == u8::MAX));
+                // Atkinson drops some of the error, pushing other grays towards black and white
+                let extreme = level != 128;
+                if dither == Dither::Threshold || (dither == Dither::Atkinson && extreme) {
+                    continue;
+                }
+                let expected = f32::from(level) / 255.0;
+                let fraction = white_fraction(&image);
+                assert!(
+                    (fraction - expected).abs() < 0.04,
+                    "{:?} turned {} into {}",
+                    dither,
+                    level,
+                    fraction
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn applies_threshold() {
+        for (level, threshold, white) in [(127, 128, false), (128, 128, true), (100, 90, true)] {
+            let mut image = gray(level);
+            Dither::Threshold.apply_with_threshold(&mut image, threshold);
+            assert_eq!(white_fraction(&image), if white { 1.0 } else { 0.0 });
+        }
+
+        // Lower thresholds make dithered images lighter
+        for dither in ALL {
+            let mut light = gray(128);
+            let mut dark = gray(128);
+            dither.apply_with_threshold(&mut light, 96);
+            dither.apply_with_threshold(&mut dark, 160);
+            assert!(
+                white_fraction(&light) > white_fraction(&dark),
+                "{:?}",
+                dither
+            );
+        }
+    }
+}
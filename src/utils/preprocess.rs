@@ -0,0 +1,240 @@
+//! Tonal adjustments applied to images before they are dithered
+
+use image::{imageops, DynamicImage, GrayImage, Luma};
+
+/// Unsharp mask parameters, as in `image::imageops::unsharpen`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sharpen {
+    /// Blur radius of the mask, in pixels
+    pub sigma: f32,
+    /// Smallest difference from the blurred image that gets sharpened
+    pub threshold: i32,
+}
+
+/// Adjustments applied in order: auto levels, brightness and contrast, gamma, sharpening, and inversion. The
+/// defaults leave the image unchanged.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Preprocess {
+    /// Blend transparent areas with a white background instead of ignoring transparency
+    pub flatten_alpha: bool,
+    /// Stretch the gray levels so that the darkest and lightest parts of the image become black and white
+    pub auto_levels: bool,
+    /// Added to every gray level, from -1.0 (black) to 1.0 (white)
+    pub brightness: f32,
+    /// Multiplies the distance of every gray level from 50% gray
+    pub contrast: f32,
+    /// Values above 1.0 lighten the midtones and values below 1.0 darken them
+    pub gamma: f32,
+    pub sharpen: Option<Sharpen>,
+    pub invert: bool,
+    /// Gray level from which pixels become white when dithering
+    pub threshold: u8,
+}
+impl Default for Preprocess {
+    fn default() -> Self {
+        Self {
+            flatten_alpha: true,
+            auto_levels: false,
+            brightness: 0.0,
+            contrast: 1.0,
+            gamma: 1.0,
+            sharpen: None,
+            invert: false,
+            threshold: 128,
+        }
+    }
+}
+impl Preprocess {
+    /// Converts an image to grayscale and applies the adjustments
+    pub fn apply(&self, image: DynamicImage) -> GrayImage {
+        let mut image = self.grayscale(image);
+
+        let levels = if self.auto_levels {
+            levels(&image)
+        } else {
+            (0, 255)
+        };
+        let lookup = self.tone_curve(levels);
+        for pixel in image.pixels_mut() {
+            pixel[0] = lookup[usize::from(pixel[0])];
+        }
+
+        if let Some(sharpen) = self.sharpen {
+            image = imageops::unsharpen(&image, sharpen.sigma, sharpen.threshold);
+        }
+        if self.invert {
+            imageops::invert(&mut image);
+        }
+        image
+    }
+    /// Converts an image to grayscale, flattening transparency if `flatten_alpha` is set
+    pub(crate) fn grayscale(&self, image: DynamicImage) -> GrayImage {
+        if self.flatten_alpha {
+            flatten_alpha(image)
+        } else {
+            image.to_luma8()
+        }
+    }
+    /// Gray level that each gray level becomes after stretching `levels` to black and white and adjusting the
+    /// brightness, contrast, and gamma
+    pub(crate) fn tone_curve(&self, (low, high): (u8, u8)) -> [u8; 256] {
+        let range = f32::from(high - low).max(1.0);
+        let mut lookup = [0; 256];
+        for (level, adjusted) in lookup.iter_mut().enumerate() {
+            let mut value = (level as f32 - f32::from(low)) / range;
+            value += self.brightness;
+            value = (value - 0.5) * self.contrast + 0.5;
+            value = value
+                .clamp(0.0, 1.0)
+                .powf(1.0 / self.gamma.max(f32::EPSILON));
+            *adjusted = (value * 255.0).round() as u8;
+        }
+        lookup
+    }
+}
+
+/// Converts an image to grayscale, blending any transparent areas with a white background
+pub fn flatten_alpha(image: DynamicImage) -> GrayImage {
+    if !image.color().has_alpha() {
+        return image.to_luma8();
+    }
+    let image = image.to_luma_alpha8();
+    GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        let [luma, alpha] = image.get_pixel(x, y).0;
+        let (luma, alpha) = (u16::from(luma), u16::from(alpha));
+        Luma([((luma * alpha + 255 * (255 - alpha)) / 255) as u8])
+    })
+}
+
+/// Darkest and lightest gray levels, ignoring the most extreme 0.5% of pixels on each end
+fn levels(image: &GrayImage) -> (u8, u8) {
+    let mut histogram = [0u64; 256];
+    for pixel in image.pixels() {
+        histogram[usize::from(pixel[0])] += 1;
+    }
+    histogram_levels(&histogram)
+}
+
+/// Same as `levels`, from the number of pixels at each gray level
+pub(crate) fn histogram_levels(histogram: &[u64; 256]) -> (u8, u8) {
+    let clip = histogram.iter().sum::<u64>() / 200;
+    let low = clipped_level(histogram, clip, 0..256);
+    let high = clipped_level(histogram, clip, (0..256).rev());
+    if low < high {
+        (low, high)
+    } else {
+        (0, 255)
+    }
+}
+
+/// First of `levels` at which more than `clip` pixels have been counted
+fn clipped_level(histogram: &[u64; 256], clip: u64, levels: impl Iterator<Item = usize>) -> u8 {
+    let mut count = 0;
+    for level in levels {
+        count += histogram[level];
+        if count > clip {
+            return level as u8;
+        }
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{LumaA, Rgba, RgbaImage};
+
+    /// A gradient through every gray level
+    fn gradient() -> GrayImage {
+        GrayImage::from_fn(256, 1, |x, _| Luma([x as u8]))
+    }
+
+    fn apply(preprocess: Preprocess, image: &GrayImage) -> Vec<u8> {
+        preprocess
+            .apply(DynamicImage::ImageLuma8(image.clone()))
+            .into_raw()
+    }
+
+    #[test]
+    fn defaults_leave_image_unchanged() {
+        let image = gradient();
+        assert_eq!(apply(Preprocess::default(), &image), image.into_raw());
+    }
+
+    #[test]
+    fn adjusts_gray_levels() {
+        let image = gradient();
+        let at = |preprocess: Preprocess, level: usize| apply(preprocess, &image)[level];
+
+        let brighter = Preprocess {
+            brightness: 0.2,
+            ..Preprocess::default()
+        };
+        assert_eq!(at(brighter, 0), 51);
+        assert_eq!(at(brighter, 255), 255);
+
+        let contrast = Preprocess {
+            contrast: 2.0,
+            ..Preprocess::default()
+        };
+        assert_eq!(at(contrast, 60), 0);
+        assert_eq!(at(contrast, 128), 129);
+        assert_eq!(at(contrast, 196), 255);
+
+        let gamma = Preprocess {
+            gamma: 2.0,
+            ..Preprocess::default()
+        };
+        assert!(at(gamma, 64) > 64);
+        assert_eq!((at(gamma, 0), at(gamma, 255)), (0, 255));
+
+        let invert = Preprocess {
+            invert: true,
+            ..Preprocess::default()
+        };
+        assert_eq!((at(invert, 0), at(invert, 200)), (255, 55));
+    }
+
+    #[test]
+    fn stretches_levels() {
+        // Mostly 100 to 150, with a few outliers that are ignored
+        let mut image = GrayImage::from_fn(100, 10, |x, _| Luma([100 + (x / 2) as u8]));
+        image.put_pixel(0, 0, Luma([0]));
+        image.put_pixel(1, 0, Luma([255]));
+        assert_eq!(levels(&image), (100, 149));
+
+        let auto_levels = Preprocess {
+            auto_levels: true,
+            ..Preprocess::default()
+        };
+        let adjusted = auto_levels.apply(DynamicImage::ImageLuma8(image));
+        assert_eq!(adjusted.get_pixel(0, 1)[0], 0);
+        assert_eq!(adjusted.get_pixel(99, 0)[0], 255);
+        assert_eq!(adjusted.get_pixel(0, 0)[0], 0);
+
+        // Flat images are left alone
+        let flat = GrayImage::from_pixel(10, 10, Luma([90]));
+        assert_eq!(levels(&flat), (0, 255));
+        assert_eq!(apply(auto_levels, &flat), flat.into_raw());
+    }
+
+    #[test]
+    fn flattens_transparency() {
+        let image = RgbaImage::from_fn(3, 1, |x, _| Rgba([0, 0, 0, [0, 128, 255][x as usize]]));
+        let flattened = flatten_alpha(DynamicImage::ImageRgba8(image.clone()));
+        assert_eq!(flattened.into_raw(), [255, 127, 0]);
+
+        let ignored = Preprocess {
+            flatten_alpha: false,
+            ..Preprocess::default()
+        };
+        assert_eq!(
+            ignored.apply(DynamicImage::ImageRgba8(image)).into_raw(),
+            [0, 0, 0]
+        );
+
+        let luma_alpha = image::ImageBuffer::from_pixel(1, 1, LumaA([200u8, 0]));
+        let flattened = flatten_alpha(DynamicImage::ImageLumaA8(luma_alpha));
+        assert_eq!(flattened.into_raw(), [255]);
+    }
+}
@@ -0,0 +1,110 @@
+//! Packing black and white images into raster lines
+
+use std::convert::TryInto;
+
+use image::GrayImage;
+
+/// Number of bytes in a raster line
+pub const LINE_LENGTH: usize = 90;
+
+/// Widest image that fits in a raster line, in dots
+pub const MAX_WIDTH: usize = LINE_LENGTH * 8;
+
+/// Packs a row of gray pixels into a raster line. Pixels darker than 50% gray are printed. The first pixel goes in
+/// the lowest bit of the last byte, and pixels past `MAX_WIDTH` are dropped.
+pub fn pack_line(row: &[u8]) -> [u8; LINE_LENGTH] {
+    let mut line = [0; LINE_LENGTH];
+    let row = &row[..row.len().min(MAX_WIDTH)];
+    let chunks = row.chunks_exact(8);
+    let remainder = chunks.remainder();
+    let full = chunks.len();
+    for (byte, pixels) in line.iter_mut().rev().zip(chunks) {
+        *byte = pack8(pixels.try_into().expect("chunks of 8"));
+    }
+    if !remainder.is_empty() {
+        // Pad the last pixels with white
+        let mut pixels = [u8::MAX; 8];
+        pixels[..remainder.len()].copy_from_slice(remainder);
+        line[LINE_LENGTH - 1 - full] = pack8(&pixels);
+    }
+    line
+}
+
+/// Packs every row of an image into `lines`, replacing its contents but keeping its allocation
+pub fn rasterize_into(image: &GrayImage, lines: &mut Vec<[u8; LINE_LENGTH]>) {
+    lines.clear();
+    let width = image.width() as usize;
+    if width == 0 {
+        lines.resize(image.height() as usize, [0; LINE_LENGTH]);
+        return;
+    }
+    lines.extend(image.as_raw().chunks_exact(width).map(pack_line));
+}
+
+/// Sets bit `i` for each of the 8 pixels that is darker than 50% gray. The top bits of the pixels are gathered
+/// into a single byte with one multiplication, which never carries between them.
+fn pack8(pixels: &[u8; 8]) -> u8 {
+    let dark = !u64::from_le_bytes(*pixels) & 0x8080_8080_8080_8080;
+    ((dark >> 7).wrapping_mul(0x0102_0408_1020_4080) >> 56) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packs a row one pixel at a time, the way lines were packed before `pack_line`
+    fn pack_line_per_pixel(row: &[u8]) -> [u8; LINE_LENGTH] {
+        let mut line = [0; LINE_LENGTH];
+        for (col, &pixel) in row.iter().enumerate().take(MAX_WIDTH) {
+            let value = if pixel > 0xFF / 2 { 0 } else { 1 };
+            line[(MAX_WIDTH - 1) / 8 - col / 8] |= value << (col % 8);
+        }
+        line
+    }
+
+    #[test]
+    fn packs_like_per_pixel_loop() {
+        // Linear congruential generator, plenty for test data
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut random = || {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (state >> 56) as u8
+        };
+
+        let widths = [
+            0, 1, 7, 8, 9, 63, 64, 65, 306, 696, 713, 719, 720, 721, 1000,
+        ];
+        for &width in &widths {
+            for _ in 0..20 {
+                let row: Vec<u8> = (0..width).map(|_| random()).collect();
+                assert_eq!(
+                    pack_line(&row)[..],
+                    pack_line_per_pixel(&row)[..],
+                    "width {}",
+                    width
+                );
+            }
+            // Pixels right at the threshold
+            let row: Vec<u8> = (0..width).map(|x| 127 + (x % 2) as u8).collect();
+            assert_eq!(pack_line(&row)[..], pack_line_per_pixel(&row)[..]);
+        }
+    }
+
+    #[test]
+    fn rasterizes_every_row() {
+        let image = GrayImage::from_fn(13, 5, |x, y| image::Luma([if x == y { 0 } else { 255 }]));
+        let mut lines = vec![[0xff; LINE_LENGTH]; 9];
+        rasterize_into(&image, &mut lines);
+        assert_eq!(lines.len(), 5);
+        for (y, line) in lines.iter().enumerate() {
+            let mut expected = [0; LINE_LENGTH];
+            expected[LINE_LENGTH - 1] = 1 << y;
+            assert_eq!(line[..], expected[..]);
+        }
+
+        rasterize_into(&GrayImage::new(0, 3), &mut lines);
+        assert_eq!(lines, vec![[0; LINE_LENGTH]; 3]);
+    }
+}
@@ -0,0 +1,821 @@
+//! Rasterizing images one line at a time, for labels too long to hold in memory as a whole
+//!
+//! Each line is resampled from the source rows under the Lanczos filter, and those are the only rows of the
+//! adjusted image that are kept: at most `6 * max(1, source length / label length) + 2` rows as wide as the
+//! source, along with the filter taps of every dot across the label. `LineStream::from_png` also decodes its file
+//! one row at a time, so memory use doesn't grow with the length of the label. `LineStream::new` reads the rows
+//! from an already decoded image instead of copying it, but that image stays in memory until the stream is dropped.
+
+use std::collections::VecDeque;
+use std::io::{BufRead, Seek, SeekFrom};
+
+use image::{DynamicImage, GenericImageView, ImageBuffer, Luma, LumaA, Rgb, Rgba};
+
+use super::dither::RowDither;
+use super::preprocess::{histogram_levels, Preprocess};
+use super::raster::{self, LINE_LENGTH};
+use super::{auto_orientation, placed_size, ImageOptions, Mirror};
+use crate::printer::{constants::Label, Orientation, PrinterError};
+
+type Result<T> = std::result::Result<T, PrinterError>;
+
+/// Number of lobes of the Lanczos filter
+const LANCZOS_WINDOW: f32 = 3.0;
+
+/// Most filter positions between source rows whose taps are kept, see `LineTaps`
+const MAX_CACHED_TAPS: u32 = 1024;
+
+/// Raster lines of an image scaled to the printable width of continuous tape, produced one at a time for
+/// `ThermalPrinter::print_stream`. Lines match those of `rasterize_image_with_options`, except that taps reused
+/// between lines may round a scaled pixel to the next gray level.
+///
+/// If the source can't be read partway through, e.g. because a PNG file is truncated, the remaining lines are
+/// blank so that the label can still be finished, and the error is kept for `take_error`.
+pub struct LineStream {
+    window: Window,
+    /// Filter taps of every column of the scaled image
+    columns: Vec<Taps>,
+    lines: LineTaps,
+    /// Whether the image is already the size of the label, so that its rows are used as is
+    resample: bool,
+    length: u32,
+    y: u32,
+    dither: RowDither,
+    /// Sum of the source rows under the filter, one value per source column
+    sums: Vec<f32>,
+    row: Vec<u8>,
+    error: Option<PrinterError>,
+}
+impl LineStream {
+    /// Prepares to rasterize a decoded image like `rasterize_image_with_options`. Only images placed to fill the
+    /// whole label are supported, e.g. with `Fit::Width` and no label length, and only on continuous tape.
+    /// Images can't be sharpened, since that needs the whole image at once.
+    pub fn new(image: DynamicImage, label: &Label, options: &ImageOptions) -> Result<Self> {
+        check_options(label, options)?;
+        let orientation = orientation(image.dimensions(), label, options);
+        let mut rows = ImageRows {
+            image,
+            preprocess: options.preprocess,
+            orientation,
+            mirror: options.mirror,
+            y: 0,
+        };
+        let levels = if options.preprocess.auto_levels {
+            let levels = levels(&mut rows)?;
+            rows.y = 0;
+            levels
+        } else {
+            (0, 255)
+        };
+        Self::with_rows(Box::new(rows), levels, label, options)
+    }
+    /// Prepares to rasterize a PNG file like `new`, decoding it one row at a time. The file must not be
+    /// interlaced, and can only be mirrored horizontally, not turned or flipped vertically. With
+    /// `Preprocess::auto_levels` it is decoded twice.
+    pub fn from_png<R: BufRead + Seek + Send + 'static>(
+        mut png: R,
+        label: &Label,
+        options: &ImageOptions,
+    ) -> Result<Self> {
+        check_options(label, options)?;
+        let levels = if options.preprocess.auto_levels {
+            let start = png.stream_position().map_err(image_error)?;
+            let levels = levels(&mut PngRows::new(&mut png, label, options)?)?;
+            png.seek(SeekFrom::Start(start)).map_err(image_error)?;
+            levels
+        } else {
+            (0, 255)
+        };
+        let rows = PngRows::new(png, label, options)?;
+        Self::with_rows(Box::new(rows), levels, label, options)
+    }
+    fn with_rows(
+        rows: Box<dyn Rows + Send>,
+        levels: (u8, u8),
+        label: &Label,
+        options: &ImageOptions,
+    ) -> Result<Self> {
+        let source = rows.size();
+        let width = label.dots_printable.0;
+        let placement = &options.placement;
+        let length = placement.length_on(label);
+        let (size, length) = placed_size(source, width, length, placement)?;
+        if size != (width, length) {
+            return Err(PrinterError::InvalidOptions(
+                "only images that fill the label can be streamed".into(),
+            ));
+        }
+
+        let preprocess = &options.preprocess;
+        let mut tone = preprocess.tone_curve(levels);
+        if preprocess.invert {
+            tone.iter_mut().for_each(|level| *level = u8::MAX - *level);
+        }
+        Ok(Self {
+            window: Window {
+                rows,
+                tone,
+                width: source.0 as usize,
+                first: 0,
+                buffer: VecDeque::new(),
+                spare: Vec::new(),
+            },
+            columns: (0..width)
+                .map(|x| Filter::new(x, source.0, width).taps(source.0))
+                .collect(),
+            lines: LineTaps::new(source.1, length),
+            resample: source != (width, length),
+            length,
+            y: 0,
+            dither: RowDither::new(options.dither, preprocess.threshold, width as usize),
+            sums: vec![0.0; source.0 as usize],
+            row: vec![0; width as usize],
+            error: None,
+        })
+    }
+    /// Number of raster lines in the label
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+    /// The error that the source failed with, if any. Lines after the error are blank.
+    pub fn take_error(&mut self) -> Option<PrinterError> {
+        self.error.take()
+    }
+    /// Resamples the next line into `row`
+    fn resample_line(&mut self) -> Result<()> {
+        if !self.resample {
+            self.window.advance(self.y, self.y + 1)?;
+            self.row.copy_from_slice(self.window.row(self.y));
+            return Ok(());
+        }
+
+        // Filter the source rows under this line, then each column of the result
+        let (start, weights) = self.lines.get(self.y);
+        self.window.advance(start, start + weights.len() as u32)?;
+        self.sums.iter_mut().for_each(|sum| *sum = 0.0);
+        for (i, &weight) in weights.iter().enumerate() {
+            let source = self.window.row(start + i as u32);
+            for (sum, &pixel) in self.sums.iter_mut().zip(source) {
+                *sum += weight * f32::from(pixel);
+            }
+        }
+        for (pixel, column) in self.row.iter_mut().zip(&self.columns) {
+            let start = column.start as usize;
+            let sums = &self.sums[start..start + column.weights.len()];
+            let value: f32 = sums.iter().zip(&column.weights).map(|(s, w)| s * w).sum();
+            *pixel = value.round().clamp(0.0, 255.0) as u8;
+        }
+        Ok(())
+    }
+}
+impl Iterator for LineStream {
+    type Item = [u8; LINE_LENGTH];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.y >= self.length {
+            return None;
+        }
+        let line = if self.error.is_some() {
+            [0; LINE_LENGTH]
+        } else {
+            match self.resample_line() {
+                Ok(()) => {
+                    self.dither.apply(&mut self.row);
+                    raster::pack_line(&self.row)
+                }
+                Err(e) => {
+                    self.error = Some(e);
+                    [0; LINE_LENGTH]
+                }
+            }
+        };
+        self.y += 1;
+        Some(line)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.length - self.y) as usize;
+        (remaining, Some(remaining))
+    }
+}
+impl ExactSizeIterator for LineStream {}
+
+/// Checks the options that don't depend on the image
+fn check_options(label: &Label, options: &ImageOptions) -> Result<()> {
+    if label.tape_size.1 != 0 {
+        return Err(PrinterError::InvalidOptions(
+            "only continuous tape can be streamed".into(),
+        ));
+    }
+    if options.preprocess.sharpen.is_some() {
+        return Err(PrinterError::InvalidOptions(
+            "images cannot be sharpened while streaming".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// `options.orientation`, with `Orientation::Auto` resolved for an image of `size`
+fn orientation(size: (u32, u32), label: &Label, options: &ImageOptions) -> Orientation {
+    match options.orientation {
+        Orientation::Auto => auto_orientation(size, label, &options.placement),
+        orientation => orientation,
+    }
+}
+
+fn image_error(e: impl std::fmt::Display) -> PrinterError {
+    PrinterError::Image(e.to_string())
+}
+
+/// Gray levels for `Preprocess::auto_levels`, from every row of the source
+fn levels(rows: &mut dyn Rows) -> Result<(u8, u8)> {
+    let (width, height) = rows.size();
+    let mut row = vec![0; width as usize];
+    let mut histogram = [0; 256];
+    for _ in 0..height {
+        rows.read(&mut row)?;
+        for &pixel in &row {
+            histogram[usize::from(pixel)] += 1;
+        }
+    }
+    Ok(histogram_levels(&histogram))
+}
+
+/// Rows of the source image turned to its orientation on the label, as gray levels
+trait Rows {
+    /// Width and height of the turned image
+    fn size(&self) -> (u32, u32);
+    /// Reads the next row into `row`, which is as wide as the turned image
+    fn read(&mut self, row: &mut [u8]) -> Result<()>;
+}
+
+/// Rows read from a decoded image, one of its rows or columns at a time
+struct ImageRows {
+    image: DynamicImage,
+    preprocess: Preprocess,
+    orientation: Orientation,
+    mirror: Mirror,
+    y: u32,
+}
+impl ImageRows {
+    /// Whether row `y` of the turned image is a column of the image, its index, and whether it runs backwards
+    fn source_line(&self, y: u32) -> (bool, u32, bool) {
+        let (width, height) = self.image.dimensions();
+        let (column, index, reversed) = match self.orientation {
+            Orientation::Normal | Orientation::Auto => (false, y, false),
+            Orientation::Rotated => (true, y, true),
+            Orientation::Rotated180 => (false, height - 1 - y, true),
+            Orientation::Rotated270 => (true, width - 1 - y, false),
+        };
+        // The image is mirrored before it is turned
+        match (self.mirror, column) {
+            (Mirror::None, _) => (column, index, reversed),
+            (Mirror::Horizontal, false) => (false, index, !reversed),
+            (Mirror::Horizontal, true) => (true, width - 1 - index, reversed),
+            (Mirror::Vertical, false) => (false, height - 1 - index, reversed),
+            (Mirror::Vertical, true) => (true, index, !reversed),
+        }
+    }
+}
+impl Rows for ImageRows {
+    fn size(&self) -> (u32, u32) {
+        let (width, height) = self.image.dimensions();
+        match self.orientation {
+            Orientation::Rotated | Orientation::Rotated270 => (height, width),
+            _ => (width, height),
+        }
+    }
+    fn read(&mut self, row: &mut [u8]) -> Result<()> {
+        let (column, index, reversed) = self.source_line(self.y);
+        let (width, height) = self.image.dimensions();
+        let line = if column {
+            self.image.crop_imm(index, 0, 1, height)
+        } else {
+            self.image.crop_imm(0, index, width, 1)
+        };
+        row.copy_from_slice(self.preprocess.grayscale(line).as_raw());
+        if reversed {
+            row.reverse();
+        }
+        self.y += 1;
+        Ok(())
+    }
+}
+
+/// Rows decoded from a PNG file one at a time
+struct PngRows<R: BufRead + Seek> {
+    reader: png::Reader<R>,
+    preprocess: Preprocess,
+    reversed: bool,
+}
+impl<R: BufRead + Seek> PngRows<R> {
+    fn new(png: R, label: &Label, options: &ImageOptions) -> Result<Self> {
+        let mut decoder = png::Decoder::new(png);
+        // Expand pixels to 8 or 16 bits like `image::open` does, so that they convert to the same gray levels
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let reader = decoder.read_info().map_err(image_error)?;
+        let info = reader.info();
+        if info.interlaced {
+            return Err(PrinterError::InvalidOptions(
+                "interlaced PNG files cannot be streamed".into(),
+            ));
+        }
+        let orientation = orientation((info.width, info.height), label, options);
+        if orientation != Orientation::Normal || options.mirror == Mirror::Vertical {
+            return Err(PrinterError::InvalidOptions(
+                "PNG files cannot be turned or flipped vertically while streaming".into(),
+            ));
+        }
+        Ok(Self {
+            reader,
+            preprocess: options.preprocess,
+            reversed: options.mirror == Mirror::Horizontal,
+        })
+    }
+}
+impl<R: BufRead + Seek> Rows for PngRows<R> {
+    fn size(&self) -> (u32, u32) {
+        let info = self.reader.info();
+        (info.width, info.height)
+    }
+    fn read(&mut self, row: &mut [u8]) -> Result<()> {
+        let width = self.reader.info().width;
+        let format = self.reader.output_color_type();
+        let data = self
+            .reader
+            .next_row()
+            .map_err(image_error)?
+            .ok_or_else(|| PrinterError::Image("PNG file ended early".into()))?
+            .data();
+        let line = png_row(format, width, data)?;
+        row.copy_from_slice(self.preprocess.grayscale(line).as_raw());
+        if self.reversed {
+            row.reverse();
+        }
+        Ok(())
+    }
+}
+
+/// A row of expanded PNG pixels as a one pixel tall image, in the pixel format that `image` decodes it to
+fn png_row(
+    (color, depth): (png::ColorType, png::BitDepth),
+    width: u32,
+    data: &[u8],
+) -> Result<DynamicImage> {
+    use png::{BitDepth, ColorType};
+
+    let wide = || -> Vec<u16> {
+        data.chunks_exact(2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .collect()
+    };
+    let narrow = || data.to_vec();
+    let image = match (color, depth) {
+        (ColorType::Grayscale, BitDepth::Eight) => {
+            ImageBuffer::<Luma<u8>, _>::from_raw(width, 1, narrow()).map(DynamicImage::ImageLuma8)
+        }
+        (ColorType::GrayscaleAlpha, BitDepth::Eight) => {
+            ImageBuffer::<LumaA<u8>, _>::from_raw(width, 1, narrow()).map(DynamicImage::ImageLumaA8)
+        }
+        (ColorType::Rgb, BitDepth::Eight) => {
+            ImageBuffer::<Rgb<u8>, _>::from_raw(width, 1, narrow()).map(DynamicImage::ImageRgb8)
+        }
+        (ColorType::Rgba, BitDepth::Eight) => {
+            ImageBuffer::<Rgba<u8>, _>::from_raw(width, 1, narrow()).map(DynamicImage::ImageRgba8)
+        }
+        (ColorType::Grayscale, BitDepth::Sixteen) => {
+            ImageBuffer::<Luma<u16>, _>::from_raw(width, 1, wide()).map(DynamicImage::ImageLuma16)
+        }
+        (ColorType::GrayscaleAlpha, BitDepth::Sixteen) => {
+            ImageBuffer::<LumaA<u16>, _>::from_raw(width, 1, wide()).map(DynamicImage::ImageLumaA16)
+        }
+        (ColorType::Rgb, BitDepth::Sixteen) => {
+            ImageBuffer::<Rgb<u16>, _>::from_raw(width, 1, wide()).map(DynamicImage::ImageRgb16)
+        }
+        (ColorType::Rgba, BitDepth::Sixteen) => {
+            ImageBuffer::<Rgba<u16>, _>::from_raw(width, 1, wide()).map(DynamicImage::ImageRgba16)
+        }
+        _ => None,
+    };
+    image.ok_or_else(|| PrinterError::Image(format!("unsupported PNG pixel format {color:?}")))
+}
+
+/// Adjusted source rows under the filter of the current line
+struct Window {
+    rows: Box<dyn Rows + Send>,
+    /// Gray levels after the `Preprocess` adjustments
+    tone: [u8; 256],
+    width: usize,
+    /// Index of the first row in `buffer`
+    first: u32,
+    buffer: VecDeque<Vec<u8>>,
+    /// Rows that left the window, to be reused
+    spare: Vec<Vec<u8>>,
+}
+impl Window {
+    /// Moves the window to source rows `start..end`, reading and adjusting rows as needed. Windows never move
+    /// back.
+    fn advance(&mut self, start: u32, end: u32) -> Result<()> {
+        while self.first < start && !self.buffer.is_empty() {
+            self.spare.extend(self.buffer.pop_front());
+            self.first += 1;
+        }
+        while self.first + (self.buffer.len() as u32) < end {
+            let mut row = self.spare.pop().unwrap_or_else(|| vec![0; self.width]);
+            self.rows.read(&mut row)?;
+            if self.first < start {
+                // Skipped by the filter entirely
+                self.spare.push(row);
+                self.first += 1;
+                continue;
+            }
+            for pixel in row.iter_mut() {
+                *pixel = self.tone[usize::from(*pixel)];
+            }
+            self.buffer.push_back(row);
+        }
+        Ok(())
+    }
+    fn row(&self, index: u32) -> &[u8] {
+        &self.buffer[(index - self.first) as usize]
+    }
+}
+
+/// Vertical filter taps of each line. The filter of line `y` sits at the same position between the source rows as
+/// that of line `y % period`, only `shift` whole rows further down for each period, so its taps are computed once
+/// per position. Lines whose filter is cut off by the ends of the image are computed on their own, as are all lines
+/// if there are more than `MAX_CACHED_TAPS` positions.
+struct LineTaps {
+    source: u32,
+    scaled: u32,
+    period: u32,
+    shift: i64,
+    cached: Vec<Option<Taps>>,
+    /// Taps of the last line that couldn't use the cache
+    uncached: Taps,
+}
+impl LineTaps {
+    fn new(source: u32, scaled: u32) -> Self {
+        let divisor = gcd(source, scaled);
+        let period = scaled / divisor;
+        let positions = if period <= MAX_CACHED_TAPS { period } else { 0 };
+        Self {
+            source,
+            scaled,
+            period,
+            shift: i64::from(source / divisor),
+            cached: (0..positions).map(|_| None).collect(),
+            uncached: Taps {
+                start: 0,
+                weights: Vec::new(),
+            },
+        }
+    }
+    /// First source row under line `y`, and the weights of the rows from there
+    fn get(&mut self, y: u32) -> (u32, &[f32]) {
+        match self.cached_start(y) {
+            Some(start) => {
+                let taps = self.cached[(y % self.period) as usize].as_ref();
+                (start, &taps.expect("cached taps").weights)
+            }
+            None => {
+                self.uncached = Filter::new(y, self.source, self.scaled).taps(self.source);
+                (self.uncached.start as u32, &self.uncached.weights)
+            }
+        }
+    }
+    /// First source row under line `y` if the cached taps of its position apply to it
+    fn cached_start(&mut self, y: u32) -> Option<u32> {
+        if self.cached.is_empty() {
+            return None;
+        }
+        let filter = Filter::new(y, self.source, self.scaled);
+        let (start, end) = filter.clipped(self.source);
+        if (start, end) != (filter.start, filter.end) {
+            return None;
+        }
+        let position = y % self.period;
+        let (source, scaled) = (self.source, self.scaled);
+        let taps = self.cached[position as usize]
+            .get_or_insert_with(|| Filter::new(position, source, scaled).unclipped_taps());
+        let shifted = taps.start + i64::from(y / self.period) * self.shift;
+        let matches = (shifted, shifted + taps.weights.len() as i64) == (start, end);
+        matches.then_some(start as u32)
+    }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Weights of the source pixels that make up one scaled pixel, starting with source pixel `start`
+struct Taps {
+    start: i64,
+    weights: Vec<f32>,
+}
+
+/// Lanczos filter of one scaled pixel, positioned like `imageops::resize` with `FilterType::Lanczos3` so that
+/// streamed labels match `rasterize_image_with_options`
+struct Filter {
+    center: f32,
+    /// Widening of the filter when shrinking, so that every source pixel counts
+    scale: f32,
+    /// Source pixels under the filter, which may be outside the image
+    start: i64,
+    end: i64,
+}
+impl Filter {
+    /// Filter of pixel `index` when scaling `source` pixels to `scaled`
+    fn new(index: u32, source: u32, scaled: u32) -> Self {
+        let ratio = source as f32 / scaled as f32;
+        let scale = ratio.max(1.0);
+        let support = LANCZOS_WINDOW * scale;
+        let center = (index as f32 + 0.5) * ratio;
+        Self {
+            center,
+            scale,
+            start: (center - support).floor() as i64,
+            end: (center + support).ceil() as i64,
+        }
+    }
+    /// Source pixels under the filter, limited to the image
+    fn clipped(&self, source: u32) -> (i64, i64) {
+        let start = self.start.clamp(0, i64::from(source) - 1);
+        (start, self.end.clamp(start + 1, i64::from(source)))
+    }
+    /// Taps of the pixels under the filter within an image of `source` pixels
+    fn taps(&self, source: u32) -> Taps {
+        self.taps_over(self.clipped(source))
+    }
+    /// Taps of all pixels under the filter, as if the image went on forever
+    fn unclipped_taps(&self) -> Taps {
+        self.taps_over((self.start, self.end))
+    }
+    fn taps_over(&self, (start, end): (i64, i64)) -> Taps {
+        let mut weights: Vec<f32> = (start..end)
+            .map(|i| lanczos((i as f32 - (self.center - 0.5)) / self.scale))
+            .collect();
+        let total: f32 = weights.iter().sum();
+        weights.iter_mut().for_each(|weight| *weight /= total);
+        Taps { start, weights }
+    }
+}
+
+/// Lanczos kernel, a sinc function windowed by a wider one
+fn lanczos(x: f32) -> f32 {
+    let sinc = |x: f32| {
+        let x = x * std::f32::consts::PI;
+        if x == 0.0 {
+            1.0
+        } else {
+            x.sin() / x
+        }
+    };
+    if x.abs() < LANCZOS_WINDOW {
+        sinc(x) * sinc(x / LANCZOS_WINDOW)
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::imageops::FilterType;
+    use image::{GrayImage, ImageFormat};
+
+    use super::*;
+    use crate::printer::constants;
+    use crate::utils::{rasterize_image_with_options, Dither};
+
+    fn test_image(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| {
+            image::Luma([((x * 7 + y * 13) % 256) as u8 ^ ((x / 5 + y / 3) % 2 * 255) as u8])
+        }))
+    }
+
+    fn png(image: &DynamicImage) -> Vec<u8> {
+        let mut png = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    /// Computes the taps of every line on its own, exactly like `imageops::resize`
+    fn uncached(mut stream: LineStream) -> LineStream {
+        stream.lines.cached.clear();
+        stream
+    }
+
+    #[test]
+    fn streams_like_rasterizing_whole_image() {
+        let label = constants::label_data(62, None).unwrap();
+        for &(width, height) in &[(300, 120), (696, 200), (1500, 700)] {
+            for &dither in &[
+                Dither::Threshold,
+                Dither::FloydSteinberg,
+                Dither::Atkinson,
+                Dither::Bayer4,
+            ] {
+                let options = ImageOptions {
+                    dither,
+                    ..ImageOptions::default()
+                };
+                let image = test_image(width, height);
+                let stream = uncached(LineStream::new(image.clone(), &label, &options).unwrap());
+                assert_eq!(stream.len(), stream.length() as usize);
+                let streamed: Vec<_> = stream.collect();
+                let whole = rasterize_image_with_options(image, &label, &options).unwrap();
+                assert_eq!(streamed, whole, "{}x{} {:?}", width, height, dither);
+            }
+        }
+    }
+
+    #[test]
+    fn streams_turned_and_adjusted_images() {
+        let label = constants::label_data(62, None).unwrap();
+        let image = test_image(400, 150);
+        for &orientation in &[
+            Orientation::Normal,
+            Orientation::Rotated,
+            Orientation::Rotated180,
+            Orientation::Rotated270,
+        ] {
+            for &mirror in &[Mirror::None, Mirror::Horizontal, Mirror::Vertical] {
+                let options = ImageOptions {
+                    orientation,
+                    mirror,
+                    preprocess: Preprocess {
+                        auto_levels: true,
+                        contrast: 1.5,
+                        invert: true,
+                        ..Preprocess::default()
+                    },
+                    ..ImageOptions::default()
+                };
+                let stream = LineStream::new(image.clone(), &label, &options).unwrap();
+                let streamed: Vec<_> = uncached(stream).collect();
+                let whole = rasterize_image_with_options(image.clone(), &label, &options).unwrap();
+                assert_eq!(streamed, whole, "{:?} {:?}", orientation, mirror);
+            }
+        }
+    }
+
+    #[test]
+    fn streams_png_files() {
+        let label = constants::label_data(62, None).unwrap();
+        let gray = test_image(500, 300);
+        let mut rgba = gray.to_rgba8();
+        rgba.enumerate_pixels_mut()
+            .for_each(|(x, y, pixel)| pixel[3] = ((x + y) % 256) as u8);
+        let images = [
+            gray.clone(),
+            DynamicImage::ImageRgba8(rgba),
+            DynamicImage::ImageLuma16(gray.to_luma16()),
+        ];
+        for image in &images {
+            for &mirror in &[Mirror::None, Mirror::Horizontal] {
+                let options = ImageOptions {
+                    mirror,
+                    dither: Dither::FloydSteinberg,
+                    preprocess: Preprocess {
+                        auto_levels: true,
+                        ..Preprocess::default()
+                    },
+                    ..ImageOptions::default()
+                };
+                let png = png(image);
+                let stream =
+                    LineStream::from_png(Cursor::new(png.clone()), &label, &options).unwrap();
+                let streamed: Vec<_> = uncached(stream).collect();
+                let decoded = image::load_from_memory(&png).unwrap();
+                let whole = rasterize_image_with_options(decoded, &label, &options).unwrap();
+                assert_eq!(streamed, whole, "{:?} {:?}", image.color(), mirror);
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_only_rows_under_filter() {
+        let label = constants::label_data(62, None).unwrap();
+        let options = ImageOptions::default();
+        let png = png(&test_image(1392, 4000));
+        let mut stream = LineStream::from_png(Cursor::new(png), &label, &options).unwrap();
+        // Halving the image puts 12 source rows under each line
+        assert_eq!(stream.lines.cached.len(), 1);
+        let mut most = 0;
+        while stream.next().is_some() {
+            most = most.max(stream.window.buffer.len() + stream.window.spare.len());
+        }
+        assert!(most <= 6 * 2 + 2, "{} rows", most);
+        assert_eq!(stream.y, 2000);
+    }
+
+    #[test]
+    fn resamples_like_whole_image_with_cached_taps() {
+        let label = constants::label_data(62, None).unwrap();
+        let options = ImageOptions::default();
+        for &(width, height) in &[(300, 120), (1500, 700)] {
+            let image = test_image(width, height);
+            let mut stream = LineStream::new(image.clone(), &label, &options).unwrap();
+            assert!(!stream.lines.cached.is_empty());
+            let gray = options.preprocess.apply(image);
+            let whole = image::imageops::resize(&gray, 696, stream.length, FilterType::Lanczos3);
+            for y in 0..stream.length {
+                stream.resample_line().unwrap();
+                stream.y += 1;
+                let expected = whole.rows().nth(y as usize).unwrap();
+                for (&a, b) in stream.row.iter().zip(expected) {
+                    assert!(a.abs_diff(b[0]) <= 1, "{}x{}, line {}", width, height, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn caches_line_taps_per_position() {
+        for &(source, scaled) in &[(120, 200), (700, 463), (4000, 2000), (1000, 999)] {
+            let mut lines = LineTaps::new(source, scaled);
+            for y in 0..scaled {
+                let expected = Filter::new(y, source, scaled).taps(source);
+                let (start, weights) = lines.get(y);
+                assert_eq!(
+                    i64::from(start),
+                    expected.start,
+                    "{} to {}, line {}",
+                    source,
+                    scaled,
+                    y
+                );
+                assert_eq!(weights.len(), expected.weights.len());
+                for (a, b) in weights.iter().zip(&expected.weights) {
+                    assert!((a - b).abs() < 1e-5, "{} to {}, line {}", source, scaled, y);
+                }
+            }
+        }
+        assert_eq!(LineTaps::new(120, 200).cached.len(), 5);
+        assert!(LineTaps::new(1000, 1999).cached.is_empty());
+    }
+
+    #[test]
+    fn finishes_label_when_file_is_cut_off() {
+        let label = constants::label_data(62, None).unwrap();
+        let mut png = png(&test_image(696, 400));
+        png.truncate(png.len() / 2);
+        let mut stream =
+            LineStream::from_png(Cursor::new(png), &label, &ImageOptions::default()).unwrap();
+        let lines: Vec<_> = stream.by_ref().collect();
+        assert_eq!(lines.len(), 400);
+        assert!(lines[300..]
+            .iter()
+            .all(|line| line.iter().all(|&byte| byte == 0)));
+        assert!(matches!(stream.take_error(), Some(PrinterError::Image(_))));
+    }
+
+    #[test]
+    fn rejects_unsupported_labels() {
+        let options = ImageOptions::default();
+        let die_cut = constants::label_data(62, Some(29)).unwrap();
+        assert!(LineStream::new(test_image(100, 100), &die_cut, &options).is_err());
+
+        let continuous = constants::label_data(62, None).unwrap();
+        let contain = ImageOptions {
+            placement: crate::utils::Placement {
+                fit: crate::utils::Fit::Contain,
+                length: Some(crate::units::Length::mm(100.0)),
+                ..Default::default()
+            },
+            ..options
+        };
+        assert!(LineStream::new(test_image(100, 100), &continuous, &contain).is_err());
+        assert!(matches!(
+            LineStream::new(test_image(0, 10), &continuous, &options),
+            Err(PrinterError::EmptyImage)
+        ));
+
+        let sharpen = ImageOptions {
+            preprocess: Preprocess {
+                sharpen: Some(crate::utils::preprocess::Sharpen {
+                    sigma: 1.0,
+                    threshold: 0,
+                }),
+                ..Preprocess::default()
+            },
+            ..options
+        };
+        assert!(LineStream::new(test_image(100, 100), &continuous, &sharpen).is_err());
+
+        let rotated = ImageOptions {
+            orientation: Orientation::Rotated,
+            ..options
+        };
+        let png = Cursor::new(png(&test_image(100, 100)));
+        assert!(LineStream::from_png(png, &continuous, &rotated).is_err());
+    }
+}